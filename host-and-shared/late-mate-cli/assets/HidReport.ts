@@ -1,3 +1,5 @@
+// Apart from these names, keys can be given as aliases (e.g. "1", "ctrl", "esc")
+// or as raw HID usage codes (e.g. "0x59")
 export type KeyboardKey =
   | "a"
   | "b"
@@ -45,6 +47,7 @@ export type KeyboardKey =
   | "open_bracket_brace"
   | "close_bracket_brace"
   | "backslash_bar"
+  | "non_u_s_hash"
   | "non_us_hash"
   | "semi_colon"
   | "single_double_quote"
@@ -78,9 +81,142 @@ export type KeyboardKey =
   | "left"
   | "down"
   | "up"
+  | "keypad_num_lock"
+  | "keypad_divide"
+  | "keypad_multiply"
+  | "keypad_minus"
+  | "keypad_plus"
+  | "keypad_enter"
+  | "keypad_1"
+  | "keypad_2"
+  | "keypad_3"
+  | "keypad_4"
+  | "keypad_5"
+  | "keypad_6"
+  | "keypad_7"
+  | "keypad_8"
+  | "keypad_9"
+  | "keypad_0"
+  | "keypad_period"
+  | "non_us_backslash"
+  | "application"
+  | "power"
+  | "keypad_equal"
+  | "f13"
+  | "f14"
+  | "f15"
+  | "f16"
+  | "f17"
+  | "f18"
+  | "f19"
+  | "f20"
+  | "f21"
+  | "f22"
+  | "f23"
+  | "f24"
+  | "execute"
+  | "help"
+  | "menu"
+  | "select"
+  | "stop"
+  | "again"
+  | "undo"
+  | "cut"
+  | "copy"
+  | "paste"
+  | "find"
   | "mute"
   | "volume_up"
-  | "volume_down";
+  | "volume_down"
+  | "locking_caps_lock"
+  | "locking_num_lock"
+  | "locking_scroll_lock"
+  | "keypad_comma"
+  | "keypad_equal_as400"
+  | "international_1"
+  | "international_2"
+  | "international_3"
+  | "international_4"
+  | "international_5"
+  | "international_6"
+  | "international_7"
+  | "international_8"
+  | "international_9"
+  | "lang_1"
+  | "lang_2"
+  | "lang_3"
+  | "lang_4"
+  | "lang_5"
+  | "lang_6"
+  | "lang_7"
+  | "lang_8"
+  | "lang_9"
+  | "alternate_erase"
+  | "sys_req"
+  | "cancel"
+  | "clear"
+  | "prior"
+  | "return"
+  | "separator"
+  | "out"
+  | "oper"
+  | "clear_again"
+  | "cr_sel"
+  | "ex_sel"
+  | "keypad_00"
+  | "keypad_000"
+  | "thousands_separator"
+  | "decimal_separator"
+  | "currency_unit"
+  | "currency_subunit"
+  | "keypad_open_parens"
+  | "keypad_close_parens"
+  | "keypad_open_brace"
+  | "keypad_close_brace"
+  | "keypad_tab"
+  | "keypad_backspace"
+  | "keypad_a"
+  | "keypad_b"
+  | "keypad_c"
+  | "keypad_d"
+  | "keypad_e"
+  | "keypad_f"
+  | "keypad_xor"
+  | "keypad_caret"
+  | "keypad_percent"
+  | "keypad_less"
+  | "keypad_greater"
+  | "keypad_ampersand"
+  | "keypad_double_ampersand"
+  | "keypad_bar"
+  | "keypad_double_bar"
+  | "keypad_colon"
+  | "keypad_hash"
+  | "keypad_space"
+  | "keypad_at"
+  | "keypad_exclamation"
+  | "keypad_memory_store"
+  | "keypad_memory_recall"
+  | "keypad_memory_clear"
+  | "keypad_memory_add"
+  | "keypad_memory_subtract"
+  | "keypad_memory_multiply"
+  | "keypad_memory_divide"
+  | "keypad_plus_minus"
+  | "keypad_clear"
+  | "keypad_clear_entry"
+  | "keypad_binary"
+  | "keypad_octal"
+  | "keypad_decimal"
+  | "keypad_hexadecimal"
+  | "left_ctrl"
+  | "left_shift"
+  | "left_alt"
+  | "left_meta"
+  | "right_ctrl"
+  | "right_shift"
+  | "right_alt"
+  | "right_meta";
 
 export type KeyboardModifier =
   | "l_ctrl"
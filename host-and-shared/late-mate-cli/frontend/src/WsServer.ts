@@ -20,6 +20,9 @@ export class WsServer {
     this.ws.addEventListener("message", (evt) => {
       if (typeof evt.data === "string") {
         const msg = JSON.parse(evt.data) as ServerToClient;
+        if (msg.type === "error") {
+          console.error("Server error:", msg.message);
+        }
         for (const listener of this.msgListeners) {
           listener(msg);
         }
@@ -2,7 +2,13 @@
 import type { Version } from "./Version";
 
 export type ServerToClient =
-  | { type: "status"; version: Version; max_light_level: number }
+  | {
+      type: "status";
+      version: Version;
+      serial_number: string;
+      max_light_level: number;
+      last_panic_message: string | null;
+    }
   | { type: "background_light_level"; avg: number }
   | {
       type: "measurement";
@@ -13,4 +19,5 @@ export type ServerToClient =
       light_levels: Array<[number, number]>;
       followup_hid_us: number | null;
       change_us: number | null;
-    };
+    }
+  | { type: "error"; message: string };
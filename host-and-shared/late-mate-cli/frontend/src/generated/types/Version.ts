@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type Version = { hardware: number; firmware: number };
+export type Version = { hardware: string; firmware: string };
@@ -310,6 +310,8 @@ export class MeasurePage extends Page {
     server.subscribe((msg) => {
       if (msg.type === "measurement") {
         this.processMeasurement(msg);
+      } else if (msg.type === "error") {
+        this.requestInFlight = false;
       }
     });
   }
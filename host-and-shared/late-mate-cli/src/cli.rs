@@ -1,11 +1,19 @@
+mod analyze;
+mod compare;
 mod device;
 mod hid;
-mod scenario;
+mod monitor;
+mod proxy;
+mod run_server;
+pub mod scenario;
 mod send_hid_report;
+mod suite;
 
+use analyze::{Analyze as CliAnalyze, AnalyzeArgs};
 use device::Device as CliDevice;
 use hid::Hid as CliHid;
 use scenario::Scenario as CliScenario;
+use suite::Suite as CliSuite;
 
 use late_mate_device::Device;
 
@@ -14,6 +22,10 @@ use late_mate_device::Device;
 pub struct Cli {
     #[command(subcommand)]
     pub command: Command,
+
+    /// Use a device shared with `late-mate proxy` on another machine instead of a local one
+    #[arg(long, global = true, value_name = "HOST:PORT")]
+    pub remote: Option<String>,
 }
 
 #[derive(Debug, clap::Subcommand)]
@@ -25,31 +37,69 @@ pub enum Command {
     /// `examples` subcommand here.
     #[command(subcommand)]
     Scenario(CliScenario),
+    /// Suites run several scenarios in one go, for example for regression testing.
+    #[command(subcommand)]
+    Suite(CliSuite),
     /// If you want to just send a HID report without any timing, you can use this subcommand.
     #[command(subcommand)]
     Hid(CliHid),
-    // todo
-    // /// Stream current light level to console output (throttled down to 120hz)
-    // MonitorBackground,
-    // /// Run an http/websocket server
-    // RunServer {
-    //     #[arg(long, default_value = "127.0.0.1")]
-    //     interface: IpAddr,
-    //     #[arg(long, default_value = "9118")]
+    /// Re-analyze a saved run, e.g. with a different changepoint detector, or analyze the
+    /// display's light level with the `display` subcommand.
+    Analyze(AnalyzeArgs),
+    /// Compare saved runs with the first one, e.g. to catch latency regressions in CI.
+    Compare(compare::Args),
+    /// Show the live light level, e.g. to aim the sensor at the right spot on the screen.
+    Monitor(monitor::Args),
+    /// Run an http/websocket server for the web frontend, with a JSON API for remote runs.
+    RunServer(run_server::Args),
+    /// Share the connected device over TCP, so that other machines can use it with `--remote`.
+    Proxy(proxy::Args),
+}
 
-    //     port: u16,
-    // },
+async fn init_device(remote: Option<&str>) -> anyhow::Result<Device> {
+    match remote {
+        Some(address) => {
+            tracing::debug!("Connecting to the device at {address}");
+            Ok(Device::connect(address).await?)
+        }
+        None => {
+            tracing::debug!("Initialising the device");
+            Ok(Device::init().await?)
+        }
+    }
 }
 
 impl Command {
-    pub async fn run(self, device: &mut Device) -> anyhow::Result<()> {
+    /// The device is only initialised for commands that need it, so that offline commands
+    /// work without a connected Late Mate
+    pub async fn run(self, remote: Option<&str>) -> anyhow::Result<()> {
+        let init_device = || init_device(remote);
         match self {
-            Command::Device(CliDevice::Status(cmd)) => cmd.run(device).await,
-            Command::Device(CliDevice::FirmwareUpdate(cmd)) => cmd.run(device).await,
-            Command::Scenario(CliScenario::Run(cmd)) => cmd.run(device).await,
-            Command::Scenario(CliScenario::Example(cmd)) => cmd.run(device).await,
-            Command::Hid(CliHid::Send(cmd)) => cmd.run(device).await,
-            Command::Hid(CliHid::ShowType(cmd)) => cmd.run(device).await,
+            Command::Device(CliDevice::Status(cmd)) => cmd.run(&mut init_device().await?).await,
+            Command::Device(CliDevice::FirmwareUpdate(cmd)) => cmd.run(&init_device().await?).await,
+            Command::Scenario(CliScenario::Run(cmd)) if cmd.dry_run => cmd.dry_run().await,
+            Command::Scenario(CliScenario::Run(cmd)) => cmd.run(&init_device().await?).await,
+            Command::Scenario(CliScenario::Ab(cmd)) => cmd.run(&init_device().await?).await,
+            Command::Scenario(CliScenario::Validate(cmd)) => cmd.run().await,
+            Command::Scenario(CliScenario::Compile(cmd)) => cmd.run().await,
+            Command::Scenario(CliScenario::Schema(cmd)) => cmd.run().await,
+            Command::Scenario(CliScenario::Example(cmd)) => cmd.run().await,
+            Command::Suite(CliSuite::Run(cmd)) => cmd.run(&init_device().await?).await,
+            Command::Hid(CliHid::Send(cmd)) => cmd.run(&init_device().await?).await,
+            Command::Hid(CliHid::ShowType(cmd)) => cmd.run().await,
+            Command::Hid(CliHid::Type(cmd)) => cmd.run(&init_device().await?).await,
+            Command::Analyze(AnalyzeArgs {
+                command: Some(CliAnalyze::Display(cmd)),
+                ..
+            }) => cmd.run(&init_device().await?).await,
+            Command::Analyze(AnalyzeArgs { command: None, run }) => run.run().await,
+            Command::Compare(cmd) => cmd.run().await,
+            Command::Monitor(cmd) => cmd.run(&init_device().await?).await,
+            Command::RunServer(cmd) => cmd.run(init_device().await?).await,
+            Command::Proxy(_) if remote.is_some() => {
+                anyhow::bail!("Only a local device can be shared")
+            }
+            Command::Proxy(cmd) => cmd.run(init_device().await?).await,
         }
     }
 }
@@ -0,0 +1,20 @@
+mod display;
+mod run;
+
+/// Without a subcommand, a saved run is analyzed
+#[derive(Debug, clap::Args)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct AnalyzeArgs {
+    #[command(subcommand)]
+    pub command: Option<Analyze>,
+
+    #[command(flatten)]
+    pub run: run::Args,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Analyze {
+    /// Capture the light level for a few seconds and look for display refresh, backlight PWM
+    /// and flickering room lighting
+    Display(display::Args),
+}
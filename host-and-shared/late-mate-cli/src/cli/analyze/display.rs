@@ -0,0 +1,90 @@
+use crate::statistics::{analyze_flicker, Flicker};
+use anyhow::anyhow;
+use console::style;
+use futures::TryStreamExt;
+use late_mate_device::Device;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// How long to capture the light level for, in seconds. Longer captures resolve
+    /// frequencies more finely
+    #[arg(long, default_value_t = 3.0)]
+    seconds: f64,
+
+    /// Print the analysis as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+fn print_flicker(flicker: &Flicker) {
+    println!(
+        "Sample rate: {:.0}hz, modulation depth: {:.1}%",
+        flicker.sample_rate_hz, flicker.modulation_depth_percent
+    );
+
+    match flicker.refresh_rate_hz {
+        Some(hz) => println!("Refresh rate: {}", style(format!("{hz:.2}hz")).bold()),
+        None => println!("Refresh rate: not visible in the light level"),
+    }
+    match flicker.pwm {
+        Some(pwm) => println!(
+            "{} at {:.1}hz, {:.1}% of the light level. Expect it in the recordings, a detector \
+             with a larger noise window or `swing` may be needed",
+            style("Backlight PWM").yellow(),
+            pwm.frequency_hz,
+            pwm.amplitude_percent
+        ),
+        None => println!("Backlight PWM: none found"),
+    }
+    if let Some(mains) = flicker.ambient {
+        println!(
+            "{} from {}hz mains lighting, shield the sensor from room light",
+            style("Ambient flicker").yellow(),
+            mains.hz()
+        );
+    }
+
+    if !flicker.peaks.is_empty() {
+        println!("Strongest periodic components:");
+        for peak in &flicker.peaks {
+            println!(
+                "  {:>8.2}hz  {:>6.2}%",
+                peak.frequency_hz, peak.amplitude_percent
+            );
+        }
+    }
+}
+
+impl Args {
+    pub async fn run(self, device: &Device) -> anyhow::Result<()> {
+        if self.seconds <= 0.0 || !self.seconds.is_finite() {
+            return Err(anyhow!("Capture length must be positive"));
+        }
+        let duration = Duration::from_secs_f64(self.seconds);
+
+        let stream = device.stream_light_level().await?;
+        tokio::pin!(stream);
+
+        eprintln!("Capturing the light level for {:.1}s", self.seconds);
+        // stream values aren't timestamped, the sample rate is derived from their count
+        let mut levels = vec![];
+        let started = Instant::now();
+        while started.elapsed() < duration {
+            let Some(level) = stream.try_next().await? else {
+                return Err(anyhow!("Light level stream ended unexpectedly"));
+            };
+            levels.push(f64::from(level));
+        }
+        let sample_rate_hz = levels.len() as f64 / started.elapsed().as_secs_f64();
+
+        let flicker = analyze_flicker(&levels, sample_rate_hz)
+            .ok_or_else(|| anyhow!("Received too few light levels to analyze"))?;
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&flicker)?);
+        } else {
+            print_flicker(&flicker);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+//! Re-analysis of saved runs, e.g. with a different changepoint detector, without running
+//! the scenario again
+
+use crate::cli::scenario::run::file_output::{self, FileOutput, SavedRun};
+use crate::cli::scenario::run::print_stats;
+use crate::cli::scenario::run::summary::{self, SummaryRow};
+use crate::statistics::{
+    parse_detector, parse_interpolation, process_measurements, process_recording, Measurement,
+};
+use anyhow::{anyhow, Context};
+use console::style;
+use late_mate_device::scenario::{Detector, Interpolation, Scenario};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Directory of a run saved with --output-json-dir. Runs of sweeps, suites and
+    /// comparisons are analyzed for every subdirectory
+    pub run_dir: PathBuf,
+
+    /// Changepoint detector, same as in "scenario run". The one the run was saved with is
+    /// used if not set
+    #[arg(long, value_name = "METHOD[:NAME=VALUE,...]", value_parser = parse_detector)]
+    pub detector: Option<Detector>,
+
+    /// Sub-sample changepoint estimation: none, linear or sigmoid. The one the run was saved
+    /// with is used if not set
+    #[arg(long, value_parser = parse_interpolation)]
+    pub interpolation: Option<Interpolation>,
+
+    /// Name of this analysis. It is used for a subdirectory to store results in.
+    /// If not provided, current date and time are used.
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Path to a directory where a subdirectory with re-analysed JSON results will be created.
+    /// Can be used simultaneously with other output options.
+    #[arg(long)]
+    output_json_dir: Option<PathBuf>,
+
+    /// Path to a directory where a subdirectory with re-analysed CSV results will be created.
+    /// Can be used simultaneously with other output options.
+    #[arg(long)]
+    output_csv_dir: Option<PathBuf>,
+}
+
+/// Subdirectories with saved recordings in them, sorted by name
+async fn sub_runs(run_dir: &Path) -> anyhow::Result<Vec<(String, Vec<SavedRun>)>> {
+    let mut dirs = vec![];
+    let mut entries = tokio::fs::read_dir(run_dir)
+        .await
+        .with_context(|| format!("Error reading \"{}\"", run_dir.to_string_lossy()))?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.path().is_dir() {
+            dirs.push(entry.path());
+        }
+    }
+    dirs.sort();
+
+    let mut result = vec![];
+    for dir in dirs {
+        let runs = file_output::read_json_runs(&dir).await?;
+        if !runs.is_empty() {
+            let name = dir
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            result.push((name, runs));
+        }
+    }
+
+    Ok(result)
+}
+
+/// The value all recordings were saved with, None if it wasn't saved
+fn saved_value<T: PartialEq>(
+    values: impl IntoIterator<Item = Option<T>>,
+    name: &str,
+) -> anyhow::Result<Option<T>> {
+    let mut values = values.into_iter();
+    let first = values.next().flatten();
+    if values.any(|value| value != first) {
+        return Err(anyhow!(
+            "Recordings were saved with different {name}s, set --{name} to re-analyse them"
+        ));
+    }
+    Ok(first)
+}
+
+impl Args {
+    /// Only what's used for processing and file output, the rest of the scenario isn't saved
+    fn scenario(&self, runs: &[SavedRun]) -> anyhow::Result<Scenario> {
+        let warmup = runs.iter().filter(|r| r.warmup).count();
+        let detector = match &self.detector {
+            Some(detector) => Some(detector.clone()),
+            None => saved_value(runs.iter().map(|r| r.detector.clone()), "detector")?,
+        };
+        let interpolation = match self.interpolation {
+            Some(interpolation) => Some(interpolation),
+            None => saved_value(runs.iter().map(|r| r.interpolation), "interpolation")?,
+        };
+        if detector.is_none() || interpolation.is_none() {
+            eprintln!(
+                "{}: the run was saved without its analysis settings, the defaults are used \
+                 unless --detector and --interpolation are set",
+                style("Warning").yellow()
+            );
+        }
+
+        Ok(Scenario {
+            repeats: u16::try_from(runs.len() - warmup).unwrap_or(u16::MAX),
+            warmup: u16::try_from(warmup).unwrap_or(u16::MAX),
+            detector: detector.unwrap_or_default(),
+            interpolation: interpolation.unwrap_or_default(),
+            ..Default::default()
+        })
+    }
+
+    async fn analyze_runs(
+        &self,
+        runs: Vec<SavedRun>,
+        file_outputs: &[FileOutput],
+    ) -> anyhow::Result<Vec<Measurement>> {
+        let scenario = self.scenario(&runs)?;
+        let mut measurements = Vec::with_capacity(usize::from(scenario.repeats));
+        for (idx, run) in runs.into_iter().enumerate() {
+            let processed =
+                process_recording(run.recording, &scenario.detector, scenario.interpolation);
+            for file_output in file_outputs {
+                file_output
+                    .output_run(&scenario, idx, &processed)
+                    .await
+                    .context("Error processing an output step")?;
+            }
+            if !run.warmup {
+                measurements.push(processed.measurement());
+            }
+        }
+
+        Ok(measurements)
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        let runs = file_output::read_json_runs(&self.run_dir).await?;
+        let sub_runs = if runs.is_empty() {
+            sub_runs(&self.run_dir).await?
+        } else {
+            vec![]
+        };
+        if runs.is_empty() && sub_runs.is_empty() {
+            return Err(anyhow!(
+                "No saved recordings found in \"{}\"",
+                self.run_dir.to_string_lossy()
+            ));
+        }
+
+        let file_outputs = file_output::prepare_outputs(
+            self.name.as_deref(),
+            self.output_json_dir.as_deref(),
+            self.output_csv_dir.as_deref(),
+        )
+        .await?;
+
+        if !runs.is_empty() {
+            let measurements = self.analyze_runs(runs, &file_outputs).await?;
+            let stats = process_measurements(&measurements);
+            print_stats(&stats);
+
+            let name = self.run_dir.to_string_lossy().to_string();
+            let rows = [SummaryRow::new(name, Default::default(), &stats)];
+            for file_output in &file_outputs {
+                file_output
+                    .output_summary("summary", &[], &rows)
+                    .await
+                    .context("Error while writing the summary")?;
+            }
+            return Ok(());
+        }
+
+        let params = vec!["run".to_owned()];
+        let mut rows = Vec::with_capacity(sub_runs.len());
+        for (name, runs) in sub_runs {
+            let mut sub_run_outputs = Vec::with_capacity(file_outputs.len());
+            for file_output in &file_outputs {
+                let output = file_output
+                    .prepare_sub_run(&name)
+                    .await
+                    .context("Error while preparing sub-run output directory")?;
+                sub_run_outputs.push(output);
+            }
+
+            let measurements = self.analyze_runs(runs, &sub_run_outputs).await?;
+            let stats = process_measurements(&measurements);
+            eprintln!("{}", style(&name).cyan().bold());
+            print_stats(&stats);
+
+            let row_params = [("run".to_owned(), name.to_owned().into())];
+            rows.push(SummaryRow::new(
+                name,
+                row_params.into_iter().collect(),
+                &stats,
+            ));
+        }
+
+        for file_output in &file_outputs {
+            file_output
+                .output_summary("summary", &params, &rows)
+                .await
+                .context("Error while writing the summary")?;
+        }
+
+        eprintln!(
+            "{}, results (in milliseconds):",
+            style("Analysis complete").bold()
+        );
+        summary::print_summary(&params, &rows);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_saved_value() {
+        assert_eq!(saved_value::<u8>([], "detector").unwrap(), None);
+        assert_eq!(saved_value::<u8>([None, None], "detector").unwrap(), None);
+        assert_eq!(
+            saved_value([Some(1), Some(1)], "detector").unwrap(),
+            Some(1)
+        );
+        assert!(saved_value([Some(1), Some(2)], "detector").is_err());
+        assert!(saved_value([Some(1), None], "detector").is_err());
+        assert!(saved_value([None, Some(1)], "detector").is_err());
+    }
+}
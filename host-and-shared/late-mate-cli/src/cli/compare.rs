@@ -0,0 +1,262 @@
+//! Comparison of saved runs, e.g. of a build against the previous release in CI
+
+use crate::cli::scenario::run::file_output;
+use crate::cli::scenario::run::summary::print_table;
+use crate::statistics::{
+    mann_whitney_u, median_difference_ci, parse_alpha, permutation_test, process_measurements,
+    Measurement, StatsSummary,
+};
+use anyhow::anyhow;
+use console::style;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Test {
+    /// Compares the whole distributions, fast with any number of repeats
+    #[default]
+    MannWhitney,
+    /// Compares the medians by shuffling the repeats between runs
+    Permutation,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Two or more run directories saved with --output-json-dir or --output-csv-dir.
+    /// The first one is the baseline
+    #[arg(required = true, num_args = 2..)]
+    pub runs: Vec<PathBuf>,
+
+    /// Significance test of the difference with the baseline
+    #[arg(long, value_enum, default_value_t)]
+    pub test: Test,
+
+    /// Significance level of the comparison with the baseline
+    #[arg(long, default_value_t = 0.05, value_parser = parse_alpha)]
+    pub alpha: f64,
+
+    /// Exit with an error if the median of any run is slower than the baseline's by more than
+    /// this, and the difference is significant. Runs with too few samples to compare fail too.
+    /// Useful for gating CI on latency regressions
+    #[arg(long, value_name = "MS")]
+    pub fail_if_slower_by: Option<f64>,
+}
+
+struct Run {
+    name: String,
+    millis: Vec<f64>,
+    stats: StatsSummary,
+}
+
+struct Difference {
+    median: f64,
+    ci_low: f64,
+    ci_high: f64,
+    /// Probability that a repeat of the run is slower than a repeat of the baseline
+    p_slower: f64,
+    p_value: f64,
+}
+
+impl Args {
+    async fn read_run(path: &Path) -> anyhow::Result<Run> {
+        let changepoints = file_output::read_changepoints(path).await?;
+        let measurements = changepoints
+            .iter()
+            .map(|&changepoint_us| Measurement {
+                changepoint_us,
+                response: None,
+                n_transitions: 0,
+                transition_intervals_us: vec![],
+            })
+            .collect::<Vec<_>>();
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| path.to_string_lossy().to_string());
+
+        Ok(Run {
+            name,
+            millis: changepoints
+                .iter()
+                .filter_map(|us| us.map(|us| f64::from(us) / 1000f64))
+                .collect(),
+            stats: process_measurements(&measurements).summary(),
+        })
+    }
+
+    /// None if either run has too few measurements to compare
+    fn difference(&self, run: &Run, baseline: &Run, n_comparisons: f64) -> Option<Difference> {
+        let mann_whitney = mann_whitney_u(&run.millis, &baseline.millis)?;
+        let ci = median_difference_ci(&run.millis, &baseline.millis)?;
+        let p_value = match self.test {
+            Test::MannWhitney => mann_whitney.p_value,
+            Test::Permutation => permutation_test(&run.millis, &baseline.millis)?,
+        };
+
+        Some(Difference {
+            median: statistical::median(&run.millis) - statistical::median(&baseline.millis),
+            ci_low: ci.low,
+            ci_high: ci.high,
+            p_slower: mann_whitney.effect_size,
+            // Bonferroni correction, every run is compared to the baseline
+            p_value: (p_value * n_comparisons).min(1.0),
+        })
+    }
+
+    /// Runs failing the --fail-if-slower-by gate. Runs that can't be compared fail it as well,
+    /// a broken run shouldn't pass as "not slower"
+    fn regressions(
+        &self,
+        others: &[Run],
+        differences: &[Option<Difference>],
+        threshold: f64,
+    ) -> Vec<String> {
+        others
+            .iter()
+            .zip(differences)
+            .filter_map(|(run, difference)| match difference {
+                None => Some(format!("{} (too few samples to compare)", run.name)),
+                Some(d) => (d.median > threshold && d.p_value < self.alpha)
+                    .then(|| format!("{} ({:+.1} ms)", run.name, d.median)),
+            })
+            .collect()
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        let mut runs = Vec::with_capacity(self.runs.len());
+        for path in &self.runs {
+            runs.push(Self::read_run(path).await?);
+        }
+
+        let (baseline, others) = runs.split_first().expect("There must be at least two runs");
+        let n_comparisons = others.len() as f64;
+        let differences = others
+            .iter()
+            .map(|run| self.difference(run, baseline, n_comparisons))
+            .collect::<Vec<_>>();
+
+        let stat = |v: Option<f64>| v.map(|v| format!("{v:.1}")).unwrap_or("-".to_owned());
+        let header = [
+            "run",
+            "n_samples",
+            "failed",
+            "median",
+            "p95",
+            "mean",
+            "median_diff",
+            "diff_ci",
+            "p_slower",
+            "p",
+            "verdict",
+        ]
+        .map(str::to_owned);
+        let cells = runs
+            .iter()
+            .zip([None].into_iter().chain(differences.iter().map(Some)))
+            .map(|(run, difference)| {
+                let mut row = vec![
+                    run.name.clone(),
+                    run.stats.n_samples.to_string(),
+                    run.stats.failed.to_string(),
+                    stat(run.stats.median),
+                    stat(run.stats.percentiles.map(|p| p.p95)),
+                    stat(run.stats.mean),
+                ];
+                row.extend(match difference {
+                    None => ["baseline", "", "", "", ""].map(str::to_owned),
+                    Some(None) => ["-", "-", "-", "-", "too few samples"].map(str::to_owned),
+                    Some(Some(d)) => [
+                        format!("{:+.1}", d.median),
+                        format!("[{:+.1}, {:+.1}]", d.ci_low, d.ci_high),
+                        format!("{:.2}", d.p_slower),
+                        format!("{:.4}", d.p_value),
+                        if d.p_value < self.alpha {
+                            "significant".to_owned()
+                        } else {
+                            "not significant".to_owned()
+                        },
+                    ],
+                });
+                row
+            })
+            .collect::<Vec<_>>();
+
+        let test_name = match self.test {
+            Test::MannWhitney => "Mann–Whitney U test",
+            Test::Permutation => "permutation test",
+        };
+        eprintln!(
+            "{} (ms, {test_name}, α = {}, 95% CI of the median difference):",
+            style("Comparison with the baseline").bold(),
+            self.alpha
+        );
+        print_table(&header, &cells);
+
+        let Some(threshold) = self.fail_if_slower_by else {
+            return Ok(());
+        };
+        let regressions = self.regressions(others, &differences, threshold);
+        if regressions.is_empty() {
+            eprintln!(
+                "{}",
+                style(format!(
+                    "No run is slower than the baseline by more than {threshold} ms"
+                ))
+                .green()
+            );
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Slower than the baseline by more than {threshold} ms or not comparable: {}",
+                regressions.join(", ")
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(name: &str) -> Run {
+        Run {
+            name: name.to_owned(),
+            millis: vec![],
+            stats: StatsSummary::default(),
+        }
+    }
+
+    fn difference(median: f64, p_value: f64) -> Difference {
+        Difference {
+            median,
+            ci_low: median - 1.0,
+            ci_high: median + 1.0,
+            p_slower: 0.5,
+            p_value,
+        }
+    }
+
+    #[test]
+    fn test_regressions() {
+        let args = Args {
+            runs: vec![],
+            test: Test::default(),
+            alpha: 0.05,
+            fail_if_slower_by: Some(1.0),
+        };
+        let others = ["slower", "insignificant", "faster", "incomparable"].map(run);
+        let differences = [
+            Some(difference(2.0, 0.01)),
+            Some(difference(2.0, 0.2)),
+            Some(difference(-2.0, 0.01)),
+            None,
+        ];
+
+        assert_eq!(
+            args.regressions(&others, &differences, 1.0),
+            [
+                "slower (+2.0 ms)".to_owned(),
+                "incomparable (too few samples to compare)".to_owned(),
+            ]
+        );
+    }
+}
@@ -1,5 +1,6 @@
 mod send;
 mod show_type;
+mod type_text;
 
 #[derive(Debug, clap::Subcommand)]
 pub enum Hid {
@@ -7,4 +8,6 @@ pub enum Hid {
     Send(send::Args),
     /// Print a HID report TypeScript type
     ShowType(show_type::Args),
+    /// Type text by sending key presses and releases
+    Type(type_text::Args),
 }
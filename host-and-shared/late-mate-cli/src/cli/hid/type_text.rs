@@ -0,0 +1,38 @@
+use late_mate_device::hid::layout::{self, KeyboardLayout};
+use late_mate_device::hid::HidReport;
+use late_mate_device::Device;
+use std::time::Duration;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Text to type. Every character is sent as a key press followed by a release
+    text: String,
+
+    /// Keyboard layout that is selected on the machine under test
+    #[arg(long, default_value_t, value_parser = parse_layout)]
+    layout: KeyboardLayout,
+
+    /// Wait between every press and release, some targets ignore zero-length presses
+    #[arg(long, value_name = "MS")]
+    hold_ms: Option<u16>,
+}
+
+fn parse_layout(s: &str) -> Result<KeyboardLayout, layout::UnknownLayout> {
+    s.parse()
+}
+
+impl Args {
+    pub async fn run(self, device: &Device) -> anyhow::Result<()> {
+        let reports = layout::type_text(&self.text, self.layout)?;
+        // reports are press/release pairs
+        for (idx, report) in reports.into_iter().enumerate() {
+            device.send_hid_report(&HidReport::Keyboard(report)).await?;
+            if let Some(ms) = self.hold_ms.filter(|_| idx % 2 == 0) {
+                tokio::time::sleep(Duration::from_millis(u64::from(ms))).await;
+            }
+        }
+        eprintln!("Done!");
+
+        Ok(())
+    }
+}
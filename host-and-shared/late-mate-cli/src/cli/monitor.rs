@@ -0,0 +1,254 @@
+//! Live light level in the terminal, mostly useful for aiming the sensor at the right spot
+//! on the screen before measuring
+
+use anyhow::anyhow;
+use console::{style, Term};
+use futures::TryStreamExt;
+use late_mate_device::Device;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::time::{interval, MissedTickBehavior};
+
+/// The live view is redrawn this often
+const FRAME: Duration = Duration::from_millis(50);
+/// Plain output is throttled down to 120hz, no point streaming faster
+const PLAIN_FRAME: Duration = Duration::from_micros(1_000_000 / 120);
+/// Every sparkline column is the average level of one frame
+const SPARKLINE_WIDTH: usize = 60;
+const SPARKLINE_CHARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+/// The sparkline is scaled to its own range, but not below this span, so that
+/// sensor noise doesn't look like a change
+const SPARKLINE_MIN_SPAN: f64 = 0.05;
+const LEVEL_BAR_WIDTH: usize = 40;
+/// Levels this close to the maximum are likely clipped by the sensor
+const SATURATION_FRACTION: f64 = 0.99;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Length of the window for min/max/noise statistics, in seconds
+    #[arg(long, default_value_t = 5.0)]
+    window: f64,
+
+    /// Print one normalised light level (in percent) per line instead of the live view,
+    /// e.g. for piping into other tools
+    #[arg(long)]
+    plain: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct WindowStats {
+    min: f64,
+    max: f64,
+    mean: f64,
+    /// Standard deviation of the samples
+    noise: f64,
+}
+
+/// Normalised light levels received within the statistics window
+#[derive(Debug)]
+struct LightWindow {
+    length: Duration,
+    samples: VecDeque<(Instant, f64)>,
+}
+
+impl LightWindow {
+    fn new(length: Duration) -> Self {
+        Self {
+            length,
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, at: Instant, level: f64) {
+        self.samples.push_back((at, level));
+        while let Some((oldest, _)) = self.samples.front() {
+            if at.duration_since(*oldest) <= self.length {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    fn stats(&self) -> Option<WindowStats> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let n = self.samples.len() as f64;
+        let levels = || self.samples.iter().map(|(_, level)| *level);
+        let mean = levels().sum::<f64>() / n;
+        let variance = levels().map(|l| (l - mean).powi(2)).sum::<f64>() / n;
+
+        Some(WindowStats {
+            min: levels().fold(f64::INFINITY, f64::min),
+            max: levels().fold(f64::NEG_INFINITY, f64::max),
+            mean,
+            noise: variance.sqrt(),
+        })
+    }
+}
+
+fn sparkline(values: &VecDeque<f64>) -> String {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    // centre the range if it's narrower than the minimum span
+    let span = (max - min).max(SPARKLINE_MIN_SPAN);
+    let low = (min + max - span) / 2.0;
+
+    let top = (SPARKLINE_CHARS.len() - 1) as f64;
+    values
+        .iter()
+        .map(|v| {
+            let idx = (((v - low) / span) * top).round().clamp(0.0, top);
+            SPARKLINE_CHARS[idx as usize]
+        })
+        .collect()
+}
+
+fn level_bar(level: f64) -> String {
+    let filled = ((level.clamp(0.0, 1.0) * LEVEL_BAR_WIDTH as f64).round()) as usize;
+    format!(
+        "{}{}",
+        "█".repeat(filled),
+        "░".repeat(LEVEL_BAR_WIDTH - filled)
+    )
+}
+
+fn percent(level: f64) -> String {
+    format!("{:.2}%", level * 100.0)
+}
+
+impl Args {
+    fn render(
+        &self,
+        level: f64,
+        history: &VecDeque<f64>,
+        stats: &WindowStats,
+        saturated: bool,
+    ) -> Vec<String> {
+        let mut lines = vec![
+            format!(
+                "{} {:>8} {}",
+                style("Light level").bold(),
+                style(percent(level)).bold(),
+                level_bar(level)
+            ),
+            format!("{}", style(sparkline(history)).cyan()),
+            format!(
+                "Last {}s: min {}, max {}, mean {}, noise (σ) {:.3}%",
+                self.window,
+                percent(stats.min),
+                percent(stats.max),
+                percent(stats.mean),
+                stats.noise * 100.0
+            ),
+        ];
+        if saturated {
+            lines.push(format!(
+                "{}",
+                style("Warning: the sensor is saturated, measurements will be clipped. Point it at a darker area or move it away from the screen")
+                    .red()
+                    .bold()
+            ));
+        }
+        lines
+    }
+
+    pub async fn run(self, device: &Device) -> anyhow::Result<()> {
+        if device.max_light_level == 0 {
+            return Err(anyhow!(
+                "The device didn't report its maximum light level, try updating the firmware"
+            ));
+        }
+        if self.window <= 0.0 || !self.window.is_finite() {
+            return Err(anyhow!("Statistics window must be positive"));
+        }
+        let max_light_level = f64::from(device.max_light_level);
+
+        let stream = device.stream_light_level().await?;
+        tokio::pin!(stream);
+
+        let mut frame = interval(if self.plain { PLAIN_FRAME } else { FRAME });
+        frame.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        let term = Term::stderr();
+        let mut window = LightWindow::new(Duration::from_secs_f64(self.window));
+        let mut history = VecDeque::with_capacity(SPARKLINE_WIDTH);
+        let mut frame_levels = Vec::new();
+        let mut drawn_lines = 0;
+
+        if !self.plain {
+            eprintln!("Press Ctrl+C to stop");
+        }
+
+        loop {
+            tokio::select! {
+                light_level = stream.try_next() => {
+                    let Some(light_level) = light_level? else {
+                        return Err(anyhow!("Light level stream ended unexpectedly"));
+                    };
+                    let level = f64::from(light_level) / max_light_level;
+                    window.push(Instant::now(), level);
+                    frame_levels.push(level);
+                }
+                _ = frame.tick() => {
+                    let Some(&level) = frame_levels.last() else {
+                        continue;
+                    };
+                    if self.plain {
+                        println!("{:.4}", level * 100.0);
+                        frame_levels.clear();
+                        continue;
+                    }
+
+                    let saturated = frame_levels.iter().any(|l| *l >= SATURATION_FRACTION);
+                    let frame_mean = frame_levels.iter().sum::<f64>() / frame_levels.len() as f64;
+                    frame_levels.clear();
+                    if history.len() == SPARKLINE_WIDTH {
+                        history.pop_front();
+                    }
+                    history.push_back(frame_mean);
+
+                    let stats = window.stats().expect("The window can't be empty after a push");
+                    let lines = self.render(level, &history, &stats, saturated);
+                    term.clear_last_lines(drawn_lines)?;
+                    for line in &lines {
+                        term.write_line(line)?;
+                    }
+                    drawn_lines = lines.len();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_stats() {
+        let start = Instant::now();
+        let mut window = LightWindow::new(Duration::from_secs(1));
+        window.push(start, 0.9);
+        window.push(start + Duration::from_millis(1500), 0.2);
+        window.push(start + Duration::from_millis(2000), 0.4);
+
+        // the first sample is outside the window by now
+        let stats = window.stats().unwrap();
+        assert_eq!(stats.min, 0.2);
+        assert_eq!(stats.max, 0.4);
+        assert!((stats.mean - 0.3).abs() < 1e-9);
+        assert!((stats.noise - 0.1).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_sparkline() {
+        let values = VecDeque::from([0.1, 0.5, 0.9]);
+        assert_eq!(sparkline(&values), "▁▅█");
+
+        // noise within the minimum span stays in the middle
+        let values = VecDeque::from([0.5, 0.501]);
+        assert_eq!(sparkline(&values), "▄▅");
+    }
+}
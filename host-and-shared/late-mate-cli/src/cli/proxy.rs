@@ -0,0 +1,26 @@
+use anyhow::Context;
+use late_mate_device::Device;
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Address to accept `--remote` clients on. The protocol isn't authenticated, only listen
+    /// on networks you trust
+    #[arg(long, default_value = "127.0.0.1:9118")]
+    listen: SocketAddr,
+}
+
+impl Args {
+    pub async fn run(self, device: Device) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(self.listen)
+            .await
+            .with_context(|| format!("Couldn't listen on {}", self.listen))?;
+        println!(
+            "Sharing the device on {}, use `late-mate --remote <this host>:{} ...` to connect",
+            listener.local_addr()?,
+            self.listen.port()
+        );
+        Ok(late_mate_device::proxy::serve(device, listener).await?)
+    }
+}
@@ -0,0 +1,23 @@
+use late_mate_device::Device;
+use std::net::IpAddr;
+use std::path::PathBuf;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    #[arg(long, default_value = "127.0.0.1")]
+    interface: IpAddr,
+
+    #[arg(long, default_value_t = 9118)]
+    port: u16,
+
+    /// Directory with the built frontend (`yarn build` in late-mate-cli/frontend creates
+    /// frontend/dist) to serve next to the websocket. Without it, use the Vite dev server
+    #[arg(long)]
+    frontend_dir: Option<PathBuf>,
+}
+
+impl Args {
+    pub async fn run(self, device: Device) -> anyhow::Result<()> {
+        crate::server::run(device, self.interface, self.port, self.frontend_dir).await
+    }
+}
@@ -1,10 +1,24 @@
+pub mod ab;
+mod compile;
 mod example;
-mod run;
+pub mod run;
+mod schema;
+mod validate;
 
 #[derive(Debug, clap::Subcommand)]
 pub enum Scenario {
     /// Run a latency testing scenario
     Run(run::Args),
+    /// Compare two or more scenarios by running their repeats interleaved in random order
+    Ab(ab::Args),
+    /// Validate a scenario and estimate its duration, doesn't need a connected device
+    Validate(validate::Args),
+    /// Show the steps that are sent to the device for a scenario, doesn't need a connected device
+    Compile(compile::Args),
+    /// Print a JSON Schema of scenario files, e.g. for editor completion.
+    /// For TOML files, Taplo-based editors pick it up from a `#:schema ./scenario.schema.json`
+    /// comment on the first line
+    Schema(schema::Args),
     /// Scenario examples in JSON and TOML
     Example(example::Args),
 }
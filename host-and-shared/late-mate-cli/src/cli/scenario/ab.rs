@@ -0,0 +1,234 @@
+use crate::cli::scenario::run::file_output::{self, FileOutput};
+use crate::cli::scenario::run::summary::{self, SummaryRow};
+use crate::cli::scenario::run::{parse_var_override, print_lints, print_stats, ScenarioSource};
+use crate::cli::suite::run::{run_entries, schedule, Entry, Order};
+use crate::statistics::{
+    mann_whitney_u, parse_alpha, parse_detector, parse_interpolation, process_measurements,
+};
+use anyhow::{anyhow, Context};
+use console::style;
+use late_mate_device::scenario::{Detector, Interpolation, Scenario};
+use late_mate_device::Device;
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Paths to two or more .json or .toml scenarios to compare. The first one is the baseline
+    #[arg(required = true, num_args = 2..)]
+    pub inputs: Vec<String>,
+
+    /// Name of this test run. It is used for a subdirectory to store results in,
+    /// with a subdirectory per scenario. If not provided, current date and time are used.
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Path to a directory where a subdirectory with JSON results will be created.
+    /// Can be used simultaneously with other output options.
+    #[arg(long)]
+    output_json_dir: Option<PathBuf>,
+
+    /// Path to a directory where a subdirectory with CSV results will be created.
+    /// Can be used simultaneously with other output options.
+    #[arg(long)]
+    output_csv_dir: Option<PathBuf>,
+
+    /// Override "repeats" field of all scenarios
+    #[arg(long)]
+    pub repeats: Option<u16>,
+
+    /// Override "warmup" field of all scenarios
+    #[arg(long)]
+    pub warmup: Option<u16>,
+
+    /// Override changepoint detector of all scenarios, same as in "scenario run"
+    #[arg(long, value_name = "METHOD[:NAME=VALUE,...]", value_parser = parse_detector)]
+    pub detector: Option<Detector>,
+
+    /// Override sub-sample changepoint estimation of all scenarios, same as in "scenario run"
+    #[arg(long, value_parser = parse_interpolation)]
+    pub interpolation: Option<Interpolation>,
+
+    /// Set a scenario variable in all scenarios, same as in "scenario run"
+    #[arg(long = "set", value_name = "NAME=VALUE", value_parser = parse_var_override)]
+    pub vars: Vec<(String, serde_json::Value)>,
+
+    /// Significance level of the comparison with the baseline
+    #[arg(long, default_value_t = 0.05, value_parser = parse_alpha)]
+    pub alpha: f64,
+}
+
+/// Arms are named with a letter and the file name, e.g. "a-type_a", so that
+/// the same file can be compared with itself
+fn arm_name(idx: usize, input: &str) -> String {
+    let letter = char::from(b'a' + u8::try_from(idx % 26).expect("idx % 26 must fit into u8"));
+    let stem = Path::new(input)
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or(input.to_owned());
+    format!("{letter}-{stem}")
+}
+
+impl Args {
+    async fn read_scenarios(&self) -> anyhow::Result<Vec<(String, Scenario)>> {
+        if self.inputs.len() > 26 {
+            return Err(anyhow!("At most 26 scenarios can be compared at once"));
+        }
+
+        let mut scenarios = Vec::with_capacity(self.inputs.len());
+        for (idx, input) in self.inputs.iter().enumerate() {
+            if input == "-" {
+                return Err(anyhow!("Compared scenarios can't be read from STDIN"));
+            }
+
+            let mut scenario = ScenarioSource::read(input)
+                .await?
+                .scenario(&self.vars)
+                .with_context(|| format!("Error in scenario \"{input}\""))?;
+            if let Some(repeats) = self.repeats {
+                scenario.repeats = repeats;
+            }
+            if let Some(warmup) = self.warmup {
+                scenario.warmup = warmup;
+            }
+            if let Some(detector) = &self.detector {
+                scenario.detector = detector.clone();
+            }
+            if let Some(interpolation) = self.interpolation {
+                scenario.interpolation = interpolation;
+            }
+            scenario
+                .validate()
+                .with_context(|| format!("Validation error in scenario \"{input}\""))?;
+
+            let name = arm_name(idx, input);
+            print_lints(&scenario, Some(&name));
+            scenarios.push((name, scenario));
+        }
+
+        Ok(scenarios)
+    }
+
+    async fn prepare_outputs(&self) -> anyhow::Result<Vec<FileOutput>> {
+        file_output::prepare_outputs(
+            self.name.as_deref(),
+            self.output_json_dir.as_deref(),
+            self.output_csv_dir.as_deref(),
+        )
+        .await
+    }
+
+    fn print_comparison(&self, entries: &[Entry]) {
+        let millis = |entry: &Entry| {
+            entry
+                .measurements
+                .iter()
+                .filter_map(|m| m.changepoint_us.map(|us| f64::from(us) / 1000f64))
+                .collect::<Vec<_>>()
+        };
+
+        let (baseline, others) = entries
+            .split_first()
+            .expect("There must be at least two entries");
+        let baseline_millis = millis(baseline);
+        // Bonferroni correction, every arm is compared to the baseline
+        let n_comparisons = others.len() as f64;
+
+        eprintln!(
+            "{} (Mann–Whitney U test, α = {}):",
+            style("Comparison with the baseline").bold(),
+            self.alpha
+        );
+        for entry in others {
+            let entry_millis = millis(entry);
+            let Some(test) = mann_whitney_u(&entry_millis, &baseline_millis) else {
+                eprintln!(
+                    "  {} vs {}: not enough measurements",
+                    entry.name, baseline.name
+                );
+                continue;
+            };
+
+            let median_diff =
+                statistical::median(&entry_millis) - statistical::median(&baseline_millis);
+            let p_value = (test.p_value * n_comparisons).min(1.0);
+            let verdict = if p_value < self.alpha {
+                style("significant").green().bold()
+            } else {
+                style("not significant").yellow()
+            };
+            eprintln!(
+                "  {} vs {}: median difference {} ms, P(slower) = {:.2}, p = {:.4}, {}",
+                style(&entry.name).cyan(),
+                style(&baseline.name).cyan(),
+                style(format!("{median_diff:+.1}")).bold(),
+                test.effect_size,
+                p_value,
+                verdict
+            );
+        }
+    }
+
+    pub async fn run(self, device: &Device) -> anyhow::Result<()> {
+        let scenarios = self.read_scenarios().await?;
+        let file_outputs = self.prepare_outputs().await?;
+
+        let mut entries = Vec::with_capacity(scenarios.len());
+        for (name, scenario) in scenarios {
+            entries.push(Entry::new(name, scenario, &file_outputs).await?);
+        }
+
+        let counts = entries
+            .iter()
+            .map(|e| (e.scenario.warmup, e.scenario.repeats))
+            .collect::<Vec<_>>();
+        let schedule = schedule(Order::Randomised, &counts, &mut SmallRng::from_entropy());
+        run_entries(device, &mut entries, schedule).await?;
+
+        let params = vec!["arm".to_owned(), "scenario".to_owned()];
+        let mut rows = Vec::with_capacity(entries.len());
+        for (entry, input) in entries.iter().zip(&self.inputs) {
+            let stats = process_measurements(&entry.measurements);
+            eprintln!("{}", style(&entry.name).cyan().bold());
+            print_stats(&stats);
+
+            let row_params = [
+                ("arm".to_owned(), entry.name.to_owned().into()),
+                ("scenario".to_owned(), input.to_owned().into()),
+            ];
+            rows.push(SummaryRow::new(
+                entry.name.to_owned(),
+                row_params.into_iter().collect(),
+                &stats,
+            ));
+        }
+
+        for file_output in &file_outputs {
+            file_output
+                .output_summary("ab", &params, &rows)
+                .await
+                .context("Error while writing the comparison summary")?;
+        }
+
+        eprintln!(
+            "{}, results (in milliseconds):",
+            style("Runs complete").bold()
+        );
+        summary::print_summary(&params, &rows);
+        self.print_comparison(&entries);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arm_name() {
+        assert_eq!(arm_name(0, "scenarios/type_a.toml"), "a-type_a");
+        assert_eq!(arm_name(1, "scenarios/type_a.toml"), "b-type_a");
+    }
+}
@@ -0,0 +1,94 @@
+use crate::cli::scenario::run::{parse_var_override, ScenarioSource};
+use anyhow::Context;
+use late_mate_device::hid::HidReport;
+use late_mate_device::scenario::{to_device_scenario, HidState};
+use late_mate_shared::comms::host_to_device;
+use late_mate_shared::MAX_SCENARIO_LENGTH;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Path to a .json or .toml with a scenario. Set to "-" to read from STDIN
+    pub input: String,
+
+    /// Set a scenario variable, same as in "scenario run"
+    #[arg(long = "set", value_name = "NAME=VALUE", value_parser = parse_var_override)]
+    pub vars: Vec<(String, serde_json::Value)>,
+
+    /// Print the device scenarios as JSON instead of a human-readable listing
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Compiled {
+    test: host_to_device::Scenario,
+    revert: Option<host_to_device::Scenario>,
+}
+
+fn print_device_scenario(
+    title: &str,
+    scenario: &host_to_device::Scenario,
+    hid_index: &[HidReport],
+) -> anyhow::Result<()> {
+    println!(
+        "{title} ({}/{MAX_SCENARIO_LENGTH} device steps):",
+        scenario.steps.len()
+    );
+
+    let start_idx = scenario.start_recording_at_idx.map(usize::from);
+    for (idx, step) in scenario.steps.iter().enumerate() {
+        if start_idx == Some(idx) {
+            println!("      -- recording starts --");
+        }
+        match step {
+            host_to_device::ScenarioStep::Wait { ms } => println!("  {idx:>2}  wait {ms}ms"),
+            host_to_device::ScenarioStep::HidRequest(request) => {
+                let report = serde_json::to_string(&hid_index[usize::from(request.id)])
+                    .context("Error serialising a HID report")?;
+                println!("  {idx:>2}  hid   {report}");
+                println!("            {:?}", request.report);
+            }
+        }
+    }
+    if start_idx == Some(scenario.steps.len()) {
+        println!("      -- recording starts --");
+    }
+
+    Ok(())
+}
+
+impl Args {
+    pub async fn run(self) -> anyhow::Result<()> {
+        let scenario = ScenarioSource::read(&self.input)
+            .await?
+            .scenario(&self.vars)?;
+        scenario.validate().context("Scenario validation error")?;
+
+        // the same sequence as in Device::run_scenario, the revert starts with the HID state
+        // left by the test
+        let mut hid_state = HidState::default();
+        let (test, test_hid_index) = to_device_scenario(&scenario.test, &mut hid_state);
+        let revert = scenario
+            .revert_steps()?
+            .map(|revert| to_device_scenario(&revert, &mut hid_state));
+
+        if self.json {
+            let compiled = Compiled {
+                test,
+                revert: revert.map(|(revert, _)| revert),
+            };
+            let serialised = serde_json::to_string_pretty(&compiled)
+                .context("Error serialising the compiled scenario")?;
+            println!("{serialised}");
+            return Ok(());
+        }
+
+        print_device_scenario("Test", &test, &test_hid_index)?;
+        if let Some((revert, revert_hid_index)) = revert {
+            println!();
+            print_device_scenario("Revert", &revert, &revert_hid_index)?;
+        }
+
+        Ok(())
+    }
+}
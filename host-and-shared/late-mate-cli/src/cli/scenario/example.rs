@@ -1,5 +1,3 @@
-use late_mate_device::Device;
-
 #[derive(Debug, clap::Args)]
 pub struct Args {
     #[arg(value_enum)]
@@ -15,7 +13,7 @@ enum Format {
 }
 
 impl Args {
-    pub async fn run(self, _device: &Device) -> anyhow::Result<()> {
+    pub async fn run(self) -> anyhow::Result<()> {
         match self.format {
             Format::Toml => {
                 println!(
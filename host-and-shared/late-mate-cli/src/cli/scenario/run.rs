@@ -1,15 +1,27 @@
-mod file_output;
-
-use crate::statistics::{process_changepoints, process_recording, FinalStats, ProcessedRecording};
+pub mod deserialize;
+pub mod file_output;
+pub mod summary;
+mod sweep;
+pub mod template;
+
+use crate::statistics::{
+    median_ci, parse_detector, parse_interpolation, process_measurements, process_recording,
+    FinalStats, Measurement, ProcessedRecording, ResponseStats, TransitionStats,
+};
 use anyhow::{anyhow, Context};
 use console::style;
-use file_output::{FileOutput, FileOutputKind};
-use futures::{StreamExt, TryStreamExt};
+use file_output::FileOutput;
+use futures::TryStreamExt;
 use indicatif::{HumanDuration, ProgressBar, ProgressState, ProgressStyle};
-use late_mate_device::scenario::Scenario;
+use late_mate_device::scenario::{Detector, Interpolation, Recording, Revert, Scenario};
 use late_mate_device::Device;
-use std::path::PathBuf;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use summary::SummaryRow;
+use sweep::SweepPoint;
+pub use template::parse_var_override;
+use template::Format;
 use tokio::io::{AsyncRead, AsyncReadExt};
 
 #[derive(Debug, clap::Args)]
@@ -35,57 +47,165 @@ pub struct Args {
     /// Override scenario's "repeats" field
     #[arg(long)]
     pub repeats: Option<u16>,
+
+    /// Override scenario's "warmup" field, the number of repeats that run first and are
+    /// excluded from statistics
+    #[arg(long)]
+    pub warmup: Option<u16>,
+
+    /// Keep repeating the scenario until the 95% confidence interval of the median latency
+    /// is narrower than this many milliseconds, instead of a fixed number of repeats
+    #[arg(long, value_name = "MS", conflicts_with = "repeats")]
+    pub until_ci: Option<f64>,
+
+    /// The least number of repeats with --until-ci
+    #[arg(long, default_value_t = 10, requires = "until_ci")]
+    pub min_repeats: u16,
+
+    /// The most number of repeats with --until-ci, even if the interval is still too wide
+    #[arg(long, default_value_t = 500, requires = "until_ci")]
+    pub max_repeats: u16,
+
+    /// Override scenario's changepoint detector, e.g. "cusum" or
+    /// "swing:percent=20,noise_window_ms=10". Methods are threshold (the default), cusum,
+    /// step_fit and swing
+    #[arg(long, value_name = "METHOD[:NAME=VALUE,...]", value_parser = parse_detector)]
+    pub detector: Option<Detector>,
+
+    /// Override scenario's sub-sample changepoint estimation: none, linear (the default)
+    /// or sigmoid
+    #[arg(long, value_parser = parse_interpolation)]
+    pub interpolation: Option<Interpolation>,
+
+    /// Set a scenario variable, overriding its value from the [vars] section.
+    /// Can be used multiple times
+    #[arg(long = "set", value_name = "NAME=VALUE", value_parser = template::parse_var_override)]
+    pub vars: Vec<(String, serde_json::Value)>,
+
+    /// Run the scenario for every combination of variable values, e.g.
+    /// --sweep wait_ms=0,50,100 --sweep key=a,space. Adds to the [sweep] section.
+    /// Results of every combination are stored in a subdirectory of the run
+    #[arg(long, value_name = "NAME=VALUE1,VALUE2,...", value_parser = sweep::parse_sweep_override)]
+    pub sweep: Vec<(String, Vec<serde_json::Value>)>,
+
+    /// Validate the scenario and print it with the revert section resolved
+    /// (e.g. for revert = "auto") without running it
+    #[arg(long)]
+    pub dry_run: bool,
 }
 
-async fn read_scenario(input: &str) -> anyhow::Result<Scenario> {
-    let input_stdin = input == "-";
+/// Scenario file contents, possibly a template that needs variables to become a Scenario
+pub struct ScenarioSource {
+    input: String,
+    format: Option<Format>,
+    contents: String,
+    document: serde_json::Value,
+}
 
-    let scenario_str = {
-        let mut reader: Pin<Box<dyn AsyncRead>> = if input_stdin {
-            Box::pin(tokio::io::stdin())
+impl ScenarioSource {
+    pub async fn read(input: &str) -> anyhow::Result<Self> {
+        let input_stdin = input == "-";
+
+        let contents = {
+            let mut reader: Pin<Box<dyn AsyncRead>> = if input_stdin {
+                Box::pin(tokio::io::stdin())
+            } else {
+                Box::pin(
+                    tokio::fs::File::open(input.to_owned())
+                        .await
+                        .context("Error while opening the scenario file")?,
+                )
+            };
+            let mut result = String::new();
+            reader
+                .read_to_string(&mut result)
+                .await
+                .context("Error while reading the scenario")?;
+            result
+        };
+
+        let format = if input_stdin {
+            None
         } else {
-            Box::pin(
-                tokio::fs::File::open(input.to_owned())
-                    .await
-                    .context("Error while opening the scenario file")?,
-            )
+            Some(Format::from_path(input).ok_or_else(|| {
+                anyhow!("The scenario file name extension must be either .toml or .json")
+            })?)
         };
-        let mut result = String::new();
-        reader
-            .read_to_string(&mut result)
-            .await
-            .context("Error while reading the scenario")?;
-        result
-    };
 
-    if input_stdin {
-        if let Ok(s) = serde_json::from_str::<Scenario>(&scenario_str) {
-            Ok(s)
-        } else if let Ok(s) = toml::from_str::<Scenario>(&scenario_str) {
-            Ok(s)
+        let document = match &format {
+            Some(format) => format.parse(&contents)?,
+            // syntax errors are reported for the format the input most likely is
+            None if contents.trim_start().starts_with('{') => Format::Json
+                .parse(&contents)
+                .context("Can't parse STDIN as JSON")?,
+            None => Format::Json
+                .parse(&contents)
+                .or_else(|_| Format::Toml.parse(&contents))
+                .context("Can't parse STDIN as either JSON or TOML")?,
+        };
+
+        Ok(Self {
+            input: input.to_owned(),
+            format,
+            contents,
+            document,
+        })
+    }
+
+    fn base_dir(&self) -> &Path {
+        if self.format.is_none() {
+            Path::new(".")
         } else {
-            Err(anyhow!(
-                "Can't parse STDIN as either JSON or TOML. \
-                 You can pass the input as a file for a more specific error."
-            ))
+            Path::new(&self.input).parent().unwrap_or(Path::new("."))
         }
-    } else if input.to_lowercase().ends_with(".json") {
-        serde_json::from_str(&scenario_str).context("Error parsing the scenario as JSON")
-    } else if input.to_lowercase().ends_with(".toml") {
-        toml::from_str(&scenario_str).context("Error parsing the scenario as TOML")
-    } else {
-        Err(anyhow!(
-            "The scenario file name extension must be either .toml or .json"
-        ))
+    }
+
+    fn sweep_matrix(&self) -> anyhow::Result<serde_json::Map<String, serde_json::Value>> {
+        template::sweep_matrix(&self.document, self.base_dir())
+            .context("Error reading the sweep section")
+    }
+
+    fn referenced_vars(&self) -> anyhow::Result<BTreeSet<String>> {
+        template::referenced_vars(&self.document, self.base_dir())
+            .context("Error reading the scenario variables")
+    }
+
+    pub fn scenario(
+        &self,
+        var_overrides: &[(String, serde_json::Value)],
+    ) -> anyhow::Result<Scenario> {
+        if !template::is_template(&self.document) && var_overrides.is_empty() {
+            // deserialising from the original string keeps line numbers in error messages
+            return match self.format {
+                Some(Format::Json) => deserialize::scenario(
+                    &mut serde_json::Deserializer::from_str(&self.contents),
+                    &self.document,
+                )
+                .context("Error parsing the scenario as JSON"),
+                Some(Format::Toml) => {
+                    deserialize::scenario(toml::Deserializer::new(&self.contents), &self.document)
+                        .context("Error parsing the scenario as TOML")
+                }
+                None => deserialize::scenario(self.document.to_owned(), &self.document)
+                    .context("Error parsing the scenario"),
+            };
+        }
+
+        let document =
+            template::preprocess(self.document.to_owned(), self.base_dir(), var_overrides)
+                .context("Error preprocessing the scenario")?;
+        deserialize::scenario(document.to_owned(), &document)
+            .context("Error parsing the preprocessed scenario")
     }
 }
 
 fn get_progressbar(scenario: &Scenario) -> ProgressBar {
     let (total_min, total_max) = scenario.total_duration();
     let total_avg = (total_max + total_min) / 2;
-    let per_repeat_avg = total_avg / scenario.repeats.into();
-
-    let repeats = u64::from(scenario.repeats);
+    // warmups take just as long
+    let repeats = u64::from(scenario.warmup) + u64::from(scenario.repeats);
+    let per_repeat_avg = total_avg
+        / u32::try_from(repeats).expect("Number of repeats and warmups must fit into u32");
 
     let progress = if repeats > 1 {
         ProgressBar::new(repeats)
@@ -114,42 +234,334 @@ fn get_progressbar(scenario: &Scenario) -> ProgressBar {
     progress
 }
 
+/// Progress of `--until-ci` runs: the bar shows repeats towards the maximum, and the message
+/// shows the current width of the median's confidence interval
+fn get_ci_progressbar(warmup: u16, max_repeats: u16, target_ms: f64) -> ProgressBar {
+    let progress = ProgressBar::new(u64::from(warmup) + u64::from(max_repeats));
+    progress.set_style(
+        ProgressStyle::with_template(&format!(
+            "{{prefix:.bold}}  [ {{bar:40.green/dim}} ] {{pos:>3}}/{{len:3}} \
+             [CI width: {{msg}} / {target_ms:.2} ms]"
+        ))
+        .expect("Progress bar template must be correct")
+        .progress_chars("##-"),
+    );
+    progress.set_prefix("Running the scenario…");
+    progress.set_message("-");
+
+    progress
+}
+
+/// Runs a single repeat of the scenario (ignoring its "repeats"), including the revert
+/// and the delay after it, so that repeats of different scenarios can be mixed
+pub async fn run_single_repeat(device: &Device, scenario: &Scenario) -> anyhow::Result<Recording> {
+    let single = Scenario {
+        repeats: 1,
+        warmup: 0,
+        ..scenario.clone()
+    };
+    // the stream only ends after the revert and the delay, so it must be drained
+    // before the next repeat can start
+    let mut recordings = device
+        .run_scenario(single)
+        .await
+        .context("Scenario validation error")?
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    recordings
+        .pop()
+        .ok_or_else(|| anyhow!("The device didn't return a recording"))
+}
+
+/// Prints scenario lints as warnings, `name` is used to tell scenarios apart
+/// when there are several of them
+pub fn print_lints(scenario: &Scenario, name: Option<&str>) {
+    for lint in scenario.lints() {
+        match name {
+            Some(name) => eprintln!("{} ({name}): {lint}", style("Warning").yellow()),
+            None => eprintln!("{}: {lint}", style("Warning").yellow()),
+        }
+    }
+}
+
+fn print_response(response: &Option<ResponseStats>) {
+    let Some(ResponseStats {
+        n_samples,
+        n_rising,
+        transition_median,
+        transition_mean,
+        transition_max,
+        overshoot_mean,
+        overshoot_max,
+        undershoot_mean,
+    }) = *response
+    else {
+        return;
+    };
+
+    let direction = match n_rising {
+        0 => "falling",
+        n if n == n_samples => "rising",
+        _ => "mixed",
+    };
+    eprintln!(
+        "  Transition, 10–90% ({}): {} ms median, {} ms mean, {} ms max",
+        style(direction).dim(),
+        style(format!("{transition_median:.01}")).green(),
+        style(format!("{transition_mean:.01}")).green(),
+        style(format!("{transition_max:.01}")).magenta(),
+    );
+    eprintln!(
+        "  Overshoot:             {}% mean, {}% max, {}% undershoot",
+        style(format!("{overshoot_mean:>6.01}")).green(),
+        style(format!("{overshoot_max:.01}")).magenta(),
+        style(format!("{undershoot_mean:.01}")).green(),
+    );
+}
+
+/// Only printed when repeats have several transitions, e.g. while scrolling
+fn print_transitions(transitions: &Option<TransitionStats>) {
+    let Some(TransitionStats {
+        n_transitions,
+        mean_per_repeat,
+        interval_median,
+        interval_mean,
+        interval_stddev,
+        interval_min,
+        interval_max,
+    }) = *transitions
+    else {
+        return;
+    };
+    if mean_per_repeat <= 1.0 {
+        return;
+    }
+
+    eprintln!(
+        "  Transitions:           {} ({:.01} per repeat)",
+        style(format!("{n_transitions:<6}")).dim(),
+        mean_per_repeat
+    );
+    eprintln!(
+        "  Interval ({} ± {}):   {} ± {} ms, median {} ms",
+        style("mean").green().bold(),
+        style("σ").green(),
+        style(format!("{interval_mean:>6.01}")).green().bold(),
+        style(format!("{interval_stddev:.01}")).green(),
+        style(format!("{interval_median:.01}")).green().dim(),
+    );
+    eprintln!(
+        "  Interval range:        {} … {} ms",
+        style(format!("{interval_min:>6.01}")).cyan(),
+        style(format!("{interval_max:.01}")).magenta(),
+    );
+}
+
+fn print_failed(n_failed: usize) {
+    if n_failed > 0 {
+        eprintln!(
+            "  {}: {} measurements failed, statistics can be skewed",
+            style("Warning").yellow(),
+            n_failed
+        )
+    }
+}
+
+pub fn print_stats(stats: &FinalStats) {
+    match *stats {
+        FinalStats::NoRuns => {}
+        FinalStats::NoSuccesses { .. } => {
+            eprintln!(
+                "{}, {}",
+                style("Scenario complete").bold(),
+                style("no succesful measurements").bold().yellow()
+            );
+        }
+        FinalStats::SingleMeasurement {
+            latency,
+            n_failed,
+            ref response,
+            ref transitions,
+        } => {
+            eprintln!(
+                "{}, measured latency is {}",
+                style("Scenario complete").bold(),
+                style(format!("{latency:.01}ms")).green().bold()
+            );
+            print_failed(n_failed);
+            print_response(response);
+            print_transitions(transitions);
+        }
+        FinalStats::MultipleMeasurements {
+            n_samples,
+            n_failed,
+            mean,
+            stddev,
+            median,
+            max,
+            min,
+            percentiles,
+            mad,
+            mean_ci,
+            median_ci,
+            ref response,
+            ref transitions,
+        } => {
+            eprintln!("{}, results:", style("Scenario complete").bold(),);
+            print_failed(n_failed);
+            eprintln!(
+                "  Samples:                 {}",
+                style(format!("{n_samples:<6}")).dim()
+            );
+            eprintln!(
+                "  Latency ({} ± {}):    {} ± {} ms, 95% CI {} … {}",
+                style("mean").green().bold(),
+                style("σ").green(),
+                style(format!("{mean:>6.01}")).green().bold(),
+                style(format!("{stddev:<6.01}")).green(),
+                style(format!("{:.01}", mean_ci.low)).green(),
+                style(format!("{:.01}", mean_ci.high)).green(),
+            );
+            eprintln!(
+                "  Median (± MAD):        {} ± {} ms, 95% CI {} … {}",
+                style(format!("{median:>6.01}")).green().dim(),
+                style(format!("{mad:<6.01}")).dim(),
+                style(format!("{:.01}", median_ci.low)).dim(),
+                style(format!("{:.01}", median_ci.high)).dim(),
+            );
+            eprintln!(
+                "  Percentiles (p5 p25 p75 p90 p95 p99): {:.01} {:.01} {:.01} {:.01} {} {} ms",
+                percentiles.p5,
+                percentiles.p25,
+                percentiles.p75,
+                percentiles.p90,
+                style(format!("{:.01}", percentiles.p95)).yellow(),
+                style(format!("{:.01}", percentiles.p99)).yellow(),
+            );
+            eprintln!(
+                "  Range ({} … {}):     {} … {} ms",
+                style("min").cyan(),
+                style("max").magenta(),
+                style(format!("{min:>6.01}")).cyan(),
+                style(format!("{max:<6.01}")).magenta(),
+            );
+            print_response(response);
+            print_transitions(transitions);
+        }
+    }
+}
+
 impl Args {
-    async fn prepare(&self) -> anyhow::Result<(Scenario, Vec<FileOutput>)> {
-        let mut scenario = read_scenario(self.input.as_str()).await?;
+    fn scenario(
+        &self,
+        source: &ScenarioSource,
+        sweep_vars: &[(String, serde_json::Value)],
+    ) -> anyhow::Result<Scenario> {
+        // explicitly set variables take precedence over the swept ones
+        let var_overrides = sweep_vars
+            .iter()
+            .chain(self.vars.iter())
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut scenario = source.scenario(&var_overrides)?;
         if let Some(repeats_override) = self.repeats {
             scenario.repeats = repeats_override;
         }
+        if let Some(warmup_override) = self.warmup {
+            scenario.warmup = warmup_override;
+        }
+        // the scenario is run until stopped, "repeats" is the upper bound
+        if let Some(until_ci) = self.until_ci {
+            if !(until_ci.is_finite() && until_ci > 0.0) {
+                return Err(anyhow!(
+                    "--until-ci must be a positive number of milliseconds"
+                ));
+            }
+            if self.min_repeats > self.max_repeats {
+                return Err(anyhow!("--min-repeats can't be larger than --max-repeats"));
+            }
+            scenario.repeats = self.max_repeats;
+        }
+        if let Some(detector_override) = &self.detector {
+            scenario.detector = detector_override.clone();
+        }
+        if let Some(interpolation_override) = self.interpolation {
+            scenario.interpolation = interpolation_override;
+        }
 
-        let run_name = self
-            .name
-            .to_owned()
-            .unwrap_or(chrono::Local::now().format("%Y_%m_%d-%H_%M_%S").to_string());
+        Ok(scenario)
+    }
 
-        let output_json = if let Some(ref dir) = self.output_json_dir {
-            let output = FileOutput::prepare(FileOutputKind::Json, dir, &run_name)
-                .await
-                .context("Error while preparing JSON output directory")?;
-            Some(output)
-        } else {
-            None
+    /// Variables to sweep over and their values, empty if this isn't a sweep
+    fn sweep_matrix(
+        &self,
+        source: &ScenarioSource,
+    ) -> anyhow::Result<Vec<(String, Vec<serde_json::Value>)>> {
+        let mut matrix = source.sweep_matrix()?;
+        for (name, values) in &self.sweep {
+            matrix.insert(name.to_owned(), serde_json::Value::Array(values.to_owned()));
+        }
+        // a variable that's explicitly set is not swept
+        for (name, _) in &self.vars {
+            matrix.remove(name);
+        }
+        // otherwise every sweep point would be the same scenario
+        if !matrix.is_empty() {
+            let used = source.referenced_vars()?;
+            if let Some(name) = matrix.keys().find(|name| !used.contains(*name)) {
+                return Err(anyhow!(
+                    "Swept variable \"{name}\" isn't used by the scenario"
+                ));
+            }
+        }
+
+        Ok(matrix
+            .into_iter()
+            .map(|(name, values)| match values {
+                serde_json::Value::Array(values) => (name, values),
+                _ => unreachable!("sweep values are validated to be lists"),
+            })
+            .collect())
+    }
+
+    fn print_resolved(&self, scenario: Scenario) -> anyhow::Result<()> {
+        scenario.validate().context("Scenario validation error")?;
+        print_lints(&scenario, None);
+
+        let revert = scenario
+            .revert_steps()?
+            .map(|steps| Revert::Steps(steps.into_owned()));
+        let resolved = Scenario {
+            revert,
+            ..scenario.clone()
         };
 
-        let output_csv = if let Some(ref dir) = self.output_csv_dir {
-            let output = FileOutput::prepare(FileOutputKind::Csv, dir, &run_name)
-                .await
-                .context("Error while preparing CSV output directory")?;
-            Some(output)
+        let serialised = if self.input.to_lowercase().ends_with(".json") {
+            serde_json::to_string_pretty(&resolved).context("Error serialising the scenario")?
         } else {
-            None
+            toml::to_string(&resolved).context("Error serialising the scenario")?
         };
+        println!("{serialised}");
 
-        let file_outputs = vec![output_json, output_csv]
-            .into_iter()
-            .flatten()
-            .collect();
+        let (total_min, total_max) = scenario.total_duration();
+        eprintln!(
+            "{}, estimated duration is {:#} to {:#}",
+            style("Scenario is valid").bold(),
+            HumanDuration(total_min),
+            HumanDuration(total_max)
+        );
+
+        Ok(())
+    }
 
-        Ok((scenario, file_outputs))
+    async fn prepare_outputs(&self) -> anyhow::Result<Vec<FileOutput>> {
+        file_output::prepare_outputs(
+            self.name.as_deref(),
+            self.output_json_dir.as_deref(),
+            self.output_csv_dir.as_deref(),
+        )
+        .await
     }
 
     fn output_init(&self, progress: &ProgressBar) {
@@ -164,9 +576,15 @@ impl Args {
         idx: usize,
         processed: &ProcessedRecording,
     ) -> anyhow::Result<()> {
+        let is_warmup = idx < usize::from(scenario.warmup);
         if let Some(changepoint_us) = processed.changepoint_us {
             let changepoint = f64::from(changepoint_us) / 1000f64;
-            progress.suspend(|| println!("{changepoint:.1}"));
+            // warmups don't go to STDOUT, which is only for measured latencies
+            if is_warmup {
+                progress.suspend(|| eprintln!("{changepoint:.1} (warmup)"));
+            } else {
+                progress.suspend(|| println!("{changepoint:.1}"));
+            }
         } else {
             progress.suspend(|| {
                 eprintln!("No reaction to the input");
@@ -185,10 +603,82 @@ impl Args {
         Ok(())
     }
 
-    pub async fn run(self, device: &Device) -> anyhow::Result<()> {
-        let (scenario, file_outputs) = self.prepare().await?;
+    /// Repeats the scenario until the median latency is known well enough, `scenario.repeats`
+    /// is the maximum
+    async fn run_scenario_until_ci(
+        &self,
+        device: &Device,
+        scenario: &Scenario,
+        file_outputs: &[FileOutput],
+        target_ms: f64,
+    ) -> anyhow::Result<Vec<Measurement>> {
+        let progress = get_ci_progressbar(scenario.warmup, scenario.repeats, target_ms);
+
+        let mut stream = device
+            .run_scenario_open_ended(scenario.clone())
+            .await
+            .context("Scenario validation error")?;
+
+        self.output_init(&progress);
+
+        let mut measurements = vec![];
+        let mut millis = vec![];
+        let mut ci_width = None;
+        let mut idx = 0;
+        while let Some(recording) = stream.try_next().await? {
+            let processed =
+                process_recording(recording, &scenario.detector, scenario.interpolation);
+            self.output_step(scenario, &progress, file_outputs, idx, &processed)
+                .await?;
+            idx += 1;
+            if idx <= usize::from(scenario.warmup) {
+                continue;
+            }
+            measurements.push(processed.measurement());
+
+            if let Some(changepoint_us) = processed.changepoint_us {
+                millis.push(f64::from(changepoint_us) / 1000f64);
+                ci_width = median_ci(&millis).map(|ci| ci.high - ci.low);
+                if let Some(width) = ci_width {
+                    progress.set_message(format!("{width:.2}"));
+                }
+            }
+
+            let reached_target = ci_width.is_some_and(|width| width < target_ms);
+            if (reached_target && measurements.len() >= usize::from(self.min_repeats))
+                || measurements.len() >= usize::from(scenario.repeats)
+            {
+                break;
+            }
+        }
+
+        progress.finish_and_clear();
+        if !ci_width.is_some_and(|width| width < target_ms) {
+            eprintln!(
+                "{}: stopped after {} repeats, the confidence interval is still wider than {} ms",
+                style("Warning").yellow(),
+                measurements.len(),
+                target_ms
+            );
+        }
+
+        Ok(measurements)
+    }
 
-        let progress = get_progressbar(&scenario);
+    /// Runs the scenario on the device and returns measurements of all repeats
+    async fn run_scenario(
+        &self,
+        device: &Device,
+        scenario: &Scenario,
+        file_outputs: &[FileOutput],
+    ) -> anyhow::Result<Vec<Measurement>> {
+        if let Some(target_ms) = self.until_ci {
+            return self
+                .run_scenario_until_ci(device, scenario, file_outputs, target_ms)
+                .await;
+        }
+
+        let progress = get_progressbar(scenario);
 
         let mut counter = 0usize;
         let mut stream = device
@@ -204,74 +694,160 @@ impl Args {
 
         self.output_init(&progress);
 
-        let mut changepoints = Vec::with_capacity(usize::from(scenario.repeats));
+        let mut measurements = Vec::with_capacity(usize::from(scenario.repeats));
 
         while let Some((idx, recording)) = stream.try_next().await? {
-            let processed = process_recording(recording);
-            self.output_step(&scenario, &progress, &file_outputs, idx, &processed)
+            let processed =
+                process_recording(recording, &scenario.detector, scenario.interpolation);
+            self.output_step(scenario, &progress, file_outputs, idx, &processed)
                 .await?;
-            changepoints.push(processed.changepoint_us);
+            if idx >= usize::from(scenario.warmup) {
+                measurements.push(processed.measurement());
+            }
         }
 
         progress.finish_and_clear();
 
-        match process_changepoints(&changepoints) {
-            FinalStats::NoRuns => {}
-            FinalStats::NoSuccesses => {
-                eprintln!(
-                    "{}, {}",
-                    style("Scenario complete").bold(),
-                    style("no succesful measurements").bold().yellow()
-                );
-            }
-            FinalStats::SingleMeasurement { latency } => {
-                eprintln!(
-                    "{}, measured latency is {}",
-                    style("Scenario complete").bold(),
-                    style(format!("{latency:.01}ms")).green().bold()
-                );
+        Ok(measurements)
+    }
+
+    /// Scenarios of all sweep points. They are all validated before anything runs, so that
+    /// a typo doesn't interrupt a long sweep halfway through
+    fn sweep_points(
+        &self,
+        source: &ScenarioSource,
+        matrix: &[(String, Vec<serde_json::Value>)],
+    ) -> anyhow::Result<Vec<(SweepPoint, Scenario)>> {
+        let points = sweep::combinations(matrix);
+        sweep::check_names(&points)?;
+        points
+            .into_iter()
+            .map(|point| {
+                let scenario = self
+                    .scenario(source, &point.vars)
+                    .and_then(|s| s.validate().map(|_| s).map_err(anyhow::Error::from))
+                    .with_context(|| format!("Error in sweep point {}", point.name()))?;
+                print_lints(&scenario, Some(&point.name()));
+                Ok((point, scenario))
+            })
+            .collect()
+    }
+
+    async fn run_sweep(
+        &self,
+        device: &Device,
+        source: &ScenarioSource,
+        matrix: Vec<(String, Vec<serde_json::Value>)>,
+    ) -> anyhow::Result<()> {
+        let params = matrix
+            .iter()
+            .map(|(name, _)| name.to_owned())
+            .collect::<Vec<_>>();
+        let points = self.sweep_points(source, &matrix)?;
+
+        let file_outputs = self.prepare_outputs().await?;
+
+        let mut rows = Vec::with_capacity(points.len());
+        for (i, (point, scenario)) in points.iter().enumerate() {
+            let name = point.name();
+            eprintln!(
+                "{} {} ({}/{})",
+                style("Sweep point").bold(),
+                style(&name).cyan(),
+                i + 1,
+                points.len()
+            );
+
+            let mut sub_run_outputs = Vec::with_capacity(file_outputs.len());
+            for file_output in &file_outputs {
+                let output = file_output
+                    .prepare_sub_run(&name)
+                    .await
+                    .context("Error while preparing sweep point output directory")?;
+                sub_run_outputs.push(output);
             }
-            FinalStats::MultipleMeasurements {
-                has_missing,
-                n_samples,
-                mean,
-                stddev,
-                median,
-                max,
-                min,
-            } => {
-                eprintln!("{}, results:", style("Scenario complete").bold(),);
-                if has_missing {
-                    eprintln!(
-                        "  {}: some measurements failed, statistics can be skewed",
-                        style("Warning").yellow()
-                    )
-                }
-                eprintln!(
-                    "  Samples:                 {}",
-                    style(format!("{n_samples:<6}")).dim()
-                );
-                eprintln!(
-                    "  Latency ({} ± {}):    {} ± {} ms",
-                    style("mean").green().bold(),
-                    style("σ").green(),
-                    style(format!("{mean:>6.01}")).green().bold(),
-                    style(format!("{stddev:<6.01}")).green(),
-                );
-                eprintln!(
-                    "  Median:                {} ms",
-                    style(format!("{median:>6.01}")).green().dim(),
-                );
-                eprintln!(
-                    "  Range ({} … {}):     {} … {} ms",
-                    style("min").cyan(),
-                    style("max").magenta(),
-                    style(format!("{min:>6.01}")).cyan(),
-                    style(format!("{max:<6.01}")).magenta(),
-                );
+
+            let measurements = self
+                .run_scenario(device, scenario, &sub_run_outputs)
+                .await?;
+            let stats = process_measurements(&measurements);
+            print_stats(&stats);
+
+            rows.push(SummaryRow::new(name, point.params(), &stats));
+            for file_output in &file_outputs {
+                file_output
+                    .output_summary("sweep", &params, &rows)
+                    .await
+                    .context("Error while writing the sweep summary")?;
             }
         }
 
+        eprintln!(
+            "{}, results (in milliseconds):",
+            style("Sweep complete").bold()
+        );
+        summary::print_summary(&params, &rows);
+
+        Ok(())
+    }
+
+    /// Validates the scenario (or all sweep points) without running it,
+    /// doesn't need a connected device
+    pub async fn dry_run(self) -> anyhow::Result<()> {
+        let source = ScenarioSource::read(&self.input).await?;
+
+        let matrix = self.sweep_matrix(&source)?;
+        if matrix.is_empty() {
+            let scenario = self.scenario(&source, &[])?;
+            return self.print_resolved(scenario);
+        }
+
+        let points = self.sweep_points(&source, &matrix)?;
+        let (mut total_min, mut total_max) = Default::default();
+        for (point, scenario) in &points {
+            let (min, max) = scenario.total_duration();
+            total_min += min;
+            total_max += max;
+            println!("{}", point.name());
+        }
+        eprintln!(
+            "{}, {} sweep points, estimated duration is {:#} to {:#}",
+            style("Scenarios are valid").bold(),
+            points.len(),
+            HumanDuration(total_min),
+            HumanDuration(total_max)
+        );
+
+        Ok(())
+    }
+
+    pub async fn run(self, device: &Device) -> anyhow::Result<()> {
+        let source = ScenarioSource::read(&self.input).await?;
+
+        let matrix = self.sweep_matrix(&source)?;
+        if !matrix.is_empty() {
+            return self.run_sweep(device, &source, matrix).await;
+        }
+
+        let scenario = self.scenario(&source, &[])?;
+        print_lints(&scenario, None);
+        let file_outputs = self.prepare_outputs().await?;
+        let measurements = self.run_scenario(device, &scenario, &file_outputs).await?;
+        let stats = process_measurements(&measurements);
+        print_stats(&stats);
+
+        let rows = [SummaryRow::new(
+            self.input.to_owned(),
+            Default::default(),
+            &stats,
+        )];
+        for file_output in &file_outputs {
+            file_output
+                .output_summary("summary", &[], &rows)
+                .await
+                .context("Error while writing the summary")?;
+        }
+
         Ok(())
     }
 }
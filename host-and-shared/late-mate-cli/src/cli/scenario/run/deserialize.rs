@@ -0,0 +1,188 @@
+//! Deserialising scenarios with errors that point at the problematic value,
+//! e.g. "test[3].pressed_keys[0]"
+
+use anyhow::anyhow;
+use late_mate_device::hid::layout::KeyboardLayout;
+use late_mate_device::hid::{KeyboardKey, KeyboardReport, MouseButton, MouseReport};
+use late_mate_device::scenario::Scenario;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+use serde_path_to_error::{Path, Segment};
+use std::sync::OnceLock;
+
+/// Tags of ScenarioStep and HidReport variants, taken from the schema so that they can't get
+/// out of sync with the enums
+fn step_types() -> &'static [String] {
+    static STEP_TYPES: OnceLock<Vec<String>> = OnceLock::new();
+    STEP_TYPES.get_or_init(schema_step_types)
+}
+
+fn schema_step_types() -> Vec<String> {
+    let schema = serde_json::to_value(Scenario::json_schema())
+        .expect("The scenario schema must be serialisable");
+    let variants = |name: &str| {
+        schema["definitions"][name]["oneOf"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    variants("ScenarioStep")
+        .into_iter()
+        .flat_map(
+            |variant| match variant.get("$ref").and_then(Value::as_str) {
+                Some(reference) => variants(reference.trim_start_matches("#/definitions/")),
+                None => vec![variant],
+            },
+        )
+        .filter_map(|variant| {
+            variant["properties"]["type"]["enum"][0]
+                .as_str()
+                .map(str::to_owned)
+        })
+        .collect()
+}
+
+/// `document` is the same scenario as a JSON value, it's used to find the exact location
+/// of errors inside steps
+pub fn scenario<'de, D>(deserializer: D, document: &Value) -> anyhow::Result<Scenario>
+where
+    D: serde::Deserializer<'de>,
+    D::Error: std::fmt::Display,
+{
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        if let Some((path, message)) = step_error(e.path(), document) {
+            return anyhow!("Error at {path}: {message}");
+        }
+        match e.path().to_string().as_str() {
+            "." => anyhow!("{}", e.inner()),
+            path => anyhow!("Error at {path}: {}", e.inner()),
+        }
+    })
+}
+
+/// Steps are internally tagged, so serde buffers them and loses the location of errors
+/// inside them. This re-checks the step that failed field by field
+fn step_error(path: &Path, document: &Value) -> Option<(String, String)> {
+    let mut segments = path.iter();
+    let Some(Segment::Map { key: section }) = segments.next() else {
+        return None;
+    };
+    let steps = document.get(section)?.as_array()?;
+    let (index, (suffix, message)) = match segments.next() {
+        Some(Segment::Seq { index }) => (*index, check_step(steps.get(*index)?.as_object()?)?),
+        // untagged enums like Revert don't report the index, so the first bad step is used
+        None => steps
+            .iter()
+            .enumerate()
+            .find_map(|(index, step)| Some((index, check_step(step.as_object()?)?)))?,
+        Some(_) => return None,
+    };
+
+    Some((format!("{section}[{index}]{suffix}"), message))
+}
+
+fn check_step(step: &Map<String, Value>) -> Option<(String, String)> {
+    let step_type = match step.get("type") {
+        None => return Some((String::new(), "missing step \"type\"".to_owned())),
+        Some(Value::String(step_type)) => step_type.as_str(),
+        Some(_) => return Some((".type".to_owned(), "step type must be a string".to_owned())),
+    };
+    let step_types = step_types();
+    if !step_types.iter().any(|t| t == step_type) {
+        return Some((
+            ".type".to_owned(),
+            format!(
+                "unknown step type \"{step_type}\", expected one of: {}",
+                step_types.join(", ")
+            ),
+        ));
+    }
+
+    match step_type {
+        "keyboard" => check_report::<KeyboardReport>(step),
+        "mouse" => check_report::<MouseReport>(step),
+        _ => step.iter().find_map(|(field, value)| {
+            let result = match field.as_str() {
+                "key" => check::<KeyboardKey>(value),
+                "button" => check::<MouseButton>(value),
+                "layout" => check::<KeyboardLayout>(value),
+                "ms" => check::<u16>(value),
+                "text" => check::<String>(value),
+                _ => Ok(()),
+            };
+            result.err().map(|message| (format!(".{field}"), message))
+        }),
+    }
+}
+
+fn check<T: DeserializeOwned>(value: &Value) -> Result<(), String> {
+    serde_json::from_value::<T>(value.to_owned())
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+fn check_report<T: DeserializeOwned>(step: &Map<String, Value>) -> Option<(String, String)> {
+    let mut report = step.to_owned();
+    report.remove("type");
+
+    let error = serde_path_to_error::deserialize::<_, T>(Value::Object(report)).err()?;
+    let suffix = match error.path().to_string().as_str() {
+        "." => String::new(),
+        path => format!(".{path}"),
+    };
+    Some((suffix, error.inner().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn error(document: Value) -> String {
+        scenario(document.to_owned(), &document)
+            .unwrap_err()
+            .to_string()
+    }
+
+    #[test]
+    fn test_step_types() {
+        assert_eq!(
+            step_types(),
+            [
+                "wait",
+                "start_timing",
+                "key_down",
+                "key_up",
+                "button_down",
+                "button_up",
+                "type_text",
+                "mouse",
+                "keyboard",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_paths() {
+        let bad_key = json!({
+            "test": [
+                { "type": "start_timing" },
+                { "type": "keyboard", "pressed_keys": ["a", "nope"] },
+            ],
+        });
+        assert!(error(bad_key).starts_with("Error at test[1].pressed_keys[1]: "));
+
+        let bad_field = json!({ "test": [{ "type": "mouse", "z": 1 }] });
+        assert!(error(bad_field).starts_with("Error at test[0].z: unknown field `z`"));
+
+        let bad_type = json!({ "revert": [{ "type": "press" }] });
+        assert!(error(bad_type).starts_with("Error at revert[0].type: unknown step type"));
+
+        let bad_wait = json!({ "test": [{ "type": "wait", "ms": -1 }] });
+        assert!(error(bad_wait).starts_with("Error at test[0].ms: "));
+
+        let bad_repeats = json!({ "repeats": "many" });
+        assert!(error(bad_repeats).starts_with("Error at repeats: "));
+    }
+}
@@ -1,20 +1,54 @@
-use crate::statistics::ProcessedRecording;
+use super::summary::SummaryRow;
+use crate::statistics::{ProcessedRecording, Response, Transition};
 use anyhow::{anyhow, Context};
 use late_mate_device::hid::HidReport;
-use late_mate_device::scenario::{Event, Moment, Recording, Scenario};
+use late_mate_device::scenario::{Detector, Event, Interpolation, Moment, Recording, Scenario};
 use std::path::{Path, PathBuf};
-use tokio::fs::{create_dir, File, OpenOptions};
+use tokio::fs::{create_dir, read_dir, File, OpenOptions};
 use tokio::io::AsyncWriteExt;
 
 #[derive(Debug, serde::Serialize)]
 struct JsonRunFile<'a> {
     run_name: &'a str,
     idx: usize,
+    /// Warmups are excluded from statistics
+    warmup: bool,
     changepoint_microsecond: Option<u32>,
+    /// The detected changepoint before sub-sample interpolation
+    changepoint_sample_microsecond: Option<u32>,
+    changepoint_estimate_microsecond: Option<f64>,
+    /// 95% interval of the estimate
+    changepoint_interval_microsecond: Option<(f64, f64)>,
+    /// Pixel response after the changepoint
+    response: Option<Response>,
+    /// All level transitions of the light level
+    transitions: &'a [Transition],
+    /// Saved for re-analysis with the same settings
+    detector: &'a Detector,
+    interpolation: Interpolation,
     #[serde(flatten)]
     recording: &'a Recording,
 }
 
+/// A recording saved by `output_run_json`, other fields are recomputed when it's read back
+#[derive(Debug, serde::Deserialize)]
+pub struct SavedRun {
+    pub idx: usize,
+    /// Missing in runs saved before warmups were marked
+    #[serde(default)]
+    pub warmup: bool,
+    /// As detected when the run was saved
+    #[serde(default)]
+    pub changepoint_microsecond: Option<u32>,
+    /// Missing in runs saved before the analysis settings were saved
+    #[serde(default)]
+    pub detector: Option<Detector>,
+    #[serde(default)]
+    pub interpolation: Option<Interpolation>,
+    #[serde(flatten)]
+    pub recording: Recording,
+}
+
 #[derive(Debug, serde::Serialize)]
 struct CsvTimelineFileRow<'a> {
     pub microsecond: u32,
@@ -22,12 +56,22 @@ struct CsvTimelineFileRow<'a> {
     pub usb_event: Option<&'a str>,
 }
 
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
 struct CsvChangepointFileRow {
     pub run_idx: usize,
+    #[serde(default)]
+    pub warmup: bool,
     pub changepoint_microsecond: Option<u32>,
+    pub changepoint_sample_microsecond: Option<u32>,
+    pub changepoint_estimate_microsecond: Option<f64>,
+    pub changepoint_low_microsecond: Option<f64>,
+    pub changepoint_high_microsecond: Option<f64>,
+    pub transition_microsecond: Option<f64>,
+    pub overshoot_percent: Option<f64>,
+    pub undershoot_percent: Option<f64>,
 }
 
+#[derive(Debug, Clone, Copy)]
 pub enum FileOutputKind {
     Csv,
     Json,
@@ -39,6 +83,105 @@ pub struct FileOutput {
     run_dir: PathBuf,
 }
 
+/// Prepares a run directory in every requested output directory.
+/// If the run name isn't provided, current date and time are used
+pub async fn prepare_outputs(
+    run_name: Option<&str>,
+    output_json_dir: Option<&Path>,
+    output_csv_dir: Option<&Path>,
+) -> anyhow::Result<Vec<FileOutput>> {
+    let run_name = run_name
+        .map(str::to_owned)
+        .unwrap_or(chrono::Local::now().format("%Y_%m_%d-%H_%M_%S").to_string());
+
+    let mut outputs = vec![];
+    if let Some(dir) = output_json_dir {
+        let output = FileOutput::prepare(FileOutputKind::Json, dir, &run_name)
+            .await
+            .context("Error while preparing JSON output directory")?;
+        outputs.push(output);
+    }
+    if let Some(dir) = output_csv_dir {
+        let output = FileOutput::prepare(FileOutputKind::Csv, dir, &run_name)
+            .await
+            .context("Error while preparing CSV output directory")?;
+        outputs.push(output);
+    }
+
+    Ok(outputs)
+}
+
+/// Reads recordings of a JSON run directory ordered by their index, summaries (files starting
+/// with "_") are skipped. Subdirectories aren't read, they are separate sub-runs
+pub async fn read_json_runs(run_dir: &Path) -> anyhow::Result<Vec<SavedRun>> {
+    let run_dir_s = run_dir.to_string_lossy();
+    let mut entries = read_dir(run_dir)
+        .await
+        .with_context(|| format!("Error reading \"{run_dir_s}\" as a directory"))?;
+
+    let mut runs = vec![];
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .with_context(|| format!("Error reading \"{run_dir_s}\""))?
+    {
+        let path = entry.path();
+        let is_recording = path.extension().is_some_and(|e| e == "json")
+            && !entry.file_name().to_string_lossy().starts_with('_');
+        if !is_recording || !path.is_file() {
+            continue;
+        }
+
+        let path_s = path.to_string_lossy();
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("Error reading \"{path_s}\""))?;
+        let deserializer = &mut serde_json::Deserializer::from_str(&contents);
+        let run: SavedRun = serde_path_to_error::deserialize(deserializer)
+            .with_context(|| format!("Error parsing \"{path_s}\" as a saved recording"))?;
+        runs.push(run);
+    }
+    runs.sort_by_key(|run| run.idx);
+
+    Ok(runs)
+}
+
+/// Changepoints of a saved run without warmups, ordered by the run index. Read from
+/// `_changepoints.csv` of a CSV run directory or from recordings of a JSON one
+pub async fn read_changepoints(run_dir: &Path) -> anyhow::Result<Vec<Option<u32>>> {
+    let path = run_dir.join("_changepoints.csv");
+    if !path.is_file() {
+        let runs = read_json_runs(run_dir).await?;
+        if runs.is_empty() {
+            return Err(anyhow!(
+                "\"{}\" has neither _changepoints.csv nor saved recordings",
+                run_dir.to_string_lossy()
+            ));
+        }
+        return Ok(runs
+            .into_iter()
+            .filter(|run| !run.warmup)
+            .map(|run| run.changepoint_microsecond)
+            .collect());
+    }
+
+    let path_s = path.to_string_lossy();
+    let contents = tokio::fs::read(&path)
+        .await
+        .with_context(|| format!("Error reading \"{path_s}\""))?;
+    let mut rows = csv::Reader::from_reader(contents.as_slice())
+        .deserialize()
+        .collect::<Result<Vec<CsvChangepointFileRow>, _>>()
+        .with_context(|| format!("Error parsing \"{path_s}\""))?;
+    rows.sort_by_key(|row| row.run_idx);
+
+    Ok(rows
+        .into_iter()
+        .filter(|row| !row.warmup)
+        .map(|row| row.changepoint_microsecond)
+        .collect())
+}
+
 impl FileOutput {
     pub async fn prepare(
         kind: FileOutputKind,
@@ -91,9 +234,61 @@ impl FileOutput {
         })
     }
 
+    /// Output for a named sub-run stored in a subdirectory of this run, e.g. for sweeps
+    pub async fn prepare_sub_run(&self, name: &str) -> anyhow::Result<Self> {
+        Self::prepare(self.kind, &self.run_dir, name).await
+    }
+
+    /// (Re)writes a table of sub-run results as `_{name}.json`/`_{name}.csv`. It's meant to
+    /// be called after every sub-run so that partial results survive an interruption
+    pub async fn output_summary(
+        &self,
+        name: &str,
+        params: &[String],
+        rows: &[SummaryRow],
+    ) -> anyhow::Result<()> {
+        let (path, bytes) = match self.kind {
+            FileOutputKind::Json => {
+                let serialised =
+                    serde_json::to_string_pretty(rows).context("Error serialising the summary")?;
+                (
+                    self.run_dir.join(format!("_{name}.json")),
+                    serialised.into_bytes(),
+                )
+            }
+            FileOutputKind::Csv => {
+                let mut bytes = Vec::new();
+                {
+                    let mut csv_writer = csv::Writer::from_writer(&mut bytes);
+                    csv_writer
+                        .write_record(SummaryRow::csv_header(params))
+                        .context("Error serialising a CSV row")?;
+                    for row in rows {
+                        csv_writer
+                            .write_record(row.csv_record(params))
+                            .context("Error serialising a CSV row")?;
+                    }
+                    csv_writer.flush().context("CSV error")?;
+                }
+                (self.run_dir.join(format!("_{name}.csv")), bytes)
+            }
+        };
+
+        let mut file = File::create(&path)
+            .await
+            .with_context(|| format!("Error opening \"{}\"", path.to_string_lossy()))?;
+        file.write_all(bytes.as_slice())
+            .await
+            .context("Error writing the summary")?;
+
+        Ok(())
+    }
+
     async fn output_run_json(
         &self,
+        scenario: &Scenario,
         idx: usize,
+        warmup: bool,
         processed_recording: &ProcessedRecording,
         filename_base: &str,
     ) -> anyhow::Result<()> {
@@ -104,10 +299,19 @@ impl FileOutput {
             .await
             .with_context(|| format!("Error creating an output file at {path_s}"))?;
 
+        let changepoint = processed_recording.changepoint.as_ref();
         let record = JsonRunFile {
             run_name: &self.run_name,
             idx,
+            warmup,
             changepoint_microsecond: processed_recording.changepoint_us,
+            changepoint_sample_microsecond: changepoint.map(|c| c.sample_us),
+            changepoint_estimate_microsecond: changepoint.map(|c| c.estimate_us),
+            changepoint_interval_microsecond: changepoint.map(|c| (c.low_us, c.high_us)),
+            response: processed_recording.response,
+            transitions: &processed_recording.transitions,
+            detector: &scenario.detector,
+            interpolation: scenario.interpolation,
             recording: &processed_recording.recording,
         };
         let serialised =
@@ -122,6 +326,7 @@ impl FileOutput {
     async fn output_run_csv_changepoint(
         &self,
         idx: usize,
+        warmup: bool,
         processed_recording: &ProcessedRecording,
     ) -> anyhow::Result<()> {
         let path = self.run_dir.join("_changepoints.csv");
@@ -137,9 +342,19 @@ impl FileOutput {
         }
         .with_context(|| format!("Error opening \"{}\"", path.to_string_lossy()))?;
 
+        let changepoint = processed_recording.changepoint.as_ref();
+        let response = processed_recording.response;
         let row = CsvChangepointFileRow {
             run_idx: idx,
+            warmup,
             changepoint_microsecond: processed_recording.changepoint_us,
+            changepoint_sample_microsecond: changepoint.map(|c| c.sample_us),
+            changepoint_estimate_microsecond: changepoint.map(|c| c.estimate_us),
+            changepoint_low_microsecond: changepoint.map(|c| c.low_us),
+            changepoint_high_microsecond: changepoint.map(|c| c.high_us),
+            transition_microsecond: response.map(|r| r.transition_us),
+            overshoot_percent: response.map(|r| r.overshoot_percent),
+            undershoot_percent: response.map(|r| r.undershoot_percent),
         };
         let mut row_bytes = Vec::new();
         {
@@ -212,10 +427,11 @@ impl FileOutput {
     async fn output_run_csv(
         &self,
         idx: usize,
+        warmup: bool,
         processed_recording: &ProcessedRecording,
         filename_base: &str,
     ) -> anyhow::Result<()> {
-        self.output_run_csv_changepoint(idx, processed_recording)
+        self.output_run_csv_changepoint(idx, warmup, processed_recording)
             .await?;
         self.output_run_csv_timeline(processed_recording, filename_base)
             .await?;
@@ -223,23 +439,25 @@ impl FileOutput {
         Ok(())
     }
 
+    /// Indices count all recordings of the run, including the warmups that come first
     pub async fn output_run(
         &self,
         scenario: &Scenario,
         idx: usize,
         processed_recording: &ProcessedRecording,
     ) -> anyhow::Result<()> {
-        let width =
-            usize::try_from(scenario.repeats.ilog10() + 1).expect("File width must fit into usize");
+        let total = u32::from(scenario.warmup) + u32::from(scenario.repeats);
+        let width = usize::try_from(total.ilog10() + 1).expect("File width must fit into usize");
         let filename_base = format!("{idx:0width$}");
+        let warmup = idx < usize::from(scenario.warmup);
 
         match self.kind {
             FileOutputKind::Csv => {
-                self.output_run_csv(idx, processed_recording, &filename_base)
+                self.output_run_csv(idx, warmup, processed_recording, &filename_base)
                     .await
             }
             FileOutputKind::Json => {
-                self.output_run_json(idx, processed_recording, &filename_base)
+                self.output_run_json(scenario, idx, warmup, processed_recording, &filename_base)
                     .await
             }
         }
@@ -0,0 +1,203 @@
+//! Summary tables of several runs (e.g. sweep points or suite entries),
+//! each row is keyed by a set of params
+
+use crate::statistics::{ConfidenceInterval, FinalStats, Percentiles, StatsSummary};
+use console::style;
+use serde_json::{Map, Value};
+
+pub fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.to_owned(),
+        other => other.to_string(),
+    }
+}
+
+/// One row of a summary table
+#[derive(Debug, serde::Serialize)]
+pub struct SummaryRow {
+    pub name: String,
+    pub params: Map<String, Value>,
+    #[serde(flatten)]
+    pub stats: StatsSummary,
+}
+
+impl SummaryRow {
+    pub fn new(name: String, params: Map<String, Value>, stats: &FinalStats) -> Self {
+        Self {
+            name,
+            params,
+            stats: stats.summary(),
+        }
+    }
+
+    pub fn csv_header(params: &[String]) -> Vec<String> {
+        let stats = [
+            "n_samples",
+            "failed",
+            "mean",
+            "stddev",
+            "median",
+            "min",
+            "max",
+            "transition_median",
+            "overshoot_mean",
+            "p5",
+            "p25",
+            "p75",
+            "p90",
+            "p95",
+            "p99",
+            "mad",
+            "mean_ci_low",
+            "mean_ci_high",
+            "median_ci_low",
+            "median_ci_high",
+        ];
+        params
+            .iter()
+            .cloned()
+            .chain(stats.iter().map(|s| s.to_string()))
+            .collect()
+    }
+
+    /// Cells in the order of csv_header(), missing statistics are empty
+    pub fn csv_record(&self, params: &[String]) -> Vec<String> {
+        let stat = |v: Option<f64>| v.map(|v| format!("{v:.3}")).unwrap_or_default();
+        let StatsSummary {
+            n_samples,
+            failed,
+            mean,
+            stddev,
+            median,
+            min,
+            max,
+            transition_median,
+            overshoot_mean,
+            percentiles,
+            mad,
+            mean_ci,
+            median_ci,
+        } = self.stats;
+        let percentile = |p: fn(&Percentiles) -> f64| stat(percentiles.as_ref().map(p));
+        let ci_low = |ci: Option<ConfidenceInterval>| stat(ci.map(|ci| ci.low));
+        let ci_high = |ci: Option<ConfidenceInterval>| stat(ci.map(|ci| ci.high));
+
+        params
+            .iter()
+            .map(|p| self.params.get(p).map(value_to_string).unwrap_or_default())
+            .chain([
+                n_samples.to_string(),
+                failed.to_string(),
+                stat(mean),
+                stat(stddev),
+                stat(median),
+                stat(min),
+                stat(max),
+                stat(transition_median),
+                stat(overshoot_mean),
+                percentile(|p| p.p5),
+                percentile(|p| p.p25),
+                percentile(|p| p.p75),
+                percentile(|p| p.p90),
+                percentile(|p| p.p95),
+                percentile(|p| p.p99),
+                stat(mad),
+                ci_low(mean_ci),
+                ci_high(mean_ci),
+                ci_low(median_ci),
+                ci_high(median_ci),
+            ])
+            .collect()
+    }
+}
+
+/// Prints a table of results keyed by the params. It has fewer columns than the CSV,
+/// tails are shown with the 95th and 99th percentiles
+pub fn print_summary(params: &[String], rows: &[SummaryRow]) {
+    let stat = |v: Option<f64>| v.map(|v| format!("{v:.1}")).unwrap_or("-".to_owned());
+    let header = params
+        .iter()
+        .cloned()
+        .chain(
+            [
+                "n_samples",
+                "failed",
+                "mean",
+                "stddev",
+                "median",
+                "p95",
+                "p99",
+                "min",
+                "max",
+                "transition_median",
+                "overshoot_mean",
+            ]
+            .map(str::to_owned),
+        )
+        .collect::<Vec<_>>();
+    let cells = rows
+        .iter()
+        .map(|row| {
+            let mut record = row.csv_record(params);
+            // the table is for humans, so one decimal is enough
+            let StatsSummary {
+                mean,
+                stddev,
+                median,
+                min,
+                max,
+                transition_median,
+                overshoot_mean,
+                percentiles,
+                ..
+            } = row.stats;
+            record.truncate(params.len() + 2);
+            record.extend(
+                [
+                    mean,
+                    stddev,
+                    median,
+                    percentiles.map(|p| p.p95),
+                    percentiles.map(|p| p.p99),
+                    min,
+                    max,
+                    transition_median,
+                    overshoot_mean,
+                ]
+                .map(stat),
+            );
+            record
+        })
+        .collect::<Vec<_>>();
+
+    print_table(&header, &cells);
+}
+
+/// Right-aligned columns on stderr, the header in bold
+pub fn print_table(header: &[String], cells: &[Vec<String>]) {
+    let widths = header
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            cells
+                .iter()
+                .map(|r| r[i].chars().count())
+                .chain([h.chars().count()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect::<Vec<_>>();
+
+    let format_row = |row: &[String]| {
+        row.iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:>width$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+    };
+
+    eprintln!("  {}", style(format_row(header)).bold());
+    for row in cells {
+        eprintln!("  {}", format_row(row));
+    }
+}
@@ -0,0 +1,138 @@
+//! Parameter sweeps: the scenario is run once for every combination of variable values
+//! from the `[sweep]` section (or `--sweep`), each combination is a separately named sub-run
+
+use super::summary::value_to_string;
+use anyhow::anyhow;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Parses a "--sweep" value: NAME=V1,V2,... where values are parsed like in "--set"
+pub fn parse_sweep_override(s: &str) -> anyhow::Result<(String, Vec<Value>)> {
+    let (name, values) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Sweep must look like NAME=VALUE1,VALUE2,..., got \"{s}\""))?;
+    let values = values
+        .split(',')
+        .map(|v| serde_json::from_str(v).unwrap_or_else(|_| Value::String(v.to_owned())))
+        .collect();
+    Ok((name.to_owned(), values))
+}
+
+/// One combination of swept variables
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepPoint {
+    pub vars: Vec<(String, Value)>,
+}
+
+impl SweepPoint {
+    /// Name of the sub-run, safe to use as a directory name
+    pub fn name(&self) -> String {
+        self.vars
+            .iter()
+            .map(|(name, value)| {
+                let value = value_to_string(value)
+                    .chars()
+                    .map(|c| {
+                        if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                            c
+                        } else {
+                            '_'
+                        }
+                    })
+                    .collect::<String>();
+                format!("{name}={value}")
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn params(&self) -> Map<String, Value> {
+        self.vars.iter().cloned().collect()
+    }
+}
+
+/// Cartesian product of the values, the first variable changes the slowest
+pub fn combinations(matrix: &[(String, Vec<Value>)]) -> Vec<SweepPoint> {
+    let mut points = vec![SweepPoint { vars: vec![] }];
+    for (name, values) in matrix {
+        points = points
+            .into_iter()
+            .flat_map(|point| {
+                values.iter().map(move |value| {
+                    let mut vars = point.vars.clone();
+                    vars.push((name.to_owned(), value.to_owned()));
+                    SweepPoint { vars }
+                })
+            })
+            .collect();
+    }
+    points
+}
+
+/// Different values can have the same directory name once they are made path safe,
+/// e.g. "a/b" and "a_b", and one sub-run would overwrite the other
+pub fn check_names(points: &[SweepPoint]) -> anyhow::Result<()> {
+    let mut names = HashMap::new();
+    for point in points {
+        if let Some(other) = names.insert(point.name(), point) {
+            let label = |point: &SweepPoint| {
+                point
+                    .vars
+                    .iter()
+                    .map(|(name, value)| format!("{name}={}", value_to_string(value)))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            };
+            return Err(anyhow!(
+                "Sweep points {} and {} would be stored in the same directory \"{}\"",
+                label(other),
+                label(point),
+                point.name()
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_combinations() {
+        let (key, keys) = parse_sweep_override("key=a,space").unwrap();
+        let (wait, waits) = parse_sweep_override("wait_ms=0,50,100").unwrap();
+        assert_eq!(waits, vec![json!(0), json!(50), json!(100)]);
+
+        let points = combinations(&[(key, keys), (wait, waits)]);
+        let names = points.iter().map(SweepPoint::name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "key=a,wait_ms=0",
+                "key=a,wait_ms=50",
+                "key=a,wait_ms=100",
+                "key=space,wait_ms=0",
+                "key=space,wait_ms=50",
+                "key=space,wait_ms=100",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_name_is_path_safe() {
+        let point = SweepPoint {
+            vars: vec![("text".to_owned(), json!("a/b c"))],
+        };
+        assert_eq!(point.name(), "text=a_b_c");
+    }
+
+    #[test]
+    fn test_name_collisions() {
+        let (text, texts) = parse_sweep_override("text=a/b,a_b").unwrap();
+        let points = combinations(&[(text, texts)]);
+        assert!(check_names(&points).is_err());
+        assert!(check_names(&points[..1]).is_ok());
+    }
+}
@@ -0,0 +1,643 @@
+//! Optional preprocessing of scenario files, applied before they are parsed as a Scenario:
+//!
+//! * `include = "common/reset.toml"` (or a list of paths, relative to the including file)
+//!   merges other files in. Keys of the including file take precedence, `vars` and `blocks`
+//!   are merged key by key
+//! * `[vars]` are substituted into strings as `${name}`. A string that consists of a single
+//!   `${name}` takes the variable's type, so `ms = "${wait_ms}"` becomes a number.
+//!   `$${` is a literal `${`. `--set name=value` overrides them, overridden variables
+//!   must be used by the scenario
+//! * `[[blocks.name]]` are named lists of steps that can be used in test/revert sections as
+//!   `{ type = "block", name = "name", repeat = 3 }`
+//! * `[sweep]` lists values of variables to run the scenario with, see the sweep module
+
+use anyhow::{anyhow, Context};
+use late_mate_shared::MAX_SCENARIO_LENGTH;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+const INCLUDE_KEY: &str = "include";
+const VARS_KEY: &str = "vars";
+const BLOCKS_KEY: &str = "blocks";
+const SWEEP_KEY: &str = "sweep";
+
+// nested blocks are expanded recursively, this catches blocks that include themselves
+const MAX_BLOCK_DEPTH: usize = 16;
+
+// every step except start_timing takes room on the device, so longer sections are invalid
+// anyway. Checked while expanding, so that a huge "repeat" isn't allocated first
+const MAX_EXPANDED_STEPS: usize = MAX_SCENARIO_LENGTH + 1;
+
+pub enum Format {
+    Json,
+    Toml,
+}
+
+impl Format {
+    pub fn from_path(path: &str) -> Option<Self> {
+        let path = path.to_lowercase();
+        if path.ends_with(".json") {
+            Some(Format::Json)
+        } else if path.ends_with(".toml") {
+            Some(Format::Toml)
+        } else {
+            None
+        }
+    }
+
+    pub fn parse(&self, s: &str) -> anyhow::Result<Value> {
+        match self {
+            Format::Json => serde_json::from_str(s).context("Error parsing the scenario as JSON"),
+            Format::Toml => toml::from_str(s).context("Error parsing the scenario as TOML"),
+        }
+    }
+}
+
+/// Whether the document uses any templating features, so it needs to be preprocessed
+pub fn is_template(document: &Value) -> bool {
+    document.as_object().is_some_and(|o| {
+        [INCLUDE_KEY, VARS_KEY, BLOCKS_KEY, SWEEP_KEY]
+            .iter()
+            .any(|k| o.contains_key(*k))
+    })
+}
+
+/// Parses a "--set" value: JSON literals (numbers, booleans, quoted strings) keep their type,
+/// anything else is a string
+pub fn parse_var_override(s: &str) -> anyhow::Result<(String, Value)> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Variable override must look like NAME=VALUE, got \"{s}\""))?;
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_owned()));
+    Ok((name.to_owned(), value))
+}
+
+pub fn preprocess(
+    document: Value,
+    base_dir: &Path,
+    var_overrides: &[(String, Value)],
+) -> anyhow::Result<Value> {
+    let mut document = resolve_includes(document, base_dir, &mut vec![])?;
+    let root = document
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Scenario must be a table/object"))?;
+    // the sweep matrix is read separately by sweep_matrix()
+    root.remove(SWEEP_KEY);
+
+    let mut vars = match root.remove(VARS_KEY) {
+        None => Map::new(),
+        Some(Value::Object(vars)) => vars,
+        Some(_) => return Err(anyhow!("\"{VARS_KEY}\" must be a table/object")),
+    };
+    let mut used = BTreeSet::new();
+    collect_references(&document, &mut used)?;
+    for (name, value) in var_overrides {
+        if !used.contains(name) {
+            return Err(anyhow!(
+                "Variable \"{name}\" is overridden, but the scenario doesn't use it"
+            ));
+        }
+        vars.insert(name.to_owned(), value.to_owned());
+    }
+
+    substitute(&mut document, &vars)?;
+
+    let root = document
+        .as_object_mut()
+        .expect("Scenario root must still be an object after substitution");
+    let blocks = match root.remove(BLOCKS_KEY) {
+        None => Map::new(),
+        Some(Value::Object(blocks)) => blocks,
+        Some(_) => return Err(anyhow!("\"{BLOCKS_KEY}\" must be a table/object")),
+    };
+    for section in ["test", "revert"] {
+        if let Some(Value::Array(steps)) = root.get_mut(section) {
+            *steps = expand_blocks(std::mem::take(steps), &blocks, &mut vec![])
+                .with_context(|| format!("Error expanding blocks in the {section} section"))?;
+        }
+    }
+
+    Ok(document)
+}
+
+fn resolve_includes(
+    mut document: Value,
+    base_dir: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> anyhow::Result<Value> {
+    let Some(root) = document.as_object_mut() else {
+        return Ok(document);
+    };
+
+    let includes = match root.remove(INCLUDE_KEY) {
+        None => vec![],
+        Some(Value::String(path)) => vec![path],
+        Some(Value::Array(paths)) => paths
+            .into_iter()
+            .map(|p| match p {
+                Value::String(path) => Ok(path),
+                _ => Err(anyhow!(
+                    "\"{INCLUDE_KEY}\" must be a path or a list of paths"
+                )),
+            })
+            .collect::<anyhow::Result<_>>()?,
+        Some(_) => {
+            return Err(anyhow!(
+                "\"{INCLUDE_KEY}\" must be a path or a list of paths"
+            ))
+        }
+    };
+
+    let mut merged = Value::Object(Map::new());
+    for include in includes {
+        let path = base_dir.join(&include);
+        let path_s = path.to_string_lossy().to_string();
+        if stack.contains(&path) {
+            return Err(anyhow!("\"{path_s}\" includes itself"));
+        }
+
+        let format = Format::from_path(&include).ok_or_else(|| {
+            anyhow!("Included file extension must be either .toml or .json, got \"{include}\"")
+        })?;
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Error reading included file \"{path_s}\""))?;
+        let included = format
+            .parse(&contents)
+            .with_context(|| format!("Error in included file \"{path_s}\""))?;
+
+        let included_dir = path.parent().unwrap_or(base_dir).to_owned();
+        stack.push(path);
+        let included = resolve_includes(included, &included_dir, stack)?;
+        stack.pop();
+
+        merged = merge(merged, included);
+    }
+
+    Ok(merge(merged, document))
+}
+
+/// Names of the variables referenced as `${name}` anywhere in the scenario or its includes
+pub fn referenced_vars(document: &Value, base_dir: &Path) -> anyhow::Result<BTreeSet<String>> {
+    let mut document = resolve_includes(document.to_owned(), base_dir, &mut vec![])?;
+    if let Some(root) = document.as_object_mut() {
+        root.remove(SWEEP_KEY);
+        root.remove(VARS_KEY);
+    }
+
+    let mut names = BTreeSet::new();
+    collect_references(&document, &mut names)?;
+    Ok(names)
+}
+
+/// Values of the variables from the `[sweep]` section, includes are taken into account
+pub fn sweep_matrix(document: &Value, base_dir: &Path) -> anyhow::Result<Map<String, Value>> {
+    let document = resolve_includes(document.to_owned(), base_dir, &mut vec![])?;
+    let matrix = match document.get(SWEEP_KEY) {
+        None => Map::new(),
+        Some(Value::Object(matrix)) => matrix.to_owned(),
+        Some(_) => return Err(anyhow!("\"{SWEEP_KEY}\" must be a table/object")),
+    };
+
+    for (name, values) in &matrix {
+        match values {
+            Value::Array(values) if !values.is_empty() => {}
+            _ => {
+                return Err(anyhow!(
+                    "Sweep values of \"{name}\" must be a non-empty list"
+                ))
+            }
+        }
+    }
+
+    Ok(matrix)
+}
+
+/// Whether a JSON Schema accepts a single scalar value that can be replaced by a variable.
+/// Enums with a single value are tags (e.g. step types) and stay as they are
+fn is_substitutable(schema: &Map<String, Value>) -> bool {
+    if let Some(Value::Array(values)) = schema.get("enum") {
+        return values.len() > 1;
+    }
+    let types = match schema.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+    types
+        .iter()
+        .any(|t| ["integer", "number", "boolean"].contains(t))
+}
+
+/// Allows a `${name}` reference everywhere a substitutable value is expected
+fn allow_references(schema: &mut Value) {
+    let Value::Object(map) = schema else {
+        return;
+    };
+    for (key, value) in map.iter_mut() {
+        match (key.as_str(), value) {
+            // these hold values rather than schemas
+            ("default" | "enum" | "required", _) => {}
+            (_, Value::Array(items)) => items.iter_mut().for_each(allow_references),
+            (_, value) => allow_references(value),
+        }
+    }
+
+    if is_substitutable(map) {
+        let mut original = std::mem::take(map);
+        for key in ["description", "default"] {
+            if let Some(value) = original.remove(key) {
+                map.insert(key.to_owned(), value);
+            }
+        }
+        let reference = serde_json::json!({ "$ref": "#/definitions/VariableReference" });
+        map.insert(
+            "anyOf".to_owned(),
+            Value::Array(vec![Value::Object(original), reference]),
+        );
+    }
+}
+
+/// Extends the JSON Schema of plain scenarios with templating: the top-level keys, block
+/// steps and `${name}` references in place of numbers, booleans and enums. Strings accept
+/// references anyway
+pub fn json_schema(mut schema: Value) -> Value {
+    use serde_json::json;
+
+    if let Some(steps) = schema
+        .pointer_mut("/definitions/ScenarioStep/oneOf")
+        .and_then(Value::as_array_mut)
+    {
+        steps.push(json!({
+            "description": "Steps of a block from [blocks]",
+            "type": "object",
+            "required": ["name", "type"],
+            "properties": {
+                "type": { "type": "string", "enum": ["block"] },
+                "name": { "type": "string" },
+                "repeat": { "default": 1, "type": "integer", "minimum": 1 },
+            },
+        }));
+    }
+    allow_references(&mut schema);
+
+    if let Some(definitions) = schema.get_mut("definitions").and_then(Value::as_object_mut) {
+        definitions.insert(
+            "VariableReference".to_owned(),
+            json!({
+                "description": "Reference to a variable from [vars], e.g. \"${wait_ms}\"",
+                "type": "string",
+                "pattern": "^\\$\\{[^}]+\\}$",
+            }),
+        );
+    }
+    if let Some(properties) = schema.get_mut("properties").and_then(Value::as_object_mut) {
+        properties.insert(
+            INCLUDE_KEY.to_owned(),
+            json!({
+                "description": "Files to merge in, relative to this one",
+                "anyOf": [
+                    { "type": "string" },
+                    { "type": "array", "items": { "type": "string" } },
+                ],
+            }),
+        );
+        properties.insert(
+            VARS_KEY.to_owned(),
+            json!({
+                "description": "Variables substituted into strings as ${name}",
+                "type": "object",
+            }),
+        );
+        properties.insert(
+            BLOCKS_KEY.to_owned(),
+            json!({
+                "description": "Named lists of steps, used as { type = \"block\", name = \"...\" }",
+                "type": "object",
+                "additionalProperties": {
+                    "type": "array",
+                    "items": { "$ref": "#/definitions/ScenarioStep" },
+                },
+            }),
+        );
+        properties.insert(
+            SWEEP_KEY.to_owned(),
+            json!({
+                "description": "Values of variables to run the scenario with",
+                "type": "object",
+                "additionalProperties": { "type": "array", "minItems": 1 },
+            }),
+        );
+    }
+
+    schema
+}
+
+/// Keys of `top` replace keys of `base`, except for vars, blocks and sweep that are merged
+fn merge(base: Value, top: Value) -> Value {
+    match (base, top) {
+        (Value::Object(mut base), Value::Object(top)) => {
+            for (key, value) in top {
+                let merged = match (base.remove(&key), value) {
+                    (Some(Value::Object(mut base_inner)), Value::Object(top_inner))
+                        if [VARS_KEY, BLOCKS_KEY, SWEEP_KEY].contains(&key.as_str()) =>
+                    {
+                        base_inner.extend(top_inner);
+                        Value::Object(base_inner)
+                    }
+                    (_, value) => value,
+                };
+                base.insert(key, merged);
+            }
+            Value::Object(base)
+        }
+        (_, top) => top,
+    }
+}
+
+fn var_to_string(name: &str, value: &Value) -> anyhow::Result<String> {
+    match value {
+        Value::String(s) => Ok(s.to_owned()),
+        Value::Number(n) => Ok(n.to_string()),
+        Value::Bool(b) => Ok(b.to_string()),
+        _ => Err(anyhow!(
+            "Variable \"{name}\" can't be used inside a string, it's not a string, a number or a boolean"
+        )),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Part<'a> {
+    Text(&'a str),
+    Reference(&'a str),
+}
+
+/// Splits a string into literal text and `${name}` references
+fn parse_references(s: &str) -> anyhow::Result<Vec<Part<'_>>> {
+    let mut parts = vec![];
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        // "$${" is an escaped "${", the first "$" is dropped
+        if rest[..start].ends_with('$') {
+            parts.push(Part::Text(&rest[..start - 1]));
+            parts.push(Part::Text("${"));
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        parts.push(Part::Text(&rest[..start]));
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| anyhow!("Unclosed variable reference in \"{s}\""))?;
+        parts.push(Part::Reference(&after[..end]));
+        rest = &after[end + 1..];
+    }
+    parts.push(Part::Text(rest));
+    parts.retain(|p| *p != Part::Text(""));
+
+    Ok(parts)
+}
+
+fn collect_references(value: &Value, names: &mut BTreeSet<String>) -> anyhow::Result<()> {
+    match value {
+        Value::String(s) => {
+            for part in parse_references(s)? {
+                if let Part::Reference(name) = part {
+                    names.insert(name.to_owned());
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                collect_references(item, names)?;
+            }
+        }
+        Value::Object(map) => {
+            for item in map.values() {
+                collect_references(item, names)?;
+            }
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+fn substitute_string(s: &str, vars: &Map<String, Value>) -> anyhow::Result<Value> {
+    let lookup = |name: &str| {
+        vars.get(name)
+            .ok_or_else(|| anyhow!("Unknown variable \"{name}\""))
+    };
+
+    let parts = parse_references(s)?;
+    // a string that is just a reference keeps the variable's type
+    if let [Part::Reference(name)] = parts.as_slice() {
+        return lookup(name).cloned();
+    }
+
+    let mut result = String::new();
+    for part in parts {
+        match part {
+            Part::Text(text) => result.push_str(text),
+            Part::Reference(name) => result.push_str(&var_to_string(name, lookup(name)?)?),
+        }
+    }
+
+    Ok(Value::String(result))
+}
+
+fn substitute(value: &mut Value, vars: &Map<String, Value>) -> anyhow::Result<()> {
+    match value {
+        Value::String(s) => *value = substitute_string(s, vars)?,
+        Value::Array(items) => {
+            for item in items {
+                substitute(item, vars)?;
+            }
+        }
+        Value::Object(map) => {
+            for item in map.values_mut() {
+                substitute(item, vars)?;
+            }
+        }
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
+
+fn expand_blocks(
+    steps: Vec<Value>,
+    blocks: &Map<String, Value>,
+    stack: &mut Vec<String>,
+) -> anyhow::Result<Vec<Value>> {
+    let mut expanded = Vec::with_capacity(steps.len());
+
+    for step in steps {
+        let is_block = step.get("type").and_then(Value::as_str) == Some("block");
+        if !is_block {
+            expanded.push(step);
+            continue;
+        }
+
+        let name = step
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("Block steps must have a \"name\""))?;
+        let repeat = match step.get("repeat") {
+            None => 1,
+            Some(r) => r
+                .as_u64()
+                .filter(|r| *r > 0)
+                .ok_or_else(|| anyhow!("Block \"{name}\" repeat must be a positive integer"))?,
+        };
+        let Some(Value::Array(block_steps)) = blocks.get(name) else {
+            return Err(anyhow!("Unknown block \"{name}\""));
+        };
+        if stack.len() >= MAX_BLOCK_DEPTH || stack.iter().any(|n| n == name) {
+            return Err(anyhow!("Block \"{name}\" includes itself"));
+        }
+
+        stack.push(name.to_owned());
+        let block_steps = expand_blocks(block_steps.to_owned(), blocks, stack)?;
+        stack.pop();
+
+        let total = usize::try_from(repeat)
+            .ok()
+            .and_then(|r| r.checked_mul(block_steps.len()))
+            .and_then(|n| n.checked_add(expanded.len()));
+        if total.map_or(true, |total| total > MAX_EXPANDED_STEPS) {
+            return Err(anyhow!(
+                "Block \"{name}\" repeated {repeat} times makes the section longer than {MAX_SCENARIO_LENGTH} steps"
+            ));
+        }
+        for _ in 0..repeat {
+            expanded.extend(block_steps.iter().cloned());
+        }
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_vars_and_blocks() {
+        let document = json!({
+            "vars": { "key": "a", "wait_ms": 100 },
+            "blocks": {
+                "press": [
+                    { "type": "key_down", "key": "${key}" },
+                    { "type": "key_up", "key": "${key}" },
+                ],
+            },
+            "test": [
+                { "type": "start_timing" },
+                { "type": "block", "name": "press", "repeat": 2 },
+                { "type": "wait", "ms": "${wait_ms}" },
+                { "type": "type_text", "text": "${key}-${wait_ms}" },
+            ],
+        });
+        let overrides = [parse_var_override("wait_ms=50").unwrap()];
+        let result = preprocess(document, Path::new("."), &overrides).unwrap();
+
+        let press = json!([
+            { "type": "key_down", "key": "a" },
+            { "type": "key_up", "key": "a" },
+        ]);
+        let press = press.as_array().unwrap();
+        let mut test = vec![json!({ "type": "start_timing" })];
+        test.extend(press.iter().cloned());
+        test.extend(press.iter().cloned());
+        test.push(json!({ "type": "wait", "ms": 50 }));
+        test.push(json!({ "type": "type_text", "text": "a-50" }));
+        assert_eq!(result, json!({ "test": test }));
+    }
+
+    #[test]
+    fn test_errors() {
+        let unknown_var = json!({ "vars": {}, "test": [{ "type": "wait", "ms": "${nope}" }] });
+        assert!(preprocess(unknown_var, Path::new("."), &[]).is_err());
+
+        let recursive = json!({
+            "blocks": { "loop": [{ "type": "block", "name": "loop" }] },
+            "test": [{ "type": "block", "name": "loop" }],
+        });
+        assert!(preprocess(recursive, Path::new("."), &[]).is_err());
+
+        let document = json!({
+            "vars": { "wait_ms": 100 },
+            "blocks": { "nothing": [{ "type": "wait", "ms": 0 }] },
+            "test": [{ "type": "wait", "ms": "${wait_ms}" }],
+        });
+        let typo = [parse_var_override("wiat_ms=50").unwrap()];
+        assert!(preprocess(document.clone(), Path::new("."), &typo).is_err());
+
+        for repeat in [0, u64::MAX] {
+            let mut document = document.clone();
+            document["test"] = json!([{ "type": "block", "name": "nothing", "repeat": repeat }]);
+            assert!(preprocess(document, Path::new("."), &[]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_escape() {
+        let document = json!({
+            "vars": { "a": 1 },
+            "test": [{ "type": "type_text", "text": "$${a} is ${a}" }],
+        });
+        let result = preprocess(document, Path::new("."), &[]).unwrap();
+        assert_eq!(result["test"][0]["text"], json!("${a} is 1"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = json!({ "vars": { "a": 1, "b": 2 }, "repeats": 10, "revert": [] });
+        let top = json!({ "vars": { "b": 3 }, "repeats": 20 });
+        assert_eq!(
+            merge(base, top),
+            json!({ "vars": { "a": 1, "b": 3 }, "repeats": 20, "revert": [] })
+        );
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let compiled =
+            serde_json::to_value(late_mate_device::scenario::Scenario::json_schema()).unwrap();
+        let schema = json_schema(compiled);
+
+        for key in [INCLUDE_KEY, VARS_KEY, BLOCKS_KEY, SWEEP_KEY] {
+            assert!(schema["properties"].get(key).is_some(), "{key}");
+        }
+        let reference = json!({ "$ref": "#/definitions/VariableReference" });
+        assert_eq!(schema["properties"]["repeats"]["anyOf"][1], reference);
+        let steps = schema["definitions"]["ScenarioStep"]["oneOf"]
+            .as_array()
+            .unwrap();
+        let wait = steps
+            .iter()
+            .find(|s| s["properties"]["type"]["enum"] == json!(["wait"]))
+            .unwrap();
+        assert_eq!(wait["properties"]["ms"]["anyOf"][1], reference);
+        assert!(steps
+            .iter()
+            .any(|s| s["properties"]["type"]["enum"] == json!(["block"])));
+    }
+
+    #[test]
+    fn test_example_scenario() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+        let contents = std::fs::read_to_string(dir.join("type_key_template.toml")).unwrap();
+        let document = Format::Toml.parse(&contents).unwrap();
+        assert!(is_template(&document));
+
+        let overrides = [parse_var_override("key=b").unwrap()];
+        let document = preprocess(document, &dir, &overrides).unwrap();
+        let scenario: late_mate_device::scenario::Scenario =
+            serde_json::from_value(document).unwrap();
+        scenario.validate().unwrap();
+        assert_eq!(scenario.test.len(), 4);
+        assert!(scenario.revert.is_some());
+    }
+}
@@ -0,0 +1,26 @@
+use super::run::template;
+use anyhow::Context;
+use late_mate_device::scenario::Scenario;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Print the schema of plain scenarios, without templating (vars, includes, blocks and
+    /// sweeps), e.g. for the output of "scenario run --dry-run"
+    #[arg(long)]
+    pub no_templates: bool,
+}
+
+impl Args {
+    pub async fn run(self) -> anyhow::Result<()> {
+        let mut schema = serde_json::to_value(Scenario::json_schema())
+            .context("Error serialising the schema")?;
+        if !self.no_templates {
+            schema = template::json_schema(schema);
+        }
+        let schema =
+            serde_json::to_string_pretty(&schema).context("Error serialising the schema")?;
+        println!("{schema}");
+
+        Ok(())
+    }
+}
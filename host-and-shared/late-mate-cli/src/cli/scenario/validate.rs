@@ -0,0 +1,50 @@
+use crate::cli::scenario::run::{parse_var_override, print_lints, ScenarioSource};
+use anyhow::Context;
+use console::style;
+use indicatif::HumanDuration;
+use late_mate_device::scenario::device_len;
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Path to a .json or .toml with a scenario. Set to "-" to read from STDIN
+    pub input: String,
+
+    /// Set a scenario variable, same as in "scenario run"
+    #[arg(long = "set", value_name = "NAME=VALUE", value_parser = parse_var_override)]
+    pub vars: Vec<(String, serde_json::Value)>,
+}
+
+impl Args {
+    pub async fn run(self) -> anyhow::Result<()> {
+        let scenario = ScenarioSource::read(&self.input)
+            .await?
+            .scenario(&self.vars)?;
+        scenario.validate().context("Scenario validation error")?;
+        print_lints(&scenario, None);
+
+        let test_len = device_len(&scenario.test)?;
+        let revert_len = match scenario.revert_steps()? {
+            Some(steps) => device_len(&steps)?,
+            None => 0,
+        };
+        let (total_min, total_max) = scenario.total_duration();
+
+        eprintln!("{}", style("Scenario is valid").bold().green());
+        eprintln!("  Repeats:            {}", scenario.repeats);
+        if scenario.warmup > 0 {
+            eprintln!("  Warmup repeats:     {}", scenario.warmup);
+        }
+        eprintln!("  Device steps:       {test_len} in test, {revert_len} in revert");
+        eprintln!(
+            "  Test duration:      {}ms",
+            scenario.test_duration().as_millis()
+        );
+        eprintln!(
+            "  Estimated duration: {:#} to {:#}",
+            HumanDuration(total_min),
+            HumanDuration(total_max)
+        );
+
+        Ok(())
+    }
+}
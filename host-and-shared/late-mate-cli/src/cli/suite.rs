@@ -0,0 +1,7 @@
+pub mod run;
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Suite {
+    /// Run several scenarios listed in a suite file and report on all of them together
+    Run(run::Args),
+}
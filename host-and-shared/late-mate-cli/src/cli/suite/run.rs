@@ -0,0 +1,374 @@
+use crate::cli::scenario::run::file_output::{self, FileOutput};
+use crate::cli::scenario::run::summary::{self, SummaryRow};
+use crate::cli::scenario::run::{print_lints, print_stats, run_single_repeat, ScenarioSource};
+use crate::statistics::{parse_detector, process_measurements, process_recording, Measurement};
+use anyhow::{anyhow, Context};
+use console::style;
+use indicatif::{ProgressBar, ProgressStyle};
+use late_mate_device::scenario::{Detector, Scenario};
+use late_mate_device::Device;
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    /// Run all repeats of a scenario before moving to the next one
+    #[default]
+    Sequential,
+    /// Alternate between scenarios after every repeat, so that slow drifts
+    /// (thermal, background load) affect all of them equally
+    Interleaved,
+    /// Like interleaved, but the order of scenarios is shuffled in every round
+    Randomised,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SuiteFile {
+    #[serde(default)]
+    order: Order,
+    #[serde(rename = "scenario")]
+    scenarios: Vec<SuiteFileEntry>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SuiteFileEntry {
+    /// Relative to the suite file
+    path: PathBuf,
+    /// Name of the output subdirectory, the file name without the extension by default
+    name: Option<String>,
+    /// Overrides scenario's "repeats"
+    repeats: Option<u16>,
+    /// Overrides scenario's "warmup"
+    warmup: Option<u16>,
+    /// Scenario variables, same as "--set" in "scenario run"
+    #[serde(default)]
+    vars: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct Args {
+    /// Path to a .toml suite file
+    pub input: PathBuf,
+
+    /// Name of this suite run. It is used for a subdirectory to store results in,
+    /// with a subdirectory per scenario. If not provided, current date and time are used.
+    #[arg(long)]
+    pub name: Option<String>,
+
+    /// Path to a directory where a subdirectory with JSON results will be created.
+    /// Can be used simultaneously with other output options.
+    #[arg(long)]
+    output_json_dir: Option<PathBuf>,
+
+    /// Path to a directory where a subdirectory with CSV results will be created.
+    /// Can be used simultaneously with other output options.
+    #[arg(long)]
+    output_csv_dir: Option<PathBuf>,
+
+    /// Override suite's "order" field
+    #[arg(long)]
+    pub order: Option<Order>,
+
+    /// Override changepoint detector of all scenarios, same as in "scenario run"
+    #[arg(long, value_name = "METHOD[:NAME=VALUE,...]", value_parser = parse_detector)]
+    pub detector: Option<Detector>,
+}
+
+/// A scenario in a run that mixes several of them
+pub struct Entry {
+    pub name: String,
+    pub scenario: Scenario,
+    pub outputs: Vec<FileOutput>,
+    /// Recordings so far, including warmups
+    pub n_recorded: usize,
+    pub measurements: Vec<Measurement>,
+}
+
+impl Entry {
+    /// Creates a subdirectory named after the entry in every output
+    pub async fn new(
+        name: String,
+        scenario: Scenario,
+        file_outputs: &[FileOutput],
+    ) -> anyhow::Result<Self> {
+        let mut outputs = Vec::with_capacity(file_outputs.len());
+        for file_output in file_outputs {
+            let output = file_output
+                .prepare_sub_run(&name)
+                .await
+                .context("Error while preparing scenario output directory")?;
+            outputs.push(output);
+        }
+
+        Ok(Self {
+            measurements: Vec::with_capacity(usize::from(scenario.repeats)),
+            name,
+            scenario,
+            outputs,
+            n_recorded: 0,
+        })
+    }
+}
+
+/// Order of repeats of entries with given (warmup, repeats) as (entry index, is warmup)
+pub fn schedule(order: Order, entries: &[(u16, u16)], rng: &mut impl Rng) -> Vec<(usize, bool)> {
+    match order {
+        Order::Sequential => entries
+            .iter()
+            .enumerate()
+            .flat_map(|(i, &(warmup, repeats))| {
+                let warmups = (0..warmup).map(move |_| (i, true));
+                let measured = (0..repeats).map(move |_| (i, false));
+                warmups.chain(measured)
+            })
+            .collect(),
+        Order::Interleaved | Order::Randomised => {
+            let mut result = vec![];
+            // all warmups go first, so that measurements start with everything warmed up
+            for is_warmup in [true, false] {
+                let count = |&(warmup, repeats): &(u16, u16)| {
+                    if is_warmup {
+                        warmup
+                    } else {
+                        repeats
+                    }
+                };
+                let rounds = entries.iter().map(count).max().unwrap_or_default();
+                for round in 0..rounds {
+                    let mut block = entries
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, e)| count(e) > round)
+                        .map(|(i, _)| (i, is_warmup))
+                        .collect::<Vec<_>>();
+                    // every round is shuffled separately so that the scenarios stay balanced
+                    // over time, slow drifts can't favour any of them
+                    if order == Order::Randomised {
+                        block.shuffle(rng);
+                    }
+                    result.extend(block);
+                }
+            }
+            result
+        }
+    }
+}
+
+fn get_progressbar(total: usize) -> ProgressBar {
+    let progress = ProgressBar::new(total as u64);
+    // Note that I can't use the spinner if I want to minimise load during the test
+    progress.set_style(
+        ProgressStyle::with_template(
+            "{prefix:.bold}  [ {bar:40.green/dim} ] {pos:>3}/{len:3} [ETA: {eta}]",
+        )
+        .expect("Progress bar template must be correct")
+        .progress_chars("##-"),
+    );
+    progress.set_prefix("Running scenarios…");
+    progress
+}
+
+/// Runs repeats of entries in the order of the schedule, collecting their measurements
+pub async fn run_entries(
+    device: &Device,
+    entries: &mut [Entry],
+    schedule: Vec<(usize, bool)>,
+) -> anyhow::Result<()> {
+    let progress = get_progressbar(schedule.len());
+    for (i, is_warmup) in schedule {
+        let entry = &mut entries[i];
+        let recording = run_single_repeat(device, &entry.scenario)
+            .await
+            .with_context(|| format!("Error running scenario \"{}\"", entry.name))?;
+        progress.inc(1);
+
+        let processed = process_recording(
+            recording,
+            &entry.scenario.detector,
+            entry.scenario.interpolation,
+        );
+        // warmups of every entry are scheduled before its measured repeats
+        let idx = entry.n_recorded;
+        entry.n_recorded += 1;
+        for output in &entry.outputs {
+            output
+                .output_run(&entry.scenario, idx, &processed)
+                .await
+                .context("Error processing an output step")?;
+        }
+        if !is_warmup {
+            entry.measurements.push(processed.measurement());
+        }
+    }
+    progress.finish_and_clear();
+
+    Ok(())
+}
+
+impl Args {
+    async fn read_suite(&self) -> anyhow::Result<(Order, Vec<(String, Scenario)>)> {
+        let input_s = self.input.to_string_lossy();
+        let contents = tokio::fs::read_to_string(&self.input)
+            .await
+            .with_context(|| format!("Error while reading the suite file \"{input_s}\""))?;
+        let suite: SuiteFile =
+            toml::from_str(&contents).context("Error parsing the suite as TOML")?;
+        if suite.scenarios.is_empty() {
+            return Err(anyhow!("The suite has no scenarios"));
+        }
+
+        let base_dir = self.input.parent().unwrap_or(Path::new("."));
+        let mut names = HashSet::new();
+        let mut scenarios = Vec::with_capacity(suite.scenarios.len());
+        for entry in suite.scenarios {
+            let path = base_dir.join(&entry.path);
+            let path_s = path.to_string_lossy().to_string();
+
+            let name = entry.name.unwrap_or_else(|| {
+                path.file_stem()
+                    .map(|s| s.to_string_lossy().to_string())
+                    .unwrap_or(path_s.to_owned())
+            });
+            if !names.insert(name.to_owned()) {
+                return Err(anyhow!(
+                    "Suite scenario names must be unique, \"{name}\" is used more than once"
+                ));
+            }
+
+            let vars = entry.vars.into_iter().collect::<Vec<_>>();
+            let mut scenario = ScenarioSource::read(&path_s)
+                .await?
+                .scenario(&vars)
+                .with_context(|| format!("Error in suite scenario \"{name}\""))?;
+            if let Some(repeats) = entry.repeats {
+                scenario.repeats = repeats;
+            }
+            if let Some(warmup) = entry.warmup {
+                scenario.warmup = warmup;
+            }
+            if let Some(detector) = &self.detector {
+                scenario.detector = detector.clone();
+            }
+            scenario
+                .validate()
+                .with_context(|| format!("Validation error in suite scenario \"{name}\""))?;
+            print_lints(&scenario, Some(&name));
+
+            scenarios.push((name, scenario));
+        }
+
+        Ok((self.order.unwrap_or(suite.order), scenarios))
+    }
+
+    async fn prepare_outputs(&self) -> anyhow::Result<Vec<FileOutput>> {
+        file_output::prepare_outputs(
+            self.name.as_deref(),
+            self.output_json_dir.as_deref(),
+            self.output_csv_dir.as_deref(),
+        )
+        .await
+    }
+
+    pub async fn run(self, device: &Device) -> anyhow::Result<()> {
+        let (order, scenarios) = self.read_suite().await?;
+        let file_outputs = self.prepare_outputs().await?;
+
+        let mut entries = Vec::with_capacity(scenarios.len());
+        for (name, scenario) in scenarios {
+            entries.push(Entry::new(name, scenario, &file_outputs).await?);
+        }
+
+        let counts = entries
+            .iter()
+            .map(|e| (e.scenario.warmup, e.scenario.repeats))
+            .collect::<Vec<_>>();
+        let schedule = schedule(order, &counts, &mut SmallRng::from_entropy());
+        run_entries(device, &mut entries, schedule).await?;
+
+        let params = vec!["scenario".to_owned()];
+        let mut rows = Vec::with_capacity(entries.len());
+        for entry in &entries {
+            let stats = process_measurements(&entry.measurements);
+            eprintln!("{}", style(&entry.name).cyan().bold());
+            print_stats(&stats);
+
+            let row_params = [("scenario".to_owned(), entry.name.to_owned().into())];
+            rows.push(SummaryRow::new(
+                entry.name.to_owned(),
+                row_params.into_iter().collect(),
+                &stats,
+            ));
+        }
+
+        for file_output in &file_outputs {
+            file_output
+                .output_summary("suite", &params, &rows)
+                .await
+                .context("Error while writing the suite summary")?;
+        }
+
+        eprintln!(
+            "{}, results (in milliseconds):",
+            style("Suite complete").bold()
+        );
+        summary::print_summary(&params, &rows);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        let entries = [(1, 2), (0, 3)];
+        let mut rng = SmallRng::seed_from_u64(0);
+        assert_eq!(
+            schedule(Order::Sequential, &entries, &mut rng),
+            [
+                (0, true),
+                (0, false),
+                (0, false),
+                (1, false),
+                (1, false),
+                (1, false)
+            ]
+        );
+        assert_eq!(
+            schedule(Order::Interleaved, &entries, &mut rng),
+            [
+                (0, true),
+                (0, false),
+                (1, false),
+                (0, false),
+                (1, false),
+                (1, false)
+            ]
+        );
+
+        let randomised = schedule(Order::Randomised, &entries, &mut rng);
+        assert_eq!(randomised[0], (0, true));
+        // every round has each of the scenarios that still have repeats left
+        let mut first_round = randomised[1..3].to_vec();
+        first_round.sort();
+        assert_eq!(first_round, [(0, false), (1, false)]);
+        assert_eq!(randomised[5], (1, false));
+    }
+
+    #[test]
+    fn test_example_suite() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/suite.toml");
+        let suite: SuiteFile = toml::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(suite.order, Order::Sequential);
+        assert_eq!(suite.scenarios.len(), 2);
+        assert_eq!(suite.scenarios[1].name.as_deref(), Some("type_space"));
+    }
+}
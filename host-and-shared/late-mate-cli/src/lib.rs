@@ -1,8 +1,7 @@
 mod cli;
+pub mod server;
 mod statistics;
 
-use late_mate_device::Device;
-
 pub async fn run() -> anyhow::Result<()> {
     let parsed_cli: cli::Cli = clap::Parser::parse();
 
@@ -11,34 +10,10 @@ pub async fn run() -> anyhow::Result<()> {
     // use that subscriber to process traces emitted after this point
     tracing::subscriber::set_global_default(subscriber)?;
 
-    tracing::debug!("Initialising the device");
-    let mut device = Device::init().await?;
-
     tracing::debug!("Running the command");
-    parsed_cli.command.run(&mut device).await
+    parsed_cli.command.run(parsed_cli.remote.as_deref()).await
 }
 
-// pub async fn monitor_background(mut device: Device) -> anyhow::Result<()> {
-//     let mut receiver = device.subscribe_to_background();
-//     device.background_enable();
-//
-//     // 120hz, no point streaming faster
-//     let mut interval = interval(Duration::from_millis(1000 / 120));
-//     loop {
-//         match receiver.recv().await {
-//             Ok(light_level) => {
-//                 println!(
-//                     "{:.4}",
-//                     (light_level as f64 / device.max_light_level as f64) * 100f64
-//                 )
-//             }
-//             Err(RecvError::Lagged(_)) => continue,
-//             Err(RecvError::Closed) => return Err(anyhow!("Background light level channel closed")),
-//         };
-//         interval.tick().await;
-//     }
-// }
-
 // pub async fn hid_demo(
 //     device_tx: mpsc::Sender<HostToDevice>,
 //     mut device_rx: broadcast::Receiver<DeviceToHost>,
@@ -1,4 +1,6 @@
 pub mod api;
+mod http;
+mod runs;
 
 use axum::{
     extract::ws::{Message, WebSocket, WebSocketUpgrade},
@@ -7,44 +9,96 @@ use axum::{
     Extension, Router,
 };
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use std::future::Future;
-use std::net::IpAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::{net::SocketAddr, path::PathBuf};
+use tower_http::services::ServeDir;
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
 
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
 use axum::extract::connect_info::ConnectInfo;
 
-use crate::device::Device;
-use futures::{sink::SinkExt, stream::StreamExt};
-use late_mate_shared::comms::device_to_host::Measurement;
-use tokio::sync::broadcast::error::RecvError;
-use tokio::sync::{mpsc, watch, Mutex as TokioMutex};
+use crate::statistics::process_recording;
+use futures::stream::SplitStream;
+use futures::{sink::SinkExt, stream::StreamExt, TryStreamExt};
+use late_mate_device::hid::HidReport;
+use late_mate_device::scenario::{
+    Detector, Event, Interpolation, Recording, Revert, Scenario, ScenarioStep,
+};
+use late_mate_device::{Device, Status};
+use tokio::sync::{mpsc, Mutex as TokioMutex, Notify, OwnedMutexGuard};
 use tokio::task::JoinHandle;
 
+/// 2 values per ms; 60 fps = 16.6ms/frame; 40 samples per message (=50hz) should be OK
+const MONITOR_BUFFER_SIZE: usize = 40;
+
+/// Exclusive access to the device, so that clients don't interleave scenarios. Light level
+/// monitoring holds it too, but steps aside whenever something else locks the device
 #[derive(Clone)]
-struct ServerState {
+struct DeviceLock {
     device: Arc<TokioMutex<Device>>,
+    wanted: Arc<Notify>,
 }
 
-pub async fn run(device: Device, interface: IpAddr, port: u16) -> anyhow::Result<()> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "late-mate-cli=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+impl DeviceLock {
+    fn new(device: Device) -> Self {
+        Self {
+            device: Arc::new(TokioMutex::new(device)),
+            wanted: Arc::new(Notify::new()),
+        }
+    }
 
-    let _assets_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets");
+    /// Held for the whole measurement or run, monitoring is paused in the meantime
+    async fn lock(&self) -> OwnedMutexGuard<Device> {
+        // a stored permit makes the next monitoring step aside even if it's just starting
+        self.wanted.notify_one();
+        self.device.clone().lock_owned().await
+    }
 
-    // build our application with some routes
-    let app = Router::new()
-        //.fallback_service(ServeDir::new(assets_dir).append_index_html_on_directories(true))
-        .route("/ws", get(ws_handler));
+    fn try_lock(&self) -> Option<OwnedMutexGuard<Device>> {
+        self.device.clone().try_lock_owned().ok()
+    }
+
+    /// Held by monitoring until `stepped_aside` completes. The mutex is fair, so whoever
+    /// wanted the device gets it before monitoring does again
+    async fn lock_for_monitoring(&self) -> OwnedMutexGuard<Device> {
+        self.device.clone().lock_owned().await
+    }
+
+    async fn stepped_aside(&self) {
+        self.wanted.notified().await
+    }
+}
+
+#[derive(Clone)]
+struct ServerState {
+    device: DeviceLock,
+    runs: runs::Runs,
+    last_status: Arc<std::sync::Mutex<Status>>,
+}
+
+pub async fn run(
+    mut device: Device,
+    interface: IpAddr,
+    port: u16,
+    frontend_dir: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let status = device.get_status().await?;
+    let device = DeviceLock::new(device);
+    let server_state = ServerState {
+        device: device.clone(),
+        runs: runs::Runs::start(device),
+        last_status: Arc::new(std::sync::Mutex::new(status)),
+    };
+
+    let mut app = Router::new()
+        .route("/ws", get(ws_handler))
+        .merge(http::router());
+    if let Some(frontend_dir) = frontend_dir {
+        app = app
+            .fallback_service(ServeDir::new(frontend_dir).append_index_html_on_directories(true));
+    }
 
     let app = app
         // logging so we can see what's going on
@@ -52,15 +106,13 @@ pub async fn run(device: Device, interface: IpAddr, port: u16) -> anyhow::Result
             TraceLayer::new_for_http()
                 .make_span_with(DefaultMakeSpan::default().include_headers(true)),
         )
-        .layer(Extension(ServerState {
-            device: Arc::new(TokioMutex::new(device)),
-        }));
+        .layer(Extension(server_state));
 
     let socket_addr = SocketAddr::from((interface, port));
     let listener = tokio::net::TcpListener::bind(&socket_addr)
         .await
         .with_context(|| format!("Couldn't bind on {socket_addr}"))?;
-    tracing::debug!("listening on {}", listener.local_addr().unwrap());
+    eprintln!("Listening on http://{}", listener.local_addr()?);
     axum::serve(
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
@@ -74,9 +126,7 @@ async fn ws_handler(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     server_state: Extension<ServerState>,
 ) -> impl IntoResponse {
-    println!("{addr} connected");
-    // finalize the upgrade process by returning upgrade callback.
-    // we can customize the callback by sending additional info such as address.
+    tracing::info!("{addr} connected");
     ws.on_upgrade(move |socket| log_error(handle_socket(socket, addr, server_state.device.clone())))
 }
 
@@ -88,64 +138,13 @@ async fn log_error(result_fut: impl Future<Output = anyhow::Result<()>> + Send +
 
 // spawned per connection
 async fn handle_socket(
-    mut socket: WebSocket,
+    socket: WebSocket,
     who: SocketAddr,
-    device: Arc<TokioMutex<Device>>,
+    device: DeviceLock,
 ) -> anyhow::Result<()> {
-    socket
-        .send(Message::Ping(vec![1, 3, 3, 7]))
-        .await
-        .with_context(|| format!("Could not send ping to {who}"))?;
-    println!("Pinged {who}");
-
-    let (bg_streaming_enabled_sender, mut bg_streaming_enabled_receiver) = watch::channel(false);
-    let mut bg_receiver = device.lock().await.subscribe_to_background();
-
     let (mut ws_sender, mut ws_receiver) = socket.split();
     let (to_client_sender, mut to_client_receiver) = mpsc::channel::<api::ServerToClient>(4);
 
-    let device_status = device.lock().await.get_status().await?;
-    let max_light_level = device_status.max_light_level;
-
-    let mut bg_light_task: JoinHandle<anyhow::Result<()>> = tokio::spawn({
-        let device = device.clone();
-        let to_client_sender = to_client_sender.clone();
-        async move {
-            // 2 values per ms; 60 fps = 16.6ms/frame; 40 samples per buffer (=50hz) should be OK
-            let buffer_size = 40;
-            let mut buffer: Vec<u32> = Vec::with_capacity(buffer_size);
-            loop {
-                let enabled = *bg_streaming_enabled_receiver.borrow_and_update();
-                if !enabled {
-                    device.lock().await.background_disable();
-                    buffer.clear();
-                    if dbg!(bg_streaming_enabled_receiver.changed().await).is_ok() {
-                        continue;
-                    } else {
-                        println!("all bg_streaming_enabled_sender dropped");
-                        return Ok(());
-                    }
-                }
-                device.lock().await.background_enable();
-                let light_level = match bg_receiver.recv().await {
-                    Ok(x) => x,
-                    Err(RecvError::Lagged(_)) => continue,
-                    e @ Err(_) => e.context("bg_receiver has closed")?,
-                };
-                if buffer.len() == buffer_size {
-                    let avg_light_level =
-                        buffer.iter().map(|x| *x as f64).sum::<f64>() / buffer.len() as f64;
-                    let fraction = avg_light_level / max_light_level as f64;
-                    to_client_sender
-                        .send(api::ServerToClient::BackgroundLightLevel { avg: fraction })
-                        .await?;
-                    buffer.clear();
-                }
-                buffer.push(light_level);
-            }
-        }
-    });
-
     let mut send_task: JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
         while let Some(msg) = to_client_receiver.recv().await {
             ws_sender
@@ -155,278 +154,277 @@ async fn handle_socket(
         Ok(())
     });
 
-    // todo: recv_task errors (e.g. validation error, a mouse value too big to fit into i8)
-    //       somehow get swallowed, investigate
-    let mut recv_task: JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
-        let bg_streaming_enabled_sender = bg_streaming_enabled_sender;
-        while let Some(msg) = ws_receiver.next().await {
-            match msg? {
-                Message::Text(txt) => {
-                    let from_client: api::ClientToServer = serde_json::from_str(&txt)?;
-                    handle_message(
-                        from_client,
-                        device.as_ref(),
-                        &to_client_sender,
-                        &bg_streaming_enabled_sender,
-                    )
-                    .await?;
-                }
-                Message::Close(_) => return Ok(()),
-                // shouldn't happen
-                Message::Binary(_) => unimplemented!(),
-                // ignore
-                Message::Pong(_) => {}
-                // ignore, will be handled by axum
-                Message::Ping(_) => {}
-            }
-        }
-        println!("recv_task has nothing more to receive, exiting");
-        Ok(())
-    });
+    let mut connection = Connection {
+        who,
+        device,
+        to_client_sender,
+        monitor_task: None,
+    };
 
-    // If any one of the tasks exit, abort the other.
-    tokio::select! {
-        bg_light_join_result = &mut bg_light_task => {
-            send_task.abort();
-            recv_task.abort();
-            dbg!(bg_light_join_result)
-                .with_context(|| format!("Panic in a bg light level task of {who}"))?
-                .with_context(|| format!("Error in a bg light level task of {who}"))?;
+    // If either side exits, stop the other one
+    let result = tokio::select! {
+        recv_result = connection.receive_loop(&mut ws_receiver) => {
+            recv_result.with_context(|| format!("Error while receiving from {who}"))
         },
         send_join_result = &mut send_task => {
-            bg_light_task.abort();
-            recv_task.abort();
-            dbg!(send_join_result)
+            send_join_result
                 .with_context(|| format!("Panic in a websocket send task of {who}"))?
-                .with_context(|| format!("Error in a websocket send task of {who}"))?;
+                .with_context(|| format!("Error in a websocket send task of {who}"))
         },
-        recv_join_result = &mut recv_task => {
-            bg_light_task.abort();
-            send_task.abort();
-            // recv_join_result is swallowed here somehow?
-            dbg!(recv_join_result)
-                .with_context(|| format!("Panic in a websocket recv task of {who}"))?
-                .with_context(|| format!("Error in a websocket recv task of {who}"))?;
-        }
-    }
+    };
+    connection.stop_monitoring();
+    send_task.abort();
 
-    println!("Closing {who} websocket");
-    Ok(())
+    tracing::info!("Closing {who} websocket");
+    result
 }
 
-async fn handle_message(
-    msg: api::ClientToServer,
-    device: &TokioMutex<Device>,
-    to_client_sender: &mpsc::Sender<api::ServerToClient>,
-    bg_streaming_enabled_sender: &watch::Sender<bool>,
-) -> anyhow::Result<()> {
-    use api::ClientToServer as CTS;
-    match msg {
-        CTS::Status => {
-            let device_status = {
-                let mut device = device.lock().await;
-                device.get_status().await?
+struct Connection {
+    who: SocketAddr,
+    device: DeviceLock,
+    to_client_sender: mpsc::Sender<api::ServerToClient>,
+    monitor_task: Option<JoinHandle<()>>,
+}
+
+impl Connection {
+    async fn receive_loop(
+        &mut self,
+        ws_receiver: &mut SplitStream<WebSocket>,
+    ) -> anyhow::Result<()> {
+        while let Some(msg) = ws_receiver.next().await {
+            let result = match msg? {
+                Message::Text(txt) => match serde_json::from_str(&txt) {
+                    Ok(from_client) => self.handle_message(from_client).await,
+                    Err(e) => Err(anyhow!(e).context("Can't parse the message")),
+                },
+                Message::Close(_) => return Ok(()),
+                Message::Binary(_) => Err(anyhow!("Binary messages are not supported")),
+                // ignore, pings are answered by axum
+                Message::Ping(_) | Message::Pong(_) => Ok(()),
             };
-            to_client_sender
-                .send(api::ServerToClient::Status {
-                    version: api::Version {
-                        hardware: device_status.version.hardware,
-                        firmware: device_status.version.firmware,
-                    },
-                    max_light_level: device_status.max_light_level,
-                })
-                .await?;
-        }
-        CTS::StartMonitoring => {
-            bg_streaming_enabled_sender.send(true)?;
-        }
-        CTS::StopMonitoring => {
-            bg_streaming_enabled_sender.send(false)?;
-        }
-        CTS::SendHidReport { hid_report } => {
-            let mut device = device.lock().await;
-            device.send_hid_report(&hid_report).await?;
-        }
-        CTS::Measure {
-            before,
-            duration_ms,
-            start,
-            followup,
-            after,
-        } => {
-            let (max_light_level, measurements) = {
-                let mut device = device.lock().await;
-
-                let status = device.get_status().await?;
-
-                for report in before {
-                    device.send_hid_report(&report).await?;
-                }
 
-                let measurements = device
-                    .measure(
-                        duration_ms,
-                        &start,
-                        followup.map(|f| (f.after_ms, f.hid_report)),
-                    )
+            // errors are reported to the client instead of dropping the connection
+            if let Err(e) = result {
+                tracing::warn!("Error while handling a message from {}: {e:?}", self.who);
+                self.to_client_sender
+                    .send(api::ServerToClient::Error {
+                        message: format!("{e:#}"),
+                    })
                     .await?;
+            }
+        }
+        Ok(())
+    }
 
-                for report in after {
-                    device.send_hid_report(&report).await?;
+    async fn handle_message(&mut self, msg: api::ClientToServer) -> anyhow::Result<()> {
+        use api::ClientToServer as CTS;
+        match msg {
+            CTS::Status => {
+                let status = self.device.lock().await.get_status().await?;
+                self.to_client_sender
+                    .send(api::ServerToClient::Status {
+                        version: api::Version {
+                            hardware: status.hardware_version,
+                            firmware: status.firmware_version,
+                        },
+                        serial_number: status.serial_number,
+                        max_light_level: status.max_light_level,
+                        last_panic_message: status.last_panic_message,
+                    })
+                    .await?;
+            }
+            CTS::StartMonitoring => {
+                if self.monitor_task.as_ref().is_some_and(|t| !t.is_finished()) {
+                    return Ok(());
                 }
+                let device = self.device.clone();
+                let to_client_sender = self.to_client_sender.clone();
+                self.monitor_task = Some(tokio::spawn(async move {
+                    if let Err(e) = monitor(device, to_client_sender.clone()).await {
+                        let message = format!("{:#}", e.context("Light level monitoring stopped"));
+                        let _ = to_client_sender
+                            .send(api::ServerToClient::Error { message })
+                            .await;
+                    }
+                }));
+            }
+            CTS::StopMonitoring => self.stop_monitoring(),
+            CTS::SendHidReport { hid_report } => {
+                self.device
+                    .lock()
+                    .await
+                    .send_hid_report(&hid_report)
+                    .await?;
+            }
+            CTS::Measure {
+                before,
+                duration_ms,
+                start,
+                followup,
+                after,
+            } => {
+                let scenario = measure_scenario(duration_ms, start, followup, after);
+                let recordings = {
+                    let device = self.device.lock().await;
+
+                    for report in before {
+                        device.send_hid_report(&report).await?;
+                    }
 
-                (status.max_light_level, measurements)
-            };
-
-            let processed = ProcessedMeasurements::new(&measurements)?;
+                    device
+                        .run_scenario(scenario)
+                        .await?
+                        .try_collect::<Vec<_>>()
+                        .await?
+                };
 
-            to_client_sender
-                .send(api::ServerToClient::Measurement {
-                    max_light_level,
-                    light_levels: processed.light_levels,
-                    followup_hid_us: processed.followup_hid_us,
-                    change_us: processed.change_us,
-                })
-                .await?;
+                for recording in recordings {
+                    self.to_client_sender.send(measurement(recording)).await?;
+                }
+            }
         }
+        Ok(())
     }
-    Ok(())
-}
 
-// realigns light_levels so that the start HID event = 0
-struct ProcessedMeasurements {
-    /// microsecond, light level
-    light_levels: Vec<(u32, u32)>,
-    followup_hid_us: Option<u32>,
-    change_us: Option<u32>,
+    fn stop_monitoring(&mut self) {
+        if let Some(task) = self.monitor_task.take() {
+            // dropping the light level stream stops the keepalive
+            task.abort();
+        }
+    }
 }
 
-impl ProcessedMeasurements {
-    fn new(measurements: &[Measurement]) -> anyhow::Result<Self> {
-        use late_mate_shared::comms::device_to_host::MeasurementEvent as ME;
-
-        let (first_hid_idx, first_hid_time) = measurements
-            .iter()
-            .enumerate()
-            .find_map(|(idx, m)| match m.event {
-                ME::LightLevel(_) => None,
-                ME::HidReport(_) => Some((idx, m.microsecond)),
-            })
-            .context("No HID report in returned measurements")?;
-
-        let followup_hid_us =
-            measurements
-                .iter()
-                .skip(first_hid_idx + 1)
-                .find_map(|m| match m.event {
-                    ME::LightLevel(_) => None,
-                    ME::HidReport(_) => Some(m.microsecond - first_hid_time),
-                });
-
-        let light_levels = measurements
-            .iter()
-            .skip(first_hid_idx + 1)
-            .filter_map(|m| match m.event {
-                ME::LightLevel(l) => Some((m.microsecond - first_hid_time, l)),
-                ME::HidReport(_) => None,
-            })
-            .collect::<Vec<_>>();
-
-        // todo: For some reason (channel shenanigans?), sometimes I get a "tail" (?)
-        //       in the beginning of the values (a tuple with an unreasonably high time).
-        //       This just filters out the tail
-        let mut filtered_light_levels = Vec::with_capacity(light_levels.len());
-        let mut last_time = light_levels.last().unwrap().0;
-        let initial_light_levels_len = light_levels.len();
-        for entry @ (time, _) in light_levels.into_iter().rev() {
-            if time <= last_time {
-                filtered_light_levels.push(entry)
-            }
-            last_time = time;
+/// Pauses while a measurement or a run uses the device, and resumes after it
+async fn monitor(
+    device_lock: DeviceLock,
+    to_client_sender: mpsc::Sender<api::ServerToClient>,
+) -> anyhow::Result<()> {
+    let mut buffer: Vec<u32> = Vec::with_capacity(MONITOR_BUFFER_SIZE);
+    loop {
+        let device = device_lock.lock_for_monitoring().await;
+        if device.max_light_level == 0 {
+            return Err(anyhow!("The device didn't report its maximum light level"));
         }
-        filtered_light_levels.reverse();
-
-        let filtered_out = initial_light_levels_len - filtered_light_levels.len();
-        if filtered_out > 0 {
-            dbg!(filtered_out);
+        let max_light_level = f64::from(device.max_light_level);
+
+        // the stream is dropped before the lock is released, which stops the keepalive
+        let stream = device.stream_light_level().await?;
+        tokio::pin!(stream);
+        let stepped_aside = device_lock.stepped_aside();
+        tokio::pin!(stepped_aside);
+
+        loop {
+            let light_level = tokio::select! {
+                _ = &mut stepped_aside => break,
+                light_level = stream.try_next() => light_level?
+                    .ok_or_else(|| anyhow!("Light level stream ended unexpectedly"))?,
+            };
+            buffer.push(light_level);
+            if buffer.len() == MONITOR_BUFFER_SIZE {
+                let avg_light_level =
+                    buffer.iter().map(|x| f64::from(*x)).sum::<f64>() / buffer.len() as f64;
+                to_client_sender
+                    .send(api::ServerToClient::BackgroundLightLevel {
+                        avg: avg_light_level / max_light_level,
+                    })
+                    .await?;
+                buffer.clear();
+            }
         }
+    }
+}
 
-        let change_us = find_changepoint(&filtered_light_levels);
-
-        Ok(ProcessedMeasurements {
-            light_levels: filtered_light_levels,
-            followup_hid_us,
-            change_us,
-        })
+/// The frontend's measurement: timing starts right before `start` is sent
+/// and lasts for `duration_ms`, with an optional followup report in between.
+/// `after` reports are the revert, they release whatever the test leaves held
+fn measure_scenario(
+    duration_ms: u16,
+    start: HidReport,
+    followup: Option<api::Followup>,
+    after: Vec<HidReport>,
+) -> Scenario {
+    let mut test = vec![ScenarioStep::StartTiming, ScenarioStep::HidReport(start)];
+    let mut remaining_ms = duration_ms;
+    if let Some(api::Followup {
+        after_ms,
+        hid_report,
+    }) = followup
+    {
+        test.push(ScenarioStep::Wait { ms: after_ms });
+        test.push(ScenarioStep::HidReport(hid_report));
+        remaining_ms = remaining_ms.saturating_sub(after_ms);
+    }
+    test.push(ScenarioStep::Wait { ms: remaining_ms });
+
+    Scenario {
+        test,
+        revert: Some(Revert::Steps(
+            after.into_iter().map(ScenarioStep::HidReport).collect(),
+        )),
+        // a single repeat, the delay isn't used
+        ..Default::default()
     }
 }
 
-fn find_changepoint(light_levels: &[(u32, u32)]) -> Option<u32> {
-    // it's unlikely there's any meaningful change in the first 7ms,
-    // so I use it to infer the range of noise
-    let noise_window = 7_000;
-    // require at least 2 noise ranges between start and end to detect change
-    let change_detect_gap_multiplier = 2;
-    // but for the actual moment of change, use just one noise range
-    let change_gap_multiplier = 1;
+fn measurement(recording: Recording) -> api::ServerToClient {
+    let processed = process_recording(recording, &Detector::default(), Interpolation::default());
+    let timeline = &processed.recording.timeline;
 
-    let (start_min, start_max) = light_levels
+    let followup_hid_us = timeline
         .iter()
-        .copied()
-        .take_while(|(us, _)| *us < noise_window)
-        .fold((u32::MAX, 0u32), |(min, max), (_, light_level)| {
-            (light_level.min(min), light_level.max(max))
-        });
-
-    // dbg!(start_min, start_max);
-
-    let last_time = light_levels
-        .last()
-        .expect("light_levels shouldn't be empty at this point")
-        .0;
-
-    let (end_min, end_max) = light_levels
+        .filter(|m| matches!(m.event, Event::HidReport(_)))
+        .nth(1)
+        .map(|m| m.microsecond);
+    let light_levels = timeline
         .iter()
-        .rev()
-        .copied()
-        .take_while(|(us, _)| *us > (last_time - noise_window))
-        .fold((u32::MAX, 0u32), |(min, max), (_, light_level)| {
-            (light_level.min(min), light_level.max(max))
-        });
-
-    let change_detect_gap = (start_max - start_min) * change_detect_gap_multiplier;
-
-    if !(end_min > (start_max + change_detect_gap) || start_min > (end_max + change_detect_gap)) {
-        return None;
+        .filter_map(|m| m.to_light_level().map(|l| (m.microsecond, l)))
+        .collect();
+
+    api::ServerToClient::Measurement {
+        max_light_level: processed.recording.max_light_level,
+        light_levels,
+        followup_hid_us,
+        change_us: processed.changepoint_us,
     }
+}
 
-    let change_gap = (start_max - start_min) * change_gap_multiplier;
-    let change_point = if end_min > start_max {
-        // raising signal
-        let threshold = start_max + change_gap;
-        light_levels.iter().copied().find_map(|(us, light_level)| {
-            if light_level > threshold {
-                Some(us)
-            } else {
-                None
-            }
-        })
-    } else {
-        // dropping signal, non-changing signal is already discarded above
-        // there must be enough between the ends for this sum to be always positive
-        assert!(
-            start_min > change_gap,
-            "expected started_min ({start_min}) > change_gap ({change_gap})"
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use late_mate_device::hid::{KeyboardKey, KeyboardReport};
+    use late_mate_device::scenario::ValidationError;
+
+    #[test]
+    fn test_measure_scenario() {
+        let press = HidReport::Keyboard(KeyboardReport {
+            modifiers: vec![],
+            pressed_keys: vec![KeyboardKey::A],
+        });
+        let followup = api::Followup {
+            after_ms: 1,
+            hid_report: HidReport::Keyboard(KeyboardReport::default()),
+        };
+
+        let scenario = measure_scenario(150, press.clone(), Some(followup), vec![]);
+        scenario.validate().unwrap();
+        assert_eq!(scenario.lints(), []);
+        assert_eq!(
+            scenario.test[2..],
+            [
+                ScenarioStep::Wait { ms: 1 },
+                ScenarioStep::HidReport(HidReport::Keyboard(KeyboardReport::default())),
+                ScenarioStep::Wait { ms: 149 },
+            ]
         );
-        let threshold = start_min - change_gap;
-        light_levels.iter().copied().find_map(|(us, light_level)| {
-            if light_level < threshold {
-                Some(us)
-            } else {
-                None
-            }
-        })
-    };
 
-    Some(change_point.expect("the signal must cross the threshold given the above"))
+        // without a followup the key is only released by the "after" reports
+        let release = HidReport::Keyboard(KeyboardReport::default());
+        measure_scenario(150, press.clone(), None, vec![release])
+            .validate()
+            .unwrap();
+        assert!(matches!(
+            measure_scenario(150, press, None, vec![]).validate(),
+            Err(ValidationError::HeldAfterRevert)
+        ));
+    }
 }
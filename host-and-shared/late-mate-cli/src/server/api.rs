@@ -1,43 +1,43 @@
-use crate::nice_hid;
+use late_mate_device::hid;
 
 #[derive(Debug, Eq, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS)]
 pub struct Followup {
     pub after_ms: u16,
-    pub hid_report: nice_hid::HidReport,
+    pub hid_report: hid::HidReport,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS)]
-#[ts(export)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ClientToServer {
     Status,
     StartMonitoring,
     StopMonitoring,
     SendHidReport {
-        hid_report: nice_hid::HidReport,
+        hid_report: hid::HidReport,
     },
     Measure {
-        before: Vec<nice_hid::HidReport>,
+        before: Vec<hid::HidReport>,
         duration_ms: u16,
-        start: nice_hid::HidReport,
+        start: hid::HidReport,
         followup: Option<Followup>,
-        after: Vec<nice_hid::HidReport>,
+        after: Vec<hid::HidReport>,
     },
 }
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS)]
+#[derive(Debug, Eq, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS)]
 pub struct Version {
-    pub hardware: u8,
-    pub firmware: u32,
+    pub hardware: String,
+    pub firmware: String,
 }
 
 #[derive(Debug, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS)]
-#[ts(export)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ServerToClient {
     Status {
         version: Version,
+        serial_number: String,
         max_light_level: u32,
+        last_panic_message: Option<String>,
     },
     BackgroundLightLevel {
         avg: f64,
@@ -49,4 +49,8 @@ pub enum ServerToClient {
         followup_hid_us: Option<u32>,
         change_us: Option<u32>,
     },
+    /// A request couldn't be completed, the connection stays open
+    Error {
+        message: String,
+    },
 }
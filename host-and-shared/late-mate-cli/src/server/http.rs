@@ -0,0 +1,144 @@
+//! JSON API for automation, e.g. CI agents that don't run on the same machine as the device:
+//!
+//! - `POST /runs` with a scenario as the body queues a run
+//! - `GET /runs` lists runs, `GET /runs/:id` returns status, progress and statistics
+//! - `GET /runs/:id/recordings/:idx` returns the raw timeline of a single repeat, only the
+//!   newest finished runs keep them
+//! - `DELETE /runs/:id` cancels a queued or running run, or removes a finished one
+//! - `GET /device/status` returns the device status
+
+use super::runs::{DeleteError, RunId, RunInfo};
+use super::ServerState;
+use crate::cli::scenario::run::deserialize;
+use axum::extract::Path;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Extension, Json, Router};
+use late_mate_device::Status;
+use serde_json::Value;
+
+pub fn router() -> Router {
+    Router::new()
+        .route("/runs", get(list_runs).post(submit_run))
+        .route("/runs/:id", get(get_run).delete(delete_run))
+        .route("/runs/:id/recordings/:idx", get(get_recording))
+        .route("/device/status", get(device_status))
+}
+
+#[derive(Debug)]
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+fn run_not_found(id: RunId) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("Run {id} doesn't exist"))
+}
+
+async fn submit_run(
+    Extension(state): Extension<ServerState>,
+    Json(document): Json<Value>,
+) -> Result<(StatusCode, Json<RunInfo>), ApiError> {
+    let scenario = deserialize::scenario(document.clone(), &document)
+        .map_err(|e| ApiError(StatusCode::UNPROCESSABLE_ENTITY, format!("{e:#}")))?;
+    scenario
+        .validate()
+        .map_err(|e| ApiError(StatusCode::UNPROCESSABLE_ENTITY, e.to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(state.runs.submit(scenario))))
+}
+
+async fn list_runs(Extension(state): Extension<ServerState>) -> Json<Vec<RunInfo>> {
+    Json(state.runs.list())
+}
+
+async fn get_run(
+    Extension(state): Extension<ServerState>,
+    Path(id): Path<RunId>,
+) -> Result<Json<RunInfo>, ApiError> {
+    state
+        .runs
+        .info(id)
+        .map(Json)
+        .ok_or_else(|| run_not_found(id))
+}
+
+async fn get_recording(
+    Extension(state): Extension<ServerState>,
+    Path((id, idx)): Path<(RunId, usize)>,
+) -> Result<Json<Value>, ApiError> {
+    if state.runs.info(id).is_none() {
+        return Err(run_not_found(id));
+    }
+    state.runs.recording(id, idx).map(Json).ok_or_else(|| {
+        ApiError(
+            StatusCode::NOT_FOUND,
+            format!("Run {id} doesn't have a recording {idx} (yet or anymore)"),
+        )
+    })
+}
+
+async fn delete_run(
+    Extension(state): Extension<ServerState>,
+    Path(id): Path<RunId>,
+) -> Result<Response, ApiError> {
+    match state.runs.delete(id) {
+        Ok(Some(info)) => Ok(Json(info).into_response()),
+        Ok(None) => Ok(StatusCode::NO_CONTENT.into_response()),
+        Err(DeleteError::NotFound(id)) => Err(run_not_found(id)),
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct DeviceStatus {
+    hardware_version: String,
+    firmware_version: String,
+    serial_number: String,
+    max_light_level: u32,
+    last_panic_message: Option<String>,
+    /// The device is used by a run or a websocket client, the status might be out of date
+    busy: bool,
+    /// Runs waiting for the device, excluding the running one
+    queued_runs: usize,
+}
+
+async fn device_status(
+    Extension(state): Extension<ServerState>,
+) -> Result<Json<DeviceStatus>, ApiError> {
+    // don't wait for runs to complete, report the last known status instead
+    let (status, busy) = match state.device.try_lock() {
+        Some(mut device) => {
+            let status = device
+                .get_status()
+                .await
+                .map_err(|e| ApiError(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+            *state
+                .last_status
+                .lock()
+                .expect("Status lock must not be poisoned") = status.clone();
+            (status, false)
+        }
+        None => {
+            let status: Status = state
+                .last_status
+                .lock()
+                .expect("Status lock must not be poisoned")
+                .clone();
+            (status, true)
+        }
+    };
+
+    Ok(Json(DeviceStatus {
+        hardware_version: status.hardware_version,
+        firmware_version: status.firmware_version,
+        serial_number: status.serial_number,
+        max_light_level: status.max_light_level,
+        last_panic_message: status.last_panic_message,
+        busy,
+        queued_runs: state.runs.queue_length(),
+    }))
+}
@@ -0,0 +1,442 @@
+//! Scenario runs submitted over HTTP. They are queued and executed one at a time,
+//! holding the device for the whole run
+
+use super::DeviceLock;
+use crate::statistics::{
+    process_measurements, process_recording, Measurement, ProcessedRecording, StatsSummary,
+};
+use futures::{TryStream, TryStreamExt};
+use late_mate_device::scenario::{Recording, Scenario};
+use late_mate_device::Device;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot};
+
+pub type RunId = u64;
+
+/// Finished runs are kept in memory until deleted, the oldest ones are dropped beyond this
+const MAX_FINISHED_RUNS: usize = 100;
+
+/// Only the newest finished runs keep their raw recordings, they take most of the memory
+const MAX_FINISHED_RUNS_WITH_RECORDINGS: usize = 10;
+
+/// Statistics of a running run are refreshed at most this often, bootstrapping gets slower
+/// with every repeat
+const STATS_REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Queued,
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+impl RunStatus {
+    fn is_finished(self) -> bool {
+        !matches!(self, RunStatus::Queued | RunStatus::Running)
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RunInfo {
+    pub id: RunId,
+    pub status: RunStatus,
+    pub error: Option<String>,
+    pub repeats: u16,
+    pub completed_repeats: usize,
+    pub changepoints_us: Vec<Option<u32>>,
+    pub stats: StatsSummary,
+}
+
+#[derive(Debug)]
+struct Run {
+    status: RunStatus,
+    error: Option<String>,
+    scenario: Scenario,
+    measurements: Vec<Measurement>,
+    /// Computing it is too slow to do on every request, see `Runs::update_stats`
+    stats: StatsSummary,
+    recordings: Vec<Recording>,
+    // both are taken out when the run starts or is cancelled
+    cancel_sender: Option<oneshot::Sender<()>>,
+    cancel_receiver: Option<oneshot::Receiver<()>>,
+}
+
+impl Run {
+    fn info(&self, id: RunId) -> RunInfo {
+        RunInfo {
+            id,
+            status: self.status,
+            error: self.error.to_owned(),
+            repeats: self.scenario.repeats,
+            completed_repeats: self.recordings.len(),
+            changepoints_us: self.measurements.iter().map(|m| m.changepoint_us).collect(),
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: RunId,
+    runs: BTreeMap<RunId, Run>,
+}
+
+impl State {
+    fn prune(&mut self) {
+        let finished = self
+            .runs
+            .iter()
+            .filter(|(_, run)| run.status.is_finished())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        let excess = finished.len().saturating_sub(MAX_FINISHED_RUNS);
+        for id in &finished[..excess] {
+            self.runs.remove(id);
+        }
+
+        let with_recordings = finished.len() - excess;
+        let excess = with_recordings.saturating_sub(MAX_FINISHED_RUNS_WITH_RECORDINGS);
+        for id in &finished[finished.len() - with_recordings..][..excess] {
+            if let Some(run) = self.runs.get_mut(id) {
+                run.recordings = vec![];
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Runs {
+    state: Arc<Mutex<State>>,
+    queue: mpsc::UnboundedSender<RunId>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DeleteError {
+    #[error("Run {0} doesn't exist")]
+    NotFound(RunId),
+}
+
+impl Runs {
+    /// Starts the worker that executes queued runs
+    pub fn start(device: DeviceLock) -> Self {
+        let (queue, queue_receiver) = mpsc::unbounded_channel();
+        let runs = Self {
+            state: Arc::default(),
+            queue,
+        };
+        tokio::spawn(worker_loop(runs.clone(), device, queue_receiver));
+        runs
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .expect("Runs state lock must not be poisoned")
+    }
+
+    /// The scenario must be already validated
+    pub fn submit(&self, scenario: Scenario) -> RunInfo {
+        let (cancel_sender, cancel_receiver) = oneshot::channel();
+        let mut state = self.state();
+        let id = state.next_id;
+        state.next_id += 1;
+
+        let run = Run {
+            status: RunStatus::Queued,
+            error: None,
+            scenario,
+            measurements: vec![],
+            stats: StatsSummary::default(),
+            recordings: vec![],
+            cancel_sender: Some(cancel_sender),
+            cancel_receiver: Some(cancel_receiver),
+        };
+        let info = run.info(id);
+        state.runs.insert(id, run);
+        state.prune();
+
+        self.queue
+            .send(id)
+            .expect("The run worker must be alive while the server is running");
+        info
+    }
+
+    pub fn list(&self) -> Vec<RunInfo> {
+        self.state()
+            .runs
+            .iter()
+            .map(|(id, run)| run.info(*id))
+            .collect()
+    }
+
+    pub fn info(&self, id: RunId) -> Option<RunInfo> {
+        self.state().runs.get(&id).map(|run| run.info(id))
+    }
+
+    /// None if either the run or the recording doesn't exist
+    pub fn recording(&self, id: RunId, idx: usize) -> Option<serde_json::Value> {
+        let state = self.state();
+        let recording = state.runs.get(&id)?.recordings.get(idx)?;
+        Some(serde_json::to_value(recording).expect("Recordings must be serialisable"))
+    }
+
+    /// Active runs are cancelled, finished ones are removed
+    pub fn delete(&self, id: RunId) -> Result<Option<RunInfo>, DeleteError> {
+        let mut state = self.state();
+        let run = state.runs.get_mut(&id).ok_or(DeleteError::NotFound(id))?;
+
+        if run.status.is_finished() {
+            state.runs.remove(&id);
+            return Ok(None);
+        }
+
+        run.status = RunStatus::Cancelled;
+        if let Some(cancel_sender) = run.cancel_sender.take() {
+            // the receiver is gone if the run has just finished, it's cancelled anyway
+            let _ = cancel_sender.send(());
+        }
+        Ok(Some(run.info(id)))
+    }
+
+    pub fn queue_length(&self) -> usize {
+        self.state()
+            .runs
+            .values()
+            .filter(|run| run.status == RunStatus::Queued)
+            .count()
+    }
+
+    /// Marks the run as running, None if it was cancelled while queued
+    fn begin(&self, id: RunId) -> Option<(Scenario, oneshot::Receiver<()>)> {
+        let mut state = self.state();
+        let run = state.runs.get_mut(&id)?;
+        if run.status != RunStatus::Queued {
+            return None;
+        }
+        run.status = RunStatus::Running;
+        let cancel_receiver = run
+            .cancel_receiver
+            .take()
+            .expect("Queued runs must have a cancel receiver");
+        Some((run.scenario.clone(), cancel_receiver))
+    }
+
+    fn push_recording(&self, id: RunId, processed: ProcessedRecording) {
+        if let Some(run) = self.state().runs.get_mut(&id) {
+            run.measurements.push(processed.measurement());
+            run.recordings.push(processed.recording);
+        }
+    }
+
+    /// Bootstrapping the confidence intervals takes a while, it's done outside of the lock
+    /// and without blocking the runtime
+    async fn update_stats(&self, id: RunId) {
+        let Some(measurements) = self
+            .state()
+            .runs
+            .get(&id)
+            .map(|run| run.measurements.clone())
+        else {
+            return;
+        };
+
+        let stats =
+            tokio::task::spawn_blocking(move || process_measurements(&measurements).summary())
+                .await
+                .expect("Computing statistics must not panic");
+        if let Some(run) = self.state().runs.get_mut(&id) {
+            run.stats = stats;
+        }
+    }
+
+    fn finish(&self, id: RunId, result: anyhow::Result<()>) {
+        let mut state = self.state();
+        let Some(run) = state.runs.get_mut(&id) else {
+            return;
+        };
+        // cancelled runs stay cancelled
+        if run.status == RunStatus::Running {
+            match result {
+                Ok(()) => run.status = RunStatus::Completed,
+                Err(e) => {
+                    run.status = RunStatus::Failed;
+                    run.error = Some(format!("{e:#}"));
+                }
+            }
+        }
+        run.cancel_sender = None;
+        state.prune();
+    }
+}
+
+async fn worker_loop(
+    runs: Runs,
+    device: DeviceLock,
+    mut queue_receiver: mpsc::UnboundedReceiver<RunId>,
+) {
+    while let Some(id) = queue_receiver.recv().await {
+        let Some((scenario, cancel_receiver)) = runs.begin(id) else {
+            continue;
+        };
+
+        tracing::info!("Starting run {id}");
+        let device = device.lock().await;
+        let result = execute(&runs, id, &device, scenario, cancel_receiver).await;
+        drop(device);
+        if let Err(e) = &result {
+            tracing::warn!("Run {id} failed: {e:?}");
+        }
+        runs.update_stats(id).await;
+        runs.finish(id, result);
+    }
+}
+
+async fn execute(
+    runs: &Runs,
+    id: RunId,
+    device: &Device,
+    scenario: Scenario,
+    cancel_receiver: oneshot::Receiver<()>,
+) -> anyhow::Result<()> {
+    let recordings = device.run_scenario_open_ended(scenario.clone()).await?;
+    collect_recordings(runs, id, recordings, &scenario, cancel_receiver).await
+}
+
+/// Polls for one repeat at a time and only checks for cancellation in between, so that
+/// the repeat in progress still gets reverted before the worker releases the device
+async fn collect_recordings<S>(
+    runs: &Runs,
+    id: RunId,
+    mut recordings: S,
+    scenario: &Scenario,
+    mut cancel_receiver: oneshot::Receiver<()>,
+) -> anyhow::Result<()>
+where
+    S: TryStream<Ok = Recording, Error = late_mate_device::Error> + Unpin,
+{
+    // warmups aren't kept, recordings are indexed like the measurements
+    let warmup = u32::from(scenario.warmup);
+    let mut stats_updated_at = Instant::now();
+    for idx in 0..warmup + u32::from(scenario.repeats) {
+        // either cancelled or deleted
+        if cancel_receiver.try_recv().is_ok() {
+            break;
+        }
+        let Some(recording) = recordings.try_next().await? else {
+            break;
+        };
+        if idx >= warmup {
+            let processed =
+                process_recording(recording, &scenario.detector, scenario.interpolation);
+            runs.push_recording(id, processed);
+            if stats_updated_at.elapsed() >= STATS_REFRESH_INTERVAL {
+                runs.update_stats(id).await;
+                stats_updated_at = Instant::now();
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(status: RunStatus) -> Run {
+        Run {
+            status,
+            error: None,
+            scenario: Scenario::default(),
+            measurements: vec![],
+            stats: StatsSummary::default(),
+            recordings: vec![Recording {
+                max_light_level: 1000,
+                timeline: vec![],
+            }],
+            cancel_sender: None,
+            cancel_receiver: None,
+        }
+    }
+
+    fn runs() -> (Runs, mpsc::UnboundedReceiver<RunId>) {
+        let (queue, queue_receiver) = mpsc::unbounded_channel();
+        let runs = Runs {
+            state: Arc::default(),
+            queue,
+        };
+        (runs, queue_receiver)
+    }
+
+    #[tokio::test]
+    async fn test_cancel_running_run() {
+        let (runs, _queue_receiver) = runs();
+        let scenario = Scenario {
+            warmup: 1,
+            repeats: 10,
+            ..Default::default()
+        };
+        let id = runs.submit(scenario).id;
+        let (scenario, cancel_receiver) = runs.begin(id).unwrap();
+
+        // the run is cancelled while the third repeat (the second after the warmup) is running
+        let polled = Arc::new(Mutex::new(0));
+        let recordings = futures::stream::repeat_with(|| {
+            let mut polled = polled.lock().unwrap();
+            *polled += 1;
+            if *polled == 3 {
+                runs.delete(id).unwrap();
+            }
+            Ok(Recording {
+                max_light_level: 1000,
+                timeline: vec![],
+            })
+        });
+        collect_recordings(&runs, id, recordings, &scenario, cancel_receiver)
+            .await
+            .unwrap();
+        runs.update_stats(id).await;
+        runs.finish(id, Ok(()));
+
+        assert_eq!(*polled.lock().unwrap(), 3);
+        let info = runs.info(id).unwrap();
+        assert_eq!(runs.queue_length(), 0);
+        assert_eq!(info.status, RunStatus::Cancelled);
+        assert_eq!(info.completed_repeats, 2);
+        assert_eq!(info.stats.failed, 2);
+    }
+
+    #[test]
+    fn test_prune_keeps_active_runs() {
+        let mut state = State::default();
+        state.runs.insert(0, run(RunStatus::Queued));
+        for id in 1..=MAX_FINISHED_RUNS as RunId + 2 {
+            state.runs.insert(id, run(RunStatus::Completed));
+        }
+        state.prune();
+
+        assert_eq!(state.runs.len(), MAX_FINISHED_RUNS + 1);
+        assert!(state.runs.contains_key(&0));
+        assert!(!state.runs.contains_key(&1));
+        assert!(!state.runs.contains_key(&2));
+
+        // active runs keep their recordings too
+        let last = MAX_FINISHED_RUNS as RunId + 2;
+        let with_recordings = state
+            .runs
+            .iter()
+            .filter(|(_, run)| !run.recordings.is_empty())
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            with_recordings,
+            std::iter::once(0)
+                .chain(last + 1 - MAX_FINISHED_RUNS_WITH_RECORDINGS as RunId..=last)
+                .collect::<Vec<_>>()
+        );
+    }
+}
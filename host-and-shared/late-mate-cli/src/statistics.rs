@@ -1,126 +1,372 @@
-use late_mate_device::scenario::{Moment, Recording};
+mod changepoint;
+mod flicker;
+mod interpolation;
+mod response;
+mod transitions;
+
+pub use changepoint::parse_detector;
+pub use flicker::{analyze as analyze_flicker, Flicker};
+pub use interpolation::{parse_interpolation, Estimate};
+use late_mate_device::scenario::{Detector, Interpolation, Recording, ThresholdParams};
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+pub use response::Response;
+pub use transitions::Transition;
 
 #[derive(Debug)]
 pub struct ProcessedRecording {
     pub recording: Recording,
+    /// Sub-sample estimate rounded to microseconds
     pub changepoint_us: Option<u32>,
+    pub changepoint: Option<Estimate>,
+    pub response: Option<Response>,
+    /// All level transitions, there are several of them e.g. while scrolling
+    pub transitions: Vec<Transition>,
 }
 
-fn find_changepoint(timeline: &[Moment]) -> Option<u32> {
-    // it's unlikely there's any meaningful change in the first 7ms,
-    // so I use it to infer the range of noise
-    let noise_window = 7_000;
-    // require at least 2 noise ranges between start and end to detect change
-    let change_detect_gap_multiplier = 2;
-    // but for the actual moment of change, use just one noise range
-    let change_gap_multiplier = 1;
-
-    let (start_min, start_max) = timeline
-        .iter()
-        .take_while(|m| m.microsecond < noise_window)
-        .filter_map(Moment::to_light_level)
-        .fold((u32::MAX, 0u32), |(min, max), light_level| {
-            (light_level.min(min), light_level.max(max))
-        });
-
-    let last_time = timeline
-        .last()
-        .expect("light_levels shouldn't be empty at this point")
-        .microsecond;
-
-    let (end_min, end_max) = timeline
-        .iter()
-        .rev()
-        .take_while(|m| m.microsecond > (last_time - noise_window))
-        .filter_map(Moment::to_light_level)
-        .fold((u32::MAX, 0u32), |(min, max), light_level| {
-            (light_level.min(min), light_level.max(max))
-        });
-
-    let change_detect_gap = (start_max - start_min) * change_detect_gap_multiplier;
-
-    if !(end_min > (start_max + change_detect_gap) || start_min > (end_max + change_detect_gap)) {
-        return None;
-    }
-
-    let change_gap = (start_max - start_min) * change_gap_multiplier;
-    if end_min > start_max {
-        // raising signal
-        let threshold = start_max + change_gap;
-        for moment in timeline.iter() {
-            if moment
-                .to_light_level()
-                .is_some_and(|value| value > threshold)
-            {
-                return Some(moment.microsecond);
-            }
+impl ProcessedRecording {
+    pub fn measurement(&self) -> Measurement {
+        Measurement {
+            changepoint_us: self.changepoint_us,
+            response: self.response,
+            n_transitions: self.transitions.len(),
+            transition_intervals_us: self
+                .transitions
+                .iter()
+                .filter_map(|t| t.interval_us)
+                .collect(),
         }
-    } else {
-        // dropping signal, non-changing signal is already discarded above
-        // there must be enough between the ends for this sum to be always positive
-        assert!(
-            start_min > change_gap,
-            "expected started_min ({start_min}) > change_gap ({change_gap})"
-        );
-        let threshold = start_min - change_gap;
-        for moment in timeline.iter() {
-            if moment
-                .to_light_level()
-                .is_some_and(|value| value < threshold)
-            {
-                return Some(moment.microsecond);
-            }
-        }
-    };
+    }
+}
 
-    unreachable!("the signal must cross the threshold given the above")
+/// What's kept from every repeat for the final statistics
+#[derive(Debug, Default, Clone)]
+pub struct Measurement {
+    pub changepoint_us: Option<u32>,
+    pub response: Option<Response>,
+    pub n_transitions: usize,
+    pub transition_intervals_us: Vec<u32>,
 }
 
-pub fn process_recording(recording: Recording) -> ProcessedRecording {
-    let changepoint_us = find_changepoint(&recording.timeline);
+pub fn process_recording(
+    recording: Recording,
+    detector: &Detector,
+    interpolation: Interpolation,
+) -> ProcessedRecording {
+    let detected = changepoint::detector(detector).find_changepoint(&recording.timeline);
+    let changepoint = detected.map(|changepoint| {
+        interpolation::estimate(&recording.timeline, &changepoint, interpolation)
+    });
+    let response =
+        detected.and_then(|changepoint| response::measure(&recording.timeline, &changepoint));
+    let noise_window_ms = detector
+        .noise_window_ms()
+        .unwrap_or(ThresholdParams::default().noise_window_ms);
+    let transitions =
+        transitions::find_transitions(&recording.timeline, noise_window_ms.saturating_mul(1000));
     ProcessedRecording {
         recording,
-        changepoint_us,
+        changepoint_us: changepoint.as_ref().map(Estimate::rounded_us),
+        changepoint,
+        response,
+        transitions,
     }
 }
 
+/// Resamples drawn for bootstrap confidence intervals
+const BOOTSTRAP_RESAMPLES: usize = 2000;
+
+/// Bootstrapping is seeded, so that the same measurements always give the same intervals
+const BOOTSTRAP_SEED: u64 = 0x1a7e_4a7e;
+
+/// Random splits drawn for the permutation test
+const PERMUTATIONS: usize = 10_000;
+
 #[derive(Debug)]
 pub enum FinalStats {
     NoRuns,
-    NoSuccesses,
+    NoSuccesses {
+        n_failed: usize,
+    },
     SingleMeasurement {
         latency: f64,
+        n_failed: usize,
+        response: Option<ResponseStats>,
+        transitions: Option<TransitionStats>,
     },
     MultipleMeasurements {
-        has_missing: bool,
         n_samples: usize,
+        /// Repeats where no changepoint was found
+        n_failed: usize,
         mean: f64,
         stddev: f64,
         median: f64,
         max: f64,
         min: f64,
+        percentiles: Percentiles,
+        /// Median absolute deviation from the median, unscaled
+        mad: f64,
+        mean_ci: ConfidenceInterval,
+        median_ci: ConfidenceInterval,
+        response: Option<ResponseStats>,
+        transitions: Option<TransitionStats>,
     },
 }
 
-pub fn process_changepoints(values: &[Option<u32>]) -> FinalStats {
-    if values.is_empty() {
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct Percentiles {
+    pub p5: f64,
+    pub p25: f64,
+    pub p75: f64,
+    pub p90: f64,
+    pub p95: f64,
+    pub p99: f64,
+}
+
+/// 95% confidence interval
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct ConfidenceInterval {
+    pub low: f64,
+    pub high: f64,
+}
+
+/// Linear interpolation between the closest ranks, `sorted` must not be empty
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = (sorted.len() - 1) as f64 * p / 100.0;
+    let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[below] + (sorted[above] - sorted[below]) * (rank - below as f64)
+}
+
+fn sorted(values: &[f64]) -> Vec<f64> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    sorted
+}
+
+fn percentiles(sorted: &[f64]) -> Percentiles {
+    Percentiles {
+        p5: percentile(sorted, 5.0),
+        p25: percentile(sorted, 25.0),
+        p75: percentile(sorted, 75.0),
+        p90: percentile(sorted, 90.0),
+        p95: percentile(sorted, 95.0),
+        p99: percentile(sorted, 99.0),
+    }
+}
+
+fn mad(values: &[f64], median: f64) -> f64 {
+    let deviations = sorted(
+        &values
+            .iter()
+            .map(|v| (v - median).abs())
+            .collect::<Vec<_>>(),
+    );
+    percentile(&deviations, 50.0)
+}
+
+/// Percentile bootstrap interval of a statistic, `values` must not be empty
+pub fn bootstrap_ci(values: &[f64], statistic: impl Fn(&[f64]) -> f64) -> ConfidenceInterval {
+    let mut rng = SmallRng::seed_from_u64(BOOTSTRAP_SEED);
+    let mut resample = vec![0f64; values.len()];
+    let estimates = (0..BOOTSTRAP_RESAMPLES)
+        .map(|_| {
+            for v in resample.iter_mut() {
+                *v = values[rng.gen_range(0..values.len())];
+            }
+            statistic(&resample)
+        })
+        .collect::<Vec<_>>();
+    let estimates = sorted(&estimates);
+    ConfidenceInterval {
+        low: percentile(&estimates, 2.5),
+        high: percentile(&estimates, 97.5),
+    }
+}
+
+fn resample(values: &[f64], rng: &mut SmallRng, into: &mut Vec<f64>) {
+    into.clear();
+    into.extend((0..values.len()).map(|_| values[rng.gen_range(0..values.len())]));
+}
+
+/// Percentile bootstrap interval of `median(a) - median(b)`, both samples are resampled
+/// separately. None if any of them is empty
+pub fn median_difference_ci(a: &[f64], b: &[f64]) -> Option<ConfidenceInterval> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+
+    let mut rng = SmallRng::seed_from_u64(BOOTSTRAP_SEED);
+    let (mut resample_a, mut resample_b) = (vec![], vec![]);
+    let differences = (0..BOOTSTRAP_RESAMPLES)
+        .map(|_| {
+            resample(a, &mut rng, &mut resample_a);
+            resample(b, &mut rng, &mut resample_b);
+            median_of(&resample_a) - median_of(&resample_b)
+        })
+        .collect::<Vec<_>>();
+    let differences = sorted(&differences);
+    Some(ConfidenceInterval {
+        low: percentile(&differences, 2.5),
+        high: percentile(&differences, 97.5),
+    })
+}
+
+/// Two-sided permutation test of the difference of medians: how often a random split of
+/// all values into groups of the same sizes differs at least as much. None if any of the
+/// samples is empty
+pub fn permutation_test(a: &[f64], b: &[f64]) -> Option<f64> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+
+    let observed = (median_of(a) - median_of(b)).abs();
+    let mut all = a.iter().chain(b).copied().collect::<Vec<_>>();
+    let mut rng = SmallRng::seed_from_u64(BOOTSTRAP_SEED);
+    let extreme = (0..PERMUTATIONS)
+        .filter(|_| {
+            all.shuffle(&mut rng);
+            let (split_a, split_b) = all.split_at(a.len());
+            // float noise must not make the observed split itself less extreme
+            (median_of(split_a) - median_of(split_b)).abs() >= observed - 1e-9
+        })
+        .count();
+
+    // the observed split counts as one of the permutations
+    Some((extreme + 1) as f64 / (PERMUTATIONS + 1) as f64)
+}
+
+/// Bootstrap interval of the median, None with fewer than two values
+pub fn median_ci(values: &[f64]) -> Option<ConfidenceInterval> {
+    (values.len() > 1).then(|| bootstrap_ci(values, median_of))
+}
+
+fn mean_of(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn median_of(values: &[f64]) -> f64 {
+    percentile(&sorted(values), 50.0)
+}
+
+/// Pixel response of repeats where it could be measured,
+/// transition times are in milliseconds and overshoots in percent
+#[derive(Debug, Clone, Copy)]
+pub struct ResponseStats {
+    pub n_samples: usize,
+    pub n_rising: usize,
+    pub transition_median: f64,
+    pub transition_mean: f64,
+    pub transition_max: f64,
+    pub overshoot_mean: f64,
+    pub overshoot_max: f64,
+    pub undershoot_mean: f64,
+}
+
+fn process_responses(responses: &[Response]) -> Option<ResponseStats> {
+    if responses.is_empty() {
+        return None;
+    }
+
+    let transitions = responses
+        .iter()
+        .map(|r| r.transition_us / 1000f64)
+        .collect::<Vec<_>>();
+    let overshoots = responses
+        .iter()
+        .map(|r| r.overshoot_percent)
+        .collect::<Vec<_>>();
+    let undershoots = responses
+        .iter()
+        .map(|r| r.undershoot_percent)
+        .collect::<Vec<_>>();
+
+    Some(ResponseStats {
+        n_samples: responses.len(),
+        n_rising: responses.iter().filter(|r| r.rising).count(),
+        transition_median: statistical::median(&transitions),
+        transition_mean: statistical::mean(&transitions),
+        transition_max: transitions.iter().copied().fold(f64::MIN, f64::max),
+        overshoot_mean: statistical::mean(&overshoots),
+        overshoot_max: overshoots.iter().copied().fold(f64::MIN, f64::max),
+        undershoot_mean: statistical::mean(&undershoots),
+    })
+}
+
+/// Level transitions within repeats, intervals are in milliseconds. The spread of intervals
+/// shows judder
+#[derive(Debug, Clone, Copy)]
+pub struct TransitionStats {
+    pub n_transitions: usize,
+    pub mean_per_repeat: f64,
+    pub interval_median: f64,
+    pub interval_mean: f64,
+    pub interval_stddev: f64,
+    pub interval_min: f64,
+    pub interval_max: f64,
+}
+
+/// None if there are no intervals, i.e. no repeat has more than one transition
+fn process_transitions(measurements: &[Measurement]) -> Option<TransitionStats> {
+    let intervals = measurements
+        .iter()
+        .flat_map(|m| &m.transition_intervals_us)
+        .map(|&us| f64::from(us) / 1000f64)
+        .collect::<Vec<_>>();
+    if intervals.is_empty() {
+        return None;
+    }
+
+    let n_transitions = measurements.iter().map(|m| m.n_transitions).sum::<usize>();
+    let interval_mean = statistical::mean(&intervals);
+    let interval_stddev = if intervals.len() > 1 {
+        statistical::standard_deviation(&intervals, Some(interval_mean))
+    } else {
+        0.0
+    };
+
+    Some(TransitionStats {
+        n_transitions,
+        mean_per_repeat: n_transitions as f64 / measurements.len() as f64,
+        interval_median: statistical::median(&intervals),
+        interval_mean,
+        interval_stddev,
+        interval_min: intervals.iter().copied().fold(f64::MAX, f64::min),
+        interval_max: intervals.iter().copied().fold(f64::MIN, f64::max),
+    })
+}
+
+pub fn process_measurements(measurements: &[Measurement]) -> FinalStats {
+    if measurements.is_empty() {
         return FinalStats::NoRuns;
     }
 
-    let millis = values
+    let millis = measurements
         .iter()
-        .filter_map(|x| x.map(|us| f64::from(us) / 1000f64))
+        .filter_map(|m| m.changepoint_us.map(|us| f64::from(us) / 1000f64))
         .collect::<Vec<_>>();
 
+    let n_failed = measurements.len() - millis.len();
     if millis.is_empty() {
-        return FinalStats::NoSuccesses;
+        return FinalStats::NoSuccesses { n_failed };
     }
 
+    let responses = measurements
+        .iter()
+        .filter_map(|m| m.response)
+        .collect::<Vec<_>>();
+    let response = process_responses(&responses);
+    let transitions = process_transitions(measurements);
+
     if millis.len() == 1 {
-        return FinalStats::SingleMeasurement { latency: millis[0] };
+        return FinalStats::SingleMeasurement {
+            latency: millis[0],
+            n_failed,
+            response,
+            transitions,
+        };
     }
 
-    let has_missing = values.len() != millis.len();
     let n = millis.len();
     let mean = statistical::mean(&millis);
     let stddev = statistical::standard_deviation(&millis, Some(mean));
@@ -143,12 +389,265 @@ pub fn process_changepoints(values: &[Option<u32>]) -> FinalStats {
         .to_owned();
 
     FinalStats::MultipleMeasurements {
-        has_missing,
         n_samples: n,
+        n_failed,
         mean,
         stddev,
         median,
         max,
         min,
+        percentiles: percentiles(&sorted(&millis)),
+        mad: mad(&millis, median),
+        mean_ci: bootstrap_ci(&millis, mean_of),
+        median_ci: median_ci(&millis).expect("There are at least two measurements here"),
+        response,
+        transitions,
+    }
+}
+
+/// Flat view of FinalStats for tables, statistics that can't be computed are None
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct StatsSummary {
+    pub n_samples: usize,
+    pub failed: usize,
+    pub mean: Option<f64>,
+    pub stddev: Option<f64>,
+    pub median: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Median 10%–90% transition time
+    pub transition_median: Option<f64>,
+    /// Mean overshoot in percent of the swing
+    pub overshoot_mean: Option<f64>,
+    pub percentiles: Option<Percentiles>,
+    pub mad: Option<f64>,
+    pub mean_ci: Option<ConfidenceInterval>,
+    pub median_ci: Option<ConfidenceInterval>,
+}
+
+impl FinalStats {
+    pub fn summary(&self) -> StatsSummary {
+        match *self {
+            FinalStats::NoRuns => StatsSummary::default(),
+            FinalStats::NoSuccesses { n_failed } => StatsSummary {
+                failed: n_failed,
+                ..Default::default()
+            },
+            FinalStats::SingleMeasurement {
+                latency,
+                n_failed,
+                response,
+                ..
+            } => StatsSummary {
+                n_samples: 1,
+                failed: n_failed,
+                mean: Some(latency),
+                stddev: None,
+                median: Some(latency),
+                min: Some(latency),
+                max: Some(latency),
+                transition_median: response.map(|r| r.transition_median),
+                overshoot_mean: response.map(|r| r.overshoot_mean),
+                percentiles: None,
+                mad: None,
+                mean_ci: None,
+                median_ci: None,
+            },
+            FinalStats::MultipleMeasurements {
+                n_samples,
+                n_failed,
+                mean,
+                stddev,
+                median,
+                max,
+                min,
+                percentiles,
+                mad,
+                mean_ci,
+                median_ci,
+                response,
+                ..
+            } => StatsSummary {
+                n_samples,
+                failed: n_failed,
+                mean: Some(mean),
+                stddev: Some(stddev),
+                median: Some(median),
+                min: Some(min),
+                max: Some(max),
+                transition_median: response.map(|r| r.transition_median),
+                overshoot_mean: response.map(|r| r.overshoot_mean),
+                percentiles: Some(percentiles),
+                mad: Some(mad),
+                mean_ci: Some(mean_ci),
+                median_ci: Some(median_ci),
+            },
+        }
+    }
+}
+
+/// Complementary error function, fractional error is below 1.2e-7
+/// (Chebyshev approximation from Numerical Recipes)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let result = t * poly.exp();
+    if x >= 0.0 {
+        result
+    } else {
+        2.0 - result
+    }
+}
+
+/// Significance levels outside of (0, 1) make every difference significant or none of them
+pub fn parse_alpha(s: &str) -> Result<f64, String> {
+    let alpha = s.parse::<f64>().map_err(|e| e.to_string())?;
+    if alpha > 0.0 && alpha < 1.0 {
+        Ok(alpha)
+    } else {
+        Err(format!(
+            "must be larger than 0 and smaller than 1, got {alpha}"
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MannWhitney {
+    /// U statistic of the first sample
+    pub u: f64,
+    /// Probability that a random value from the first sample is larger than a random value
+    /// from the second one (ties count as half), 0.5 means no difference
+    pub effect_size: f64,
+    /// Two-sided, using the normal approximation with tie and continuity corrections
+    pub p_value: f64,
+}
+
+/// Mann–Whitney U test, None if any of the samples is empty or all values are equal
+pub fn mann_whitney_u(a: &[f64], b: &[f64]) -> Option<MannWhitney> {
+    if a.is_empty() || b.is_empty() {
+        return None;
+    }
+
+    let mut all = a
+        .iter()
+        .map(|&v| (v, true))
+        .chain(b.iter().map(|&v| (v, false)))
+        .collect::<Vec<_>>();
+    all.sort_by(|x, y| {
+        x.0.partial_cmp(&y.0)
+            .expect("There must be no NaNs among samples")
+    });
+
+    // ranks start from 1, tied values get the average of their ranks
+    let mut rank_sum_a = 0f64;
+    let mut tie_term = 0f64;
+    let mut i = 0;
+    while i < all.len() {
+        let mut j = i;
+        while j + 1 < all.len() && all[j + 1].0 == all[i].0 {
+            j += 1;
+        }
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        let ties = (j - i + 1) as f64;
+        tie_term += ties.powi(3) - ties;
+        rank_sum_a += rank * all[i..=j].iter().filter(|(_, is_a)| *is_a).count() as f64;
+        i = j + 1;
+    }
+
+    let (n_a, n_b) = (a.len() as f64, b.len() as f64);
+    let n = n_a + n_b;
+    let u = rank_sum_a - n_a * (n_a + 1.0) / 2.0;
+    let mean = n_a * n_b / 2.0;
+    let variance = n_a * n_b / 12.0 * ((n + 1.0) - tie_term / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return None;
+    }
+
+    let z = ((u - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    Some(MannWhitney {
+        u,
+        effect_size: u / (n_a * n_b),
+        p_value: erfc(z / std::f64::consts::SQRT_2).min(1.0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let values = (1..=101).map(f64::from).collect::<Vec<_>>();
+        let percentiles = percentiles(&values);
+        assert_eq!(percentiles.p5, 6.0);
+        assert_eq!(percentiles.p99, 100.0);
+        assert_eq!(percentile(&[1.0, 2.0], 25.0), 1.25);
+        assert_eq!(mad(&[1.0, 2.0, 3.0, 4.0, 100.0], 3.0), 1.0);
+    }
+
+    #[test]
+    fn test_bootstrap_ci() {
+        let values = (0..200).map(|i| f64::from(i % 20)).collect::<Vec<_>>();
+        let ci = bootstrap_ci(&values, mean_of);
+        // σ of the mean is about 5.8 / √200 = 0.41
+        assert!(ci.low < 9.5 && ci.high > 9.5, "{ci:?}");
+        assert!(ci.high - ci.low > 1.2 && ci.high - ci.low < 2.0, "{ci:?}");
+        assert_eq!(ci, bootstrap_ci(&values, mean_of));
+
+        let constant = bootstrap_ci(&[3.0; 10], median_of);
+        assert_eq!((constant.low, constant.high), (3.0, 3.0));
+    }
+
+    #[test]
+    fn test_two_samples() {
+        let a = (0..50).map(|i| f64::from(i % 10)).collect::<Vec<_>>();
+        let slower = a.iter().map(|v| v + 3.0).collect::<Vec<_>>();
+
+        let ci = median_difference_ci(&slower, &a).unwrap();
+        assert!(ci.low <= 3.0 && ci.high >= 3.0, "{ci:?}");
+        assert!(permutation_test(&slower, &a).unwrap() < 0.01);
+
+        let same = permutation_test(&a, &a).unwrap();
+        assert!(same > 0.5, "{same}");
+        assert_eq!(permutation_test(&a, &[]), None);
+    }
+
+    #[test]
+    fn test_erfc() {
+        assert!((erfc(0.0) - 1.0).abs() < 1e-7);
+        assert!((erfc(1.0) - 0.157_299_207).abs() < 1e-7);
+        assert!((erfc(-1.0) - 1.842_700_793).abs() < 1e-7);
+    }
+
+    #[test]
+    fn test_mann_whitney_u() {
+        let result = mann_whitney_u(&[1.0, 2.0, 3.0], &[4.0, 5.0, 6.0]).unwrap();
+        assert_eq!(result.u, 0.0);
+        assert_eq!(result.effect_size, 0.0);
+        // z = (4.5 - 0.5) / sqrt(5.25)
+        assert!((result.p_value - 0.080_856).abs() < 1e-5);
+
+        let same = mann_whitney_u(&[1.0, 2.0, 3.0], &[3.0, 2.0, 1.0]).unwrap();
+        assert_eq!(same.effect_size, 0.5);
+        assert!(same.p_value > 0.99);
+
+        assert!(mann_whitney_u(&[1.0], &[1.0]).is_none());
+        assert!(mann_whitney_u(&[], &[1.0]).is_none());
+    }
+
+    #[test]
+    fn test_parse_alpha() {
+        assert_eq!(parse_alpha("0.01"), Ok(0.01));
+        for invalid in ["0", "1", "-0.05", "5", "NaN", "five"] {
+            assert!(parse_alpha(invalid).is_err(), "{invalid}");
+        }
     }
 }
@@ -0,0 +1,370 @@
+//! Finding the moment the light level changed in a recording. Detectors are configured with
+//! `Detector` from the scenario file or `--detector`
+
+use late_mate_device::scenario::{Detector, Moment};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Changepoint {
+    /// Microsecond of the first light level after the change
+    pub microsecond: u32,
+    /// Light level that marks the change, the signal crosses it between the previous light
+    /// level sample and this one
+    pub level: f64,
+    /// Standard deviation of the light level before the change
+    pub noise: f64,
+}
+
+pub trait ChangepointDetector {
+    /// None if there is no change
+    fn find_changepoint(&self, timeline: &[Moment]) -> Option<Changepoint>;
+}
+
+pub fn detector(config: &Detector) -> Box<dyn ChangepointDetector> {
+    match config {
+        Detector::Threshold(params) => Box::new(Threshold {
+            noise_window_us: params.noise_window_ms.saturating_mul(1000),
+            detect_multiplier: params.detect_multiplier,
+            change_multiplier: params.change_multiplier,
+        }),
+        Detector::Cusum(params) => Box::new(Cusum {
+            noise_window_us: params.noise_window_ms.saturating_mul(1000),
+            drift: params.drift,
+            threshold: params.threshold,
+        }),
+        Detector::StepFit(params) => Box::new(StepFit {
+            min_step_sigmas: params.min_step_sigmas,
+        }),
+        Detector::Swing(params) => Box::new(Swing {
+            noise_window_us: params.noise_window_ms.saturating_mul(1000),
+            fraction: params.percent / 100.0,
+            detect_multiplier: params.detect_multiplier,
+        }),
+    }
+}
+
+/// Parses `--detector`, e.g. "cusum" or "swing:percent=20,noise_window_ms=10"
+pub fn parse_detector(s: &str) -> Result<Detector, String> {
+    let (method, params) = s.split_once(':').unwrap_or((s, ""));
+
+    let mut document = serde_json::Map::new();
+    document.insert("method".to_owned(), method.trim().into());
+    for param in params.split(',').filter(|p| !p.trim().is_empty()) {
+        let (name, value) = param
+            .split_once('=')
+            .ok_or_else(|| format!("expected NAME=VALUE, got \"{param}\""))?;
+        let value = value
+            .trim()
+            .parse::<serde_json::Number>()
+            .map_err(|_| format!("\"{name}\" must be a number, got \"{value}\""))?;
+        document.insert(name.trim().to_owned(), value.into());
+    }
+
+    let detector: Detector =
+        serde_json::from_value(serde_json::Value::Object(document)).map_err(|e| e.to_string())?;
+    detector.validate().map_err(|e| e.to_string())?;
+    Ok(detector)
+}
+
+pub(super) fn light_levels(timeline: &[Moment]) -> Vec<(u32, f64)> {
+    timeline
+        .iter()
+        .filter_map(|m| m.to_light_level().map(|l| (m.microsecond, f64::from(l))))
+        .collect()
+}
+
+/// Light levels at the start and at the end of the recording, where the signal is expected to
+/// be stable. None if either of them is empty
+fn edges(samples: &[(u32, f64)], window_us: u32) -> Option<(Vec<f64>, Vec<f64>)> {
+    let last_time = samples.last()?.0;
+    let start = samples
+        .iter()
+        .take_while(|(t, _)| *t < window_us)
+        .map(|(_, l)| *l)
+        .collect::<Vec<_>>();
+    let end = samples
+        .iter()
+        .rev()
+        .take_while(|(t, _)| *t > last_time.saturating_sub(window_us))
+        .map(|(_, l)| *l)
+        .collect::<Vec<_>>();
+
+    if start.is_empty() || end.is_empty() {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+fn range(values: &[f64]) -> (f64, f64) {
+    values
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &v| {
+            (min.min(v), max.max(v))
+        })
+}
+
+fn mean_and_stddev(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+    (mean, variance.sqrt())
+}
+
+/// The original heuristic: noise range is the spread of light levels in the noise window
+pub struct Threshold {
+    pub noise_window_us: u32,
+    pub detect_multiplier: f64,
+    pub change_multiplier: f64,
+}
+
+impl ChangepointDetector for Threshold {
+    fn find_changepoint(&self, timeline: &[Moment]) -> Option<Changepoint> {
+        let samples = light_levels(timeline);
+        let (start, end) = edges(&samples, self.noise_window_us)?;
+        let (start_min, start_max) = range(&start);
+        let (end_min, end_max) = range(&end);
+        let (_, noise) = mean_and_stddev(&start);
+
+        let noise_range = start_max - start_min;
+        let detect_gap = noise_range * self.detect_multiplier;
+        let change_gap = noise_range * self.change_multiplier;
+
+        let (level, is_change): (f64, fn(f64, f64) -> bool) = if end_min > start_max + detect_gap {
+            // raising signal
+            (start_max + change_gap, |value, level| value > level)
+        } else if start_min > end_max + detect_gap {
+            // dropping signal
+            (start_min - change_gap, |value, level| value < level)
+        } else {
+            return None;
+        };
+
+        let (microsecond, _) = samples.iter().find(|(_, value)| is_change(*value, level))?;
+        Some(Changepoint {
+            microsecond: *microsecond,
+            level,
+            noise,
+        })
+    }
+}
+
+/// Two-sided CUSUM in units of the starting noise's standard deviation
+pub struct Cusum {
+    pub noise_window_us: u32,
+    pub drift: f64,
+    pub threshold: f64,
+}
+
+impl ChangepointDetector for Cusum {
+    fn find_changepoint(&self, timeline: &[Moment]) -> Option<Changepoint> {
+        let samples = light_levels(timeline);
+        let (start, _) = edges(&samples, self.noise_window_us)?;
+        let (mean, noise) = mean_and_stddev(&start);
+        // perfectly quiet sensors still have a quantisation step
+        let stddev = noise.max(1.0);
+
+        let (mut high, mut low) = (0f64, 0f64);
+        let (mut high_start, mut low_start) = (None, None);
+        for &(t, value) in &samples {
+            let deviation = (value - mean) / stddev;
+
+            high = (high + deviation - self.drift).max(0.0);
+            low = (low - deviation - self.drift).max(0.0);
+            // the change starts with the first sample of the run of growing sums,
+            // which is the first one that deviates by more than the drift
+            high_start = if high > 0.0 {
+                high_start.or(Some(t))
+            } else {
+                None
+            };
+            low_start = if low > 0.0 {
+                low_start.or(Some(t))
+            } else {
+                None
+            };
+
+            let (start, direction) = if high > self.threshold {
+                (high_start, 1.0)
+            } else if low > self.threshold {
+                (low_start, -1.0)
+            } else {
+                continue;
+            };
+            return start.map(|microsecond| Changepoint {
+                microsecond,
+                level: mean + direction * self.drift * stddev,
+                noise,
+            });
+        }
+
+        None
+    }
+}
+
+/// Least-squares fit of a single step, the changepoint is the first sample after the step
+pub struct StepFit {
+    pub min_step_sigmas: f64,
+}
+
+impl ChangepointDetector for StepFit {
+    fn find_changepoint(&self, timeline: &[Moment]) -> Option<Changepoint> {
+        let samples = light_levels(timeline);
+        let n = samples.len();
+        if n < 4 {
+            return None;
+        }
+
+        // with prefix sums of values and squares, SSE of any segment is O(1)
+        let mut sums = vec![(0f64, 0f64); n + 1];
+        for (i, (_, value)) in samples.iter().enumerate() {
+            sums[i + 1] = (sums[i].0 + value, sums[i].1 + value * value);
+        }
+        let segment = |from: usize, to: usize| {
+            let count = (to - from) as f64;
+            let sum = sums[to].0 - sums[from].0;
+            let sum_sq = sums[to].1 - sums[from].1;
+            let mean = sum / count;
+            (mean, (sum_sq - sum * mean).max(0.0))
+        };
+
+        let (split, sse, left_mean, right_mean) = (1..n)
+            .map(|split| {
+                let (left_mean, left_sse) = segment(0, split);
+                let (right_mean, right_sse) = segment(split, n);
+                (split, left_sse + right_sse, left_mean, right_mean)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+
+        let step = right_mean - left_mean;
+        let residual_stddev = (sse / (n - 2) as f64).sqrt();
+        if step == 0.0 || step.abs() <= self.min_step_sigmas * residual_stddev {
+            return None;
+        }
+
+        Some(Changepoint {
+            microsecond: samples[split].0,
+            level: (left_mean + right_mean) / 2.0,
+            noise: residual_stddev,
+        })
+    }
+}
+
+/// Percentage of the way from the starting level to the final one
+pub struct Swing {
+    pub noise_window_us: u32,
+    pub fraction: f64,
+    pub detect_multiplier: f64,
+}
+
+impl ChangepointDetector for Swing {
+    fn find_changepoint(&self, timeline: &[Moment]) -> Option<Changepoint> {
+        let samples = light_levels(timeline);
+        let (start, end) = edges(&samples, self.noise_window_us)?;
+        let (start_min, start_max) = range(&start);
+        let (start_mean, noise) = mean_and_stddev(&start);
+        let (end_mean, _) = mean_and_stddev(&end);
+
+        let swing = end_mean - start_mean;
+        if swing == 0.0 || swing.abs() <= (start_max - start_min) * self.detect_multiplier {
+            return None;
+        }
+
+        let (microsecond, _) = samples
+            .iter()
+            .find(|(_, value)| (value - start_mean) / swing >= self.fraction)?;
+        Some(Changepoint {
+            microsecond: *microsecond,
+            level: start_mean + self.fraction * swing,
+            noise,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use late_mate_device::scenario::{Event, SwingParams};
+
+    /// A sample every 500us with a little noise, stepping from `from` to `to` at `step_us`
+    fn step_timeline(from: u32, to: u32, step_us: u32, length_us: u32) -> Vec<Moment> {
+        (0..length_us)
+            .step_by(500)
+            .enumerate()
+            .map(|(i, microsecond)| {
+                let level = if microsecond < step_us { from } else { to };
+                Moment {
+                    microsecond,
+                    event: Event::LightLevel(level + (i as u32 % 3)),
+                }
+            })
+            .collect()
+    }
+
+    fn all_detectors() -> Vec<Detector> {
+        ["threshold", "cusum", "step_fit", "swing"]
+            .into_iter()
+            .map(|method| parse_detector(method).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_detectors_find_steps() {
+        for config in all_detectors() {
+            let detector = detector(&config);
+            // CUSUM can pick the sample right before the step if noise happened to go the
+            // same way
+            let raising = step_timeline(100, 1000, 20_000, 50_000);
+            let changepoint = detector.find_changepoint(&raising).unwrap();
+            assert!(
+                (19_500..=20_000).contains(&changepoint.microsecond),
+                "{config:?}"
+            );
+            let dropping = step_timeline(1000, 100, 20_000, 50_000);
+            let changepoint = detector.find_changepoint(&dropping).unwrap();
+            assert!(
+                (19_500..=20_000).contains(&changepoint.microsecond),
+                "{config:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_detectors_edge_cases() {
+        let flat = step_timeline(100, 100, 0, 50_000);
+        let short = step_timeline(100, 1000, 1_000, 3_000);
+        // zero noise range, the start window ends before the first sample
+        let quiet = vec![
+            Moment {
+                microsecond: 8_000,
+                event: Event::LightLevel(0),
+            },
+            Moment {
+                microsecond: 9_000,
+                event: Event::LightLevel(0),
+            },
+        ];
+        for config in all_detectors() {
+            let detector = detector(&config);
+            assert_eq!(detector.find_changepoint(&[]), None, "{config:?}");
+            assert_eq!(detector.find_changepoint(&flat), None, "{config:?}");
+            assert_eq!(detector.find_changepoint(&quiet), None, "{config:?}");
+            // must not panic, the answer doesn't matter much
+            detector.find_changepoint(&short);
+        }
+    }
+
+    #[test]
+    fn test_parse_detector() {
+        assert_eq!(parse_detector("threshold").unwrap(), Detector::default());
+        assert_eq!(
+            parse_detector("swing:percent=20").unwrap(),
+            Detector::Swing(SwingParams {
+                percent: 20.0,
+                ..Default::default()
+            })
+        );
+        assert!(parse_detector("swing:percent=120").is_err());
+        assert!(parse_detector("cusum:nope=1").is_err());
+        assert!(parse_detector("magic").is_err());
+    }
+}
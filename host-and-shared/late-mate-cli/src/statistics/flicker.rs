@@ -0,0 +1,277 @@
+//! Periodic modulation of the light level: display refresh, backlight PWM and flickering
+//! room lighting. These show up as changes in every recording, so knowing about them helps
+//! to tell them apart from the change being measured
+
+use serde::Serialize;
+use std::f64::consts::PI;
+
+/// Displays refresh at one of a few standard rates, while PWM frequencies are arbitrary.
+/// Fractional NTSC rates such as 59.94hz are within the tolerance
+const REFRESH_RATES_HZ: [f64; 19] = [
+    24.0, 30.0, 48.0, 50.0, 60.0, 72.0, 75.0, 85.0, 90.0, 100.0, 120.0, 144.0, 165.0, 170.0, 180.0,
+    200.0, 240.0, 360.0, 480.0,
+];
+
+/// A peak must be this many times above the median of the spectrum
+const PEAK_FLOOR_MULTIPLIER: f64 = 10.0;
+
+/// Peaks are also ignored below this amplitude, in percent of the mean level
+const MIN_AMPLITUDE_PERCENT: f64 = 0.1;
+
+/// Reported peaks, strongest first
+const MAX_PEAKS: usize = 8;
+
+/// Lighting powered from the mains flickers at twice the mains frequency, mains frequency
+/// is kept within a fraction of a hertz
+const MAINS_TOLERANCE_HZ: f64 = 0.5;
+
+/// Relative tolerance when matching harmonics
+const HARMONIC_TOLERANCE: f64 = 0.01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Peak {
+    pub frequency_hz: f64,
+    /// Amplitude of the sine at this frequency, in percent of the mean light level
+    pub amplitude_percent: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Mains {
+    #[serde(rename = "50hz")]
+    Hz50,
+    #[serde(rename = "60hz")]
+    Hz60,
+}
+
+impl Mains {
+    pub fn hz(&self) -> u32 {
+        match self {
+            Mains::Hz50 => 50,
+            Mains::Hz60 => 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Flicker {
+    pub sample_rate_hz: f64,
+    pub mean_level: f64,
+    /// Percent flicker: (max - min) / (max + min) of the 1st and 99th percentiles
+    pub modulation_depth_percent: f64,
+    pub peaks: Vec<Peak>,
+    /// The fundamental of the strongest periodic signal at a standard refresh rate
+    pub refresh_rate_hz: Option<f64>,
+    /// The strongest periodic signal that isn't related to the refresh or the room lighting
+    pub pwm: Option<Peak>,
+    pub ambient: Option<Mains>,
+}
+
+/// Amplitude spectrum with a Hann window, one bin per `sample_rate / n` hertz up to Nyquist.
+/// A plain DFT is fast enough for a few seconds of samples
+fn spectrum(levels: &[f64], mean: f64) -> Vec<f64> {
+    let n = levels.len();
+    let window = (0..n)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / n as f64).cos())
+        .collect::<Vec<_>>();
+    let windowed = levels
+        .iter()
+        .zip(&window)
+        .map(|(y, w)| (y - mean) * w)
+        .collect::<Vec<_>>();
+    let gain = window.iter().sum::<f64>() / 2.0;
+
+    (0..=n / 2)
+        .map(|k| {
+            let (step_sin, step_cos) = (-2.0 * PI * k as f64 / n as f64).sin_cos();
+            let (mut re, mut im) = (0.0, 0.0);
+            let (mut sin, mut cos) = (0.0f64, 1.0f64);
+            for y in &windowed {
+                re += y * cos;
+                im += y * sin;
+                (sin, cos) = (
+                    sin * step_cos + cos * step_sin,
+                    cos * step_cos - sin * step_sin,
+                );
+            }
+            (re * re + im * im).sqrt() / gain
+        })
+        .collect()
+}
+
+/// Local maxima above the noise floor, with the frequency refined by fitting a parabola
+/// through the neighbouring bins
+fn find_peaks(spectrum: &[f64], bin_hz: f64, mean: f64) -> Vec<Peak> {
+    let mut sorted = spectrum.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let floor = sorted.get(sorted.len() / 2).copied().unwrap_or(0.0);
+    let threshold = (floor * PEAK_FLOOR_MULTIPLIER).max(mean * MIN_AMPLITUDE_PERCENT / 100.0);
+
+    let mut peaks = (2..spectrum.len().saturating_sub(1))
+        .filter(|&k| {
+            spectrum[k] > threshold
+                && spectrum[k] >= spectrum[k - 1]
+                && spectrum[k] > spectrum[k + 1]
+        })
+        .map(|k| {
+            let (a, b, c) = (spectrum[k - 1], spectrum[k], spectrum[k + 1]);
+            let denominator = a - 2.0 * b + c;
+            let offset = if denominator == 0.0 {
+                0.0
+            } else {
+                (0.5 * (a - c) / denominator).clamp(-0.5, 0.5)
+            };
+            Peak {
+                frequency_hz: (k as f64 + offset) * bin_hz,
+                amplitude_percent: (b - 0.25 * (a - c) * offset) / mean * 100.0,
+            }
+        })
+        .collect::<Vec<_>>();
+    peaks.sort_by(|a, b| b.amplitude_percent.total_cmp(&a.amplitude_percent));
+    peaks.truncate(MAX_PEAKS);
+    peaks
+}
+
+fn is_harmonic(frequency_hz: f64, fundamental_hz: f64) -> bool {
+    let ratio = frequency_hz / fundamental_hz;
+    ratio.round() >= 1.0 && (ratio - ratio.round()).abs() < HARMONIC_TOLERANCE * ratio.round()
+}
+
+fn is_refresh_rate(frequency_hz: f64) -> bool {
+    REFRESH_RATES_HZ
+        .iter()
+        .any(|hz| (frequency_hz - hz).abs() < HARMONIC_TOLERANCE * hz)
+}
+
+fn mains(peaks: &[Peak]) -> Option<Mains> {
+    let near = |hz: f64| {
+        peaks
+            .iter()
+            .any(|p| (p.frequency_hz - hz).abs() < MAINS_TOLERANCE_HZ)
+    };
+    // a display refreshing at 50hz or 60hz has harmonics at the same frequencies, but lamps
+    // have next to nothing at the mains frequency itself
+    if near(100.0) && !near(50.0) {
+        Some(Mains::Hz50)
+    } else if near(120.0) && !near(60.0) {
+        Some(Mains::Hz60)
+    } else {
+        None
+    }
+}
+
+/// Levels are expected to be evenly spaced. The classification is a heuristic: a 120hz display
+/// in a room lit from 60hz mains can't be told apart, PWM synchronised to the refresh counts
+/// as the refresh, and PWM above half the sample rate shows up at an aliased frequency
+pub fn analyze(levels: &[f64], sample_rate_hz: f64) -> Option<Flicker> {
+    if levels.len() < 16 || sample_rate_hz.is_nan() || sample_rate_hz <= 0.0 {
+        return None;
+    }
+
+    let mean_level = levels.iter().sum::<f64>() / levels.len() as f64;
+    let mut sorted = levels.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let percentile = |p: f64| sorted[((sorted.len() - 1) as f64 * p).round() as usize];
+    let (low, high) = (percentile(0.01), percentile(0.99));
+    let modulation_depth_percent = if high + low > 0.0 {
+        (high - low) / (high + low) * 100.0
+    } else {
+        0.0
+    };
+    if mean_level <= 0.0 {
+        return Some(Flicker {
+            sample_rate_hz,
+            mean_level,
+            modulation_depth_percent,
+            peaks: vec![],
+            refresh_rate_hz: None,
+            pwm: None,
+            ambient: None,
+        });
+    }
+
+    let bin_hz = sample_rate_hz / levels.len() as f64;
+    let peaks = find_peaks(&spectrum(levels, mean_level), bin_hz, mean_level);
+    let ambient = mains(&peaks);
+    let is_ambient =
+        |p: &Peak| ambient.is_some_and(|mains| is_harmonic(p.frequency_hz, f64::from(mains.hz())));
+
+    let refresh_rate_hz = peaks
+        .iter()
+        .filter(|p| !is_ambient(p))
+        .find(|p| is_refresh_rate(p.frequency_hz))
+        .map(|strongest| {
+            // the strongest peak can be a harmonic of the refresh rate
+            peaks
+                .iter()
+                .filter(|p| is_refresh_rate(p.frequency_hz))
+                .filter(|p| is_harmonic(strongest.frequency_hz, p.frequency_hz))
+                .map(|p| p.frequency_hz)
+                .fold(strongest.frequency_hz, f64::min)
+        });
+    let pwm = peaks
+        .iter()
+        .filter(|p| !is_ambient(p))
+        .find(|p| refresh_rate_hz.map_or(true, |refresh| !is_harmonic(p.frequency_hz, refresh)))
+        .copied();
+
+    Some(Flicker {
+        sample_rate_hz,
+        mean_level,
+        modulation_depth_percent,
+        peaks,
+        refresh_rate_hz,
+        pwm,
+        ambient,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f64 = 2000.0;
+
+    fn levels(signal: impl Fn(f64) -> f64) -> Vec<f64> {
+        (0..6000)
+            .map(|i| signal(i as f64 / SAMPLE_RATE).round())
+            .collect()
+    }
+
+    fn sine(hz: f64, t: f64) -> f64 {
+        (2.0 * PI * hz * t).sin()
+    }
+
+    #[test]
+    fn test_refresh_and_pwm() {
+        // a faint 60hz refresh with its harmonic under strong 437hz PWM, smoothed by the sensor
+        let levels = levels(|t| {
+            10_000.0 + 30.0 * sine(60.0, t) + 40.0 * sine(120.0, t) + 2_000.0 * sine(437.0, t)
+        });
+        let flicker = analyze(&levels, SAMPLE_RATE).unwrap();
+
+        let refresh = flicker.refresh_rate_hz.unwrap();
+        assert!((refresh - 60.0).abs() < 0.2, "{flicker:?}");
+        let pwm = flicker.pwm.unwrap();
+        assert!((pwm.frequency_hz - 437.0).abs() < 0.2, "{flicker:?}");
+        assert!((pwm.amplitude_percent - 20.0).abs() < 0.5, "{flicker:?}");
+        assert!((flicker.modulation_depth_percent - 20.0).abs() < 1.0);
+        assert_eq!(flicker.ambient, None);
+    }
+
+    #[test]
+    fn test_ambient() {
+        let levels = levels(|t| 5_000.0 + 100.0 * sine(100.0, t) + 50.0 * sine(144.0, t));
+        let flicker = analyze(&levels, SAMPLE_RATE).unwrap();
+        assert_eq!(flicker.ambient, Some(Mains::Hz50));
+        assert!((flicker.refresh_rate_hz.unwrap() - 144.0).abs() < 0.2);
+        assert_eq!(flicker.pwm, None);
+
+        let steady = self::levels(|_| 5_000.0);
+        let flicker = analyze(&steady, SAMPLE_RATE).unwrap();
+        assert_eq!(flicker.peaks, vec![]);
+        assert_eq!(flicker.refresh_rate_hz, None);
+        assert_eq!(flicker.modulation_depth_percent, 0.0);
+
+        assert_eq!(analyze(&[], SAMPLE_RATE), None);
+    }
+}
@@ -0,0 +1,252 @@
+//! Sub-sample changepoint estimation. The sensor is sampled about every 500us, which is too
+//! coarse for comparing high refresh rate displays
+
+use super::changepoint::{light_levels, Changepoint};
+use late_mate_device::scenario::{Interpolation, Moment};
+
+/// Samples this far from the changepoint on either side are used to fit the sigmoid
+const SIGMOID_WINDOW_US: u32 = 10_000;
+
+/// Two-sided 95% interval for normally distributed errors
+const Z_95: f64 = 1.96;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimate {
+    /// The first light level sample after the change, as found by the detector
+    pub sample_us: u32,
+    pub estimate_us: f64,
+    /// 95% interval of the estimate, assuming uniformly distributed error within the sample
+    /// spacing and normally distributed noise
+    pub low_us: f64,
+    pub high_us: f64,
+}
+
+impl Estimate {
+    pub fn rounded_us(&self) -> u32 {
+        // estimates are always between samples, so they fit
+        self.estimate_us.round() as u32
+    }
+}
+
+/// Where the line through two samples crosses the level, as a fraction of the way between them
+fn crossing(from: f64, to: f64, level: f64) -> Option<f64> {
+    let fraction = (level - from) / (to - from);
+    (fraction.is_finite() && (0.0..=1.0).contains(&fraction)).then_some(fraction)
+}
+
+/// Time uncertainty from noise is the noise divided by how fast the signal changes
+fn interval(estimate_us: f64, spacing_us: f64, noise: f64, slope: f64) -> (f64, f64) {
+    let quantisation = spacing_us / 12f64.sqrt();
+    let noise = if slope == 0.0 {
+        spacing_us
+    } else {
+        noise / slope.abs()
+    };
+    let half_width = Z_95 * (quantisation.powi(2) + noise.powi(2)).sqrt();
+    (estimate_us - half_width, estimate_us + half_width)
+}
+
+fn linear(samples: &[(u32, f64)], idx: usize, changepoint: &Changepoint) -> Estimate {
+    let (t1, y1) = samples[idx];
+    let Some(&(t0, y0)) = idx.checked_sub(1).and_then(|i| samples.get(i)) else {
+        return none(samples, idx);
+    };
+    let spacing = f64::from(t1 - t0);
+
+    match crossing(y0, y1, changepoint.level) {
+        Some(fraction) => {
+            let estimate_us = f64::from(t0) + fraction * spacing;
+            let slope = (y1 - y0) / spacing;
+            let (low_us, high_us) = interval(estimate_us, spacing, changepoint.noise, slope);
+            Estimate {
+                sample_us: t1,
+                estimate_us,
+                low_us,
+                high_us,
+            }
+        }
+        // noise pushed the previous sample over the level
+        None => none(samples, idx),
+    }
+}
+
+/// Fits `a + b / (1 + exp(-(t - c) / w))`, where `a` and `a + b` are the levels at the edges
+/// of the window, by linear regression of the logit of the transition samples
+fn sigmoid(samples: &[(u32, f64)], idx: usize, changepoint: &Changepoint) -> Option<Estimate> {
+    let t1 = samples[idx].0;
+    let window = samples
+        .iter()
+        .filter(|(t, _)| t.abs_diff(t1) <= SIGMOID_WINDOW_US)
+        .collect::<Vec<_>>();
+    if window.len() < 8 {
+        return None;
+    }
+
+    let quarter = window.len() / 4;
+    let edge_mean =
+        |edge: &[&(u32, f64)]| edge.iter().map(|(_, y)| y).sum::<f64>() / quarter as f64;
+    let a = edge_mean(&window[..quarter]);
+    let b = edge_mean(&window[window.len() - quarter..]) - a;
+
+    let points = window
+        .iter()
+        .filter_map(|&&(t, y)| {
+            let fraction = (y - a) / b;
+            (fraction > 0.05 && fraction < 0.95)
+                .then(|| (f64::from(t), (fraction / (1.0 - fraction)).ln()))
+        })
+        .collect::<Vec<_>>();
+    // a transition faster than the sample spacing has nothing to fit
+    if points.len() < 2 {
+        return None;
+    }
+
+    let n = points.len() as f64;
+    let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+    let mean_z = points.iter().map(|(_, z)| z).sum::<f64>() / n;
+    let covariance = points
+        .iter()
+        .map(|(t, z)| (t - mean_t) * (z - mean_z))
+        .sum::<f64>();
+    let variance = points
+        .iter()
+        .map(|(t, _)| (t - mean_t).powi(2))
+        .sum::<f64>();
+    let inverse_width = covariance / variance;
+    if !(inverse_width.is_finite() && inverse_width > 0.0) {
+        return None;
+    }
+    let center = mean_t - mean_z / inverse_width;
+
+    let fraction = (changepoint.level - a) / b;
+    if !(fraction > 0.0 && fraction < 1.0) {
+        return None;
+    }
+    let estimate_us = center + (fraction / (1.0 - fraction)).ln() / inverse_width;
+    let (first, last) = (window[0].0, window[window.len() - 1].0);
+    if !(estimate_us >= f64::from(first) && estimate_us <= f64::from(last)) {
+        return None;
+    }
+
+    let spacing = window
+        .windows(2)
+        .map(|pair| f64::from(pair[1].0 - pair[0].0))
+        .sum::<f64>()
+        / (window.len() - 1) as f64;
+    let slope = b * fraction * (1.0 - fraction) * inverse_width;
+    let (low_us, high_us) = interval(estimate_us, spacing, changepoint.noise, slope);
+
+    Some(Estimate {
+        sample_us: t1,
+        estimate_us,
+        low_us,
+        high_us,
+    })
+}
+
+/// The sample after the change, which happened somewhere since the previous one
+fn none(samples: &[(u32, f64)], idx: usize) -> Estimate {
+    let t1 = samples[idx].0;
+    let t0 = idx.checked_sub(1).map_or(t1, |i| samples[i].0);
+    Estimate {
+        sample_us: t1,
+        estimate_us: f64::from(t1),
+        low_us: f64::from(t0),
+        high_us: f64::from(t1),
+    }
+}
+
+/// Parses `--interpolation`
+pub fn parse_interpolation(s: &str) -> Result<Interpolation, String> {
+    serde_json::from_value(serde_json::Value::String(s.to_owned())).map_err(|e| e.to_string())
+}
+
+pub fn estimate(
+    timeline: &[Moment],
+    changepoint: &Changepoint,
+    interpolation: Interpolation,
+) -> Estimate {
+    let samples = light_levels(timeline);
+    let Some(idx) = samples
+        .iter()
+        .position(|(t, _)| *t == changepoint.microsecond)
+    else {
+        return Estimate {
+            sample_us: changepoint.microsecond,
+            estimate_us: f64::from(changepoint.microsecond),
+            low_us: f64::from(changepoint.microsecond),
+            high_us: f64::from(changepoint.microsecond),
+        };
+    };
+
+    match interpolation {
+        Interpolation::None => none(&samples, idx),
+        Interpolation::Linear => linear(&samples, idx, changepoint),
+        Interpolation::Sigmoid => sigmoid(&samples, idx, changepoint)
+            .unwrap_or_else(|| linear(&samples, idx, changepoint)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use late_mate_device::scenario::Event;
+
+    fn timeline(level: impl Fn(f64) -> f64) -> Vec<Moment> {
+        (0..40_000)
+            .step_by(500)
+            .map(|microsecond| Moment {
+                microsecond,
+                event: Event::LightLevel(level(f64::from(microsecond)).round() as u32),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_linear() {
+        // ramp from 0 to 1000 between 10ms and 11ms, crossing 300 at 10.3ms
+        let timeline = timeline(|t| ((t - 10_000.0) / 1_000.0).clamp(0.0, 1.0) * 1000.0);
+        let changepoint = Changepoint {
+            microsecond: 10_500,
+            level: 300.0,
+            noise: 0.0,
+        };
+
+        let linear = estimate(&timeline, &changepoint, Interpolation::Linear);
+        assert!((linear.estimate_us - 10_300.0).abs() < 1.0);
+        assert!(linear.low_us < 10_300.0 && linear.high_us > 10_300.0);
+        // only quantisation without noise, ±1.96 × 500 / √12
+        assert!((linear.high_us - linear.low_us - 565.8).abs() < 1.0);
+
+        let none = estimate(&timeline, &changepoint, Interpolation::None);
+        assert_eq!(none.estimate_us, 10_500.0);
+        assert_eq!(none.low_us, 10_000.0);
+    }
+
+    #[test]
+    fn test_sigmoid() {
+        let center = 20_130.0;
+        let timeline = timeline(|t| 100.0 + 900.0 / (1.0 + (-(t - center) / 800.0).exp()));
+        let changepoint = Changepoint {
+            microsecond: 20_500,
+            // half of the swing is at the center
+            level: 550.0,
+            noise: 1.0,
+        };
+
+        let sigmoid = estimate(&timeline, &changepoint, Interpolation::Sigmoid);
+        assert!((sigmoid.estimate_us - center).abs() < 50.0, "{sigmoid:?}");
+
+        // a step is too fast to fit, the linear estimate is used instead
+        let step = self::timeline(|t| if t < 20_000.0 { 0.0 } else { 1000.0 });
+        let changepoint = Changepoint {
+            microsecond: 20_000,
+            level: 500.0,
+            noise: 0.0,
+        };
+        assert_eq!(
+            estimate(&step, &changepoint, Interpolation::Sigmoid),
+            estimate(&step, &changepoint, Interpolation::Linear)
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Pixel response: how long the light level takes to go from the old level to the new one
+//! after the changepoint, and how far it overshoots
+
+use super::changepoint::{light_levels, Changepoint};
+use late_mate_device::scenario::Moment;
+
+/// The levels before and after the change are averaged over this much of the recording
+/// at its start and its end
+const LEVEL_WINDOW_US: u32 = 5_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct Response {
+    pub rising: bool,
+    /// Time between the signal covering 10% and 90% of the way to the settled level
+    pub transition_us: f64,
+    /// How far the signal went beyond the settled level, in percent of the swing
+    pub overshoot_percent: f64,
+    /// How far the signal came back short of the settled level after overshooting,
+    /// in percent of the swing
+    pub undershoot_percent: f64,
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0f64, 0usize), |(sum, count), v| (sum + v, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+/// Time where the line between two points crosses the level
+fn crossing((t0, f0): (f64, f64), (t1, f1): (f64, f64), level: f64) -> f64 {
+    t0 + (level - f0) / (f1 - f0) * (t1 - t0)
+}
+
+/// None if the levels can't be established or the signal doesn't cover 10%–90% of the way
+pub fn measure(timeline: &[Moment], changepoint: &Changepoint) -> Option<Response> {
+    let samples = light_levels(timeline);
+    let idx = samples
+        .iter()
+        .position(|(t, _)| *t == changepoint.microsecond)?;
+    let last_time = samples.last()?.0;
+
+    let initial = mean(
+        samples[..idx]
+            .iter()
+            .take_while(|(t, _)| *t < LEVEL_WINDOW_US)
+            .map(|(_, y)| *y),
+    )?;
+    let settled = mean(
+        samples[idx..]
+            .iter()
+            .filter(|(t, _)| *t > last_time.saturating_sub(LEVEL_WINDOW_US))
+            .map(|(_, y)| *y),
+    )?;
+    let swing = settled - initial;
+    if swing == 0.0 {
+        return None;
+    }
+
+    // fraction of the way from the initial level to the settled one
+    let fractions = samples
+        .iter()
+        .map(|(t, y)| (f64::from(*t), (y - initial) / swing))
+        .collect::<Vec<_>>();
+
+    // the last time the signal was still near the initial level before the changepoint
+    let before_change = fractions[..=idx].iter().rposition(|(_, f)| *f < 0.1)?;
+    let end = before_change
+        + fractions[before_change..]
+            .iter()
+            .position(|(_, f)| *f >= 0.9)?;
+    // a changepoint that's found early can be well before the signal starts to change
+    let start = fractions[..end].iter().rposition(|(_, f)| *f < 0.1)?;
+    let t10 = crossing(fractions[start], fractions[start + 1], 0.1);
+    let t90 = crossing(fractions[end - 1], fractions[end], 0.9);
+
+    // light levels are noisy, extremes are taken from a moving average
+    let smoothed = fractions[end..]
+        .windows(3)
+        .map(|w| w.iter().map(|(_, f)| f).sum::<f64>() / 3.0)
+        .collect::<Vec<_>>();
+    let (peak_idx, peak) = smoothed
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map_or((0, 1.0), |(i, f)| (i, *f));
+    let overshoot = (peak - 1.0).max(0.0);
+    let undershoot = if overshoot > 0.0 {
+        let trough = smoothed[peak_idx..]
+            .iter()
+            .copied()
+            .min_by(f64::total_cmp)
+            .unwrap_or(1.0);
+        (1.0 - trough).max(0.0)
+    } else {
+        0.0
+    };
+
+    Some(Response {
+        rising: swing > 0.0,
+        transition_us: t90 - t10,
+        overshoot_percent: overshoot * 100.0,
+        undershoot_percent: undershoot * 100.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use late_mate_device::scenario::Event;
+
+    fn timeline(level: impl Fn(f64) -> f64) -> Vec<Moment> {
+        (0..40_000)
+            .step_by(500)
+            .map(|microsecond| Moment {
+                microsecond,
+                event: Event::LightLevel(level(f64::from(microsecond)).round() as u32),
+            })
+            .collect()
+    }
+
+    fn changepoint(microsecond: u32) -> Changepoint {
+        Changepoint {
+            microsecond,
+            level: 0.0,
+            noise: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_transition() {
+        // 2ms ramp from 1000 down to 0 starting at 10ms, 10%–90% takes 1.6ms
+        let falling = timeline(|t| (1.0 - ((t - 10_000.0) / 2_000.0).clamp(0.0, 1.0)) * 1000.0);
+        let response = measure(&falling, &changepoint(10_500)).unwrap();
+        assert!(!response.rising);
+        assert!((response.transition_us - 1_600.0).abs() < 1.0);
+        assert_eq!(response.overshoot_percent, 0.0);
+        assert_eq!(response.undershoot_percent, 0.0);
+
+        // the transition time doesn't depend on how early the changepoint is found
+        let early = measure(&falling, &changepoint(7_000)).unwrap();
+        assert!((early.transition_us - 1_600.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_overshoot() {
+        // jumps to 1200 at 10ms, drops to 950 at 15ms and settles at 1000 at 20ms
+        let overshooting = timeline(|t| match t {
+            t if t < 10_000.0 => 0.0,
+            t if t < 15_000.0 => 1200.0,
+            t if t < 20_000.0 => 950.0,
+            _ => 1000.0,
+        });
+        let response = measure(&overshooting, &changepoint(10_000)).unwrap();
+        assert!(response.rising);
+        assert!((response.overshoot_percent - 20.0).abs() < 0.01);
+        assert!((response.undershoot_percent - 5.0).abs() < 0.01);
+
+        // the change happens before there's anything to establish the initial level from
+        assert_eq!(measure(&overshooting, &changepoint(0)), None);
+    }
+}
@@ -0,0 +1,147 @@
+//! Splitting a recording into all level transitions, not just the first one. Intervals
+//! between transitions show frame times and judder of e.g. scrolling or dragging
+
+use super::changepoint::light_levels;
+use late_mate_device::scenario::Moment;
+
+/// A change must be larger than this many standard deviations of the starting noise
+const BAND_SIGMAS: f64 = 5.0;
+
+/// Consecutive samples outside of the band that start a transition
+const CONFIRM_SAMPLES: usize = 2;
+
+/// Consecutive samples within one band of each other that end a transition
+const SETTLE_SAMPLES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize)]
+pub struct Transition {
+    /// The first light level sample that left the previous level
+    pub microsecond: u32,
+    pub rising: bool,
+    pub from_level: f64,
+    pub to_level: f64,
+    /// Absolute difference between the levels
+    pub magnitude: f64,
+    /// Time since the previous transition
+    pub interval_us: Option<u32>,
+}
+
+fn mean(values: &[(u32, f64)]) -> f64 {
+    values.iter().map(|(_, y)| y).sum::<f64>() / values.len() as f64
+}
+
+/// Noise is estimated from the first `noise_window_us` of the recording, where the light level
+/// is expected to be stable. Slow transitions can be split in several
+pub fn find_transitions(timeline: &[Moment], noise_window_us: u32) -> Vec<Transition> {
+    let samples = light_levels(timeline);
+    let baseline = samples
+        .iter()
+        .take_while(|(t, _)| *t < noise_window_us)
+        .count();
+    if baseline < 2 {
+        return vec![];
+    }
+
+    let mut level = mean(&samples[..baseline]);
+    let variance = samples[..baseline]
+        .iter()
+        .map(|(_, y)| (y - level).powi(2))
+        .sum::<f64>()
+        / baseline as f64;
+    // perfectly quiet sensors still have a quantisation step
+    let band = BAND_SIGMAS * variance.sqrt().max(1.0);
+
+    let mut transitions: Vec<Transition> = vec![];
+    let mut i = baseline;
+    while i + CONFIRM_SAMPLES <= samples.len() {
+        let confirmed = samples[i..i + CONFIRM_SAMPLES]
+            .iter()
+            .all(|(_, y)| (y - level).abs() > band && (y > &level) == (samples[i].1 > level));
+        if !confirmed {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        let Some(settled) = (start..=samples.len().saturating_sub(SETTLE_SAMPLES)).find(|&j| {
+            let window = &samples[j..j + SETTLE_SAMPLES];
+            let (min, max) = window
+                .iter()
+                .fold((f64::MAX, f64::MIN), |(min, max), (_, y)| {
+                    (min.min(*y), max.max(*y))
+                });
+            max - min <= band
+        }) else {
+            // the recording ended mid-transition
+            break;
+        };
+
+        let new_level = mean(&samples[settled..settled + SETTLE_SAMPLES]);
+        // otherwise it was a spike that came back to the same level
+        if (new_level - level).abs() > band {
+            let microsecond = samples[start].0;
+            transitions.push(Transition {
+                microsecond,
+                rising: new_level > level,
+                from_level: level,
+                to_level: new_level,
+                magnitude: (new_level - level).abs(),
+                interval_us: transitions.last().map(|t| microsecond - t.microsecond),
+            });
+            level = new_level;
+        }
+        i = settled + SETTLE_SAMPLES;
+    }
+
+    transitions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use late_mate_device::scenario::Event;
+
+    fn timeline(level: impl Fn(u32) -> u32) -> Vec<Moment> {
+        (0..60_000)
+            .step_by(500)
+            .enumerate()
+            .map(|(i, microsecond)| Moment {
+                microsecond,
+                // a little noise
+                event: Event::LightLevel(level(microsecond) + (i as u32 % 3)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_frames() {
+        // 1000 → 2000 → 1000 → 3000 with a 1-sample spike in between
+        let frames = timeline(|t| match t {
+            t if t < 10_000 => 1000,
+            t if t < 26_500 => 2000,
+            30_000 => 5000,
+            t if t < 43_000 => 1000,
+            _ => 3000,
+        });
+        let transitions = find_transitions(&frames, 7_000);
+
+        let times = transitions
+            .iter()
+            .map(|t| (t.microsecond, t.rising, t.interval_us))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            times,
+            vec![
+                (10_000, true, None),
+                (26_500, false, Some(16_500)),
+                (43_000, true, Some(16_500))
+            ]
+        );
+        assert!((transitions[0].magnitude - 1000.0).abs() < 2.0);
+        assert!((transitions[2].magnitude - 2000.0).abs() < 2.0);
+
+        let flat = timeline(|_| 1000);
+        assert_eq!(find_transitions(&flat, 7_000), vec![]);
+        assert_eq!(find_transitions(&[], 7_000), vec![]);
+    }
+}
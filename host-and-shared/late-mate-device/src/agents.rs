@@ -1,4 +1,5 @@
 pub mod agent_watcher;
 pub mod dispatcher;
+pub mod tcp;
 pub mod usb_rx;
 pub mod usb_tx;
@@ -0,0 +1,76 @@
+//! Connection to a device shared by `late-mate proxy`. The stream uses the same CRC+COBS framing
+//! as USB and produces the same handles as the USB agents, so the rest of the driver
+//! doesn't know the difference
+
+use crate::agents::usb_rx::{self, ProcessingError, UsbRxHandle};
+use crate::agents::usb_tx::{TxRequest, UsbTxHandle};
+use crate::usb::ALIGNED_BUFFER_SIZE;
+use crate::Error;
+use late_mate_shared::comms;
+use late_mate_shared::comms::{device_to_host, CrcCobsAccumulator};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+async fn tcp_rx_loop(mut read_half: OwnedReadHalf, sender: mpsc::Sender<device_to_host::Envelope>) {
+    let mut buf = vec![0; ALIGNED_BUFFER_SIZE];
+    let mut cobs_acc = CrcCobsAccumulator::new();
+
+    loop {
+        let len = match read_half.read(&mut buf).await {
+            Ok(0) => {
+                tracing::error!("The proxy closed the connection, TCP RX loop exiting");
+                break;
+            }
+            Ok(len) => len,
+            Err(e) => {
+                tracing::error!("TCP RX error: {e}, TCP RX loop exiting");
+                break;
+            }
+        };
+
+        match usb_rx::process_packet(&sender, &mut cobs_acc, &buf[..len]).await {
+            Ok(_) => (),
+            Err(ProcessingError::ChannelClosed) => {
+                tracing::info!("Envelope receiver is dropped, TCP RX loop exiting");
+                break;
+            }
+            Err(e) => {
+                tracing::error!("TCP packet deserialisation error: {e}");
+            }
+        }
+    }
+}
+
+async fn tcp_tx_loop(mut write_half: OwnedWriteHalf, mut receiver: mpsc::Receiver<TxRequest>) {
+    let mut buf = vec![0; ALIGNED_BUFFER_SIZE];
+
+    loop {
+        let Some((envelope, reply_error_to)) = receiver.recv().await else {
+            tracing::info!("All senders are dropped, TCP TX loop exiting");
+            break;
+        };
+
+        let used_len = comms::encode(&envelope, buf.as_mut_slice());
+        if let Err(e) = write_half.write_all(&buf[..used_len]).await {
+            // if the receiver was dropped, the error doesn't matter anyway
+            _ = reply_error_to.send(Error::NetworkError("sending data", e));
+            tracing::error!("Proxy connection is broken, TCP TX loop exiting");
+            break;
+        }
+    }
+}
+
+pub fn start(agent_set: &mut JoinSet<()>, stream: TcpStream) -> (UsbRxHandle, UsbTxHandle) {
+    let (read_half, write_half) = stream.into_split();
+
+    let (rx_sender, rx_receiver) = mpsc::channel(16);
+    agent_set.spawn(tcp_rx_loop(read_half, rx_sender));
+
+    let (tx_sender, tx_receiver) = mpsc::channel(4);
+    agent_set.spawn(tcp_tx_loop(write_half, tx_receiver));
+
+    (UsbRxHandle::new(rx_receiver), UsbTxHandle::new(tx_sender))
+}
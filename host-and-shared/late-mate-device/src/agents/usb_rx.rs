@@ -7,7 +7,7 @@ use tokio::sync::mpsc;
 use tokio::task::JoinSet;
 
 #[derive(Debug, thiserror::Error)]
-enum ProcessingError {
+pub enum ProcessingError {
     #[error("CRC/COBS buffer is overfull")]
     BufferOverfull,
     #[error("Postcard error: {0:?}")]
@@ -16,13 +16,14 @@ enum ProcessingError {
     ChannelClosed,
 }
 
-async fn process_packet(
-    sender: &mpsc::Sender<device_to_host::Envelope>,
+/// Also used for TCP streams, where packets are whatever a single read returned
+pub async fn process_packet<T: for<'de> serde::Deserialize<'de>>(
+    sender: &mpsc::Sender<T>,
     cobs_acc: &mut CrcCobsAccumulator,
     mut packet: &[u8],
 ) -> Result<(), ProcessingError> {
     'cobs: while !packet.is_empty() {
-        packet = match cobs_acc.feed::<device_to_host::Envelope>(packet) {
+        packet = match cobs_acc.feed::<T>(packet) {
             FeedResult::Consumed => break 'cobs,
             FeedResult::OverFull { .. } => {
                 return Err(ProcessingError::BufferOverfull);
@@ -96,6 +97,10 @@ pub struct UsbRxHandle {
 }
 
 impl UsbRxHandle {
+    pub fn new(receiver: mpsc::Receiver<device_to_host::Envelope>) -> Self {
+        Self { receiver }
+    }
+
     pub async fn recv(&mut self) -> Option<device_to_host::Envelope> {
         self.receiver.recv().await
     }
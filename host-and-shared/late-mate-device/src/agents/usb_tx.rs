@@ -9,9 +9,12 @@ use tokio::sync::{mpsc, oneshot};
 use tokio::task::JoinSet;
 use tokio::time::timeout;
 
+/// The envelope to send and where to report the error to, dropping the error sender means success
+pub type TxRequest = (host_to_device::Envelope, oneshot::Sender<Error>);
+
 async fn usb_tx_loop(
     mut out_queue: transfer::Queue<Vec<u8>>,
-    mut receiver: mpsc::Receiver<(host_to_device::Envelope, oneshot::Sender<Error>)>,
+    mut receiver: mpsc::Receiver<TxRequest>,
 ) {
     let mut buf = vec![0; ALIGNED_BUFFER_SIZE];
 
@@ -50,10 +53,14 @@ async fn usb_tx_loop(
 
 #[derive(Debug, Clone)]
 pub struct UsbTxHandle {
-    sender: mpsc::Sender<(host_to_device::Envelope, oneshot::Sender<Error>)>,
+    sender: mpsc::Sender<TxRequest>,
 }
 
 impl UsbTxHandle {
+    pub fn new(sender: mpsc::Sender<TxRequest>) -> Self {
+        Self { sender }
+    }
+
     pub async fn send(&self, envelope: host_to_device::Envelope) -> Result<(), Error> {
         let (error_sender, error_receiver) = oneshot::channel();
 
@@ -1,9 +1,24 @@
 use late_mate_shared::comms;
 
+mod keys;
+pub mod layout;
+
+pub use keys::{KeyParseError, KeyboardKey};
+
 /// This is a neater/more convenient version of HID stuff from late-mate-shared
 
 #[non_exhaustive]
-#[derive(Debug, Eq, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize, ts_rs::TS)]
+#[derive(
+    Debug,
+    Eq,
+    PartialEq,
+    Clone,
+    Copy,
+    serde::Deserialize,
+    serde::Serialize,
+    ts_rs::TS,
+    schemars::JsonSchema,
+)]
 #[repr(u8)]
 #[serde(rename_all = "snake_case")]
 pub enum MouseButton {
@@ -12,7 +27,17 @@ pub enum MouseButton {
     Middle = 0x03,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Default, serde::Deserialize, serde::Serialize, ts_rs::TS)]
+#[derive(
+    Debug,
+    Eq,
+    PartialEq,
+    Clone,
+    Default,
+    serde::Deserialize,
+    serde::Serialize,
+    ts_rs::TS,
+    schemars::JsonSchema,
+)]
 #[serde(default, deny_unknown_fields)]
 #[serde(rename_all = "snake_case")]
 pub struct MouseReport {
@@ -48,7 +73,17 @@ impl From<&MouseReport> for comms::hid::MouseReport {
 }
 
 // see https://gist.github.com/MightyPork/6da26e382a7ad91b5496ee55fdc73db2
-#[derive(Debug, Eq, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize, ts_rs::TS)]
+#[derive(
+    Debug,
+    Eq,
+    PartialEq,
+    Clone,
+    Copy,
+    serde::Deserialize,
+    serde::Serialize,
+    ts_rs::TS,
+    schemars::JsonSchema,
+)]
 #[repr(u8)]
 #[serde(rename_all = "snake_case")]
 pub enum KeyboardModifier {
@@ -62,98 +97,34 @@ pub enum KeyboardModifier {
     RMeta = 0x80,
 }
 
-// see https://docs.rs/usbd-hid/latest/usbd_hid/descriptor/enum.KeyboardUsage.html
-// adjusted for readability
-#[non_exhaustive]
-#[derive(Debug, Eq, PartialEq, Clone, Copy, serde::Deserialize, serde::Serialize, ts_rs::TS)]
-#[repr(u8)]
-#[serde(rename_all = "snake_case")]
-pub enum KeyboardKey {
-    A = 4,
-    B = 5,
-    C = 6,
-    D = 7,
-    E = 8,
-    F = 9,
-    G = 10,
-    H = 11,
-    I = 12,
-    J = 13,
-    K = 14,
-    L = 15,
-    M = 16,
-    N = 17,
-    O = 18,
-    P = 19,
-    Q = 20,
-    R = 21,
-    S = 22,
-    T = 23,
-    U = 24,
-    V = 25,
-    W = 26,
-    X = 27,
-    Y = 28,
-    Z = 29,
-    OneExclamation = 30,
-    TwoAt = 31,
-    ThreeHash = 32,
-    FourDollar = 33,
-    FivePercent = 34,
-    SixCaret = 35,
-    SevenAmpersand = 36,
-    EightAsterisk = 37,
-    NineOpenParens = 38,
-    ZeroCloseParens = 39,
-    Enter = 40,
-    Escape = 41,
-    Backspace = 42,
-    Tab = 43,
-    Spacebar = 44,
-    DashUnderscore = 45,
-    EqualPlus = 46,
-    OpenBracketBrace = 47,
-    CloseBracketBrace = 48,
-    BackslashBar = 49,
-    NonUSHash = 50,
-    SemiColon = 51,
-    SingleDoubleQuote = 52,
-    BacktickTilde = 53,
-    CommaLess = 54,
-    PeriodGreater = 55,
-    SlashQuestion = 56,
-    CapsLock = 57,
-    F1 = 58,
-    F2 = 59,
-    F3 = 60,
-    F4 = 61,
-    F5 = 62,
-    F6 = 63,
-    F7 = 64,
-    F8 = 65,
-    F9 = 66,
-    F10 = 67,
-    F11 = 68,
-    F12 = 69,
-    PrintScreen = 70,
-    ScrollLock = 71,
-    Pause = 72,
-    Insert = 73,
-    Home = 74,
-    PageUp = 75,
-    Delete = 76,
-    End = 77,
-    PageDown = 78,
-    Right = 79,
-    Left = 80,
-    Down = 81,
-    Up = 82,
-    Mute = 127,
-    VolumeUp = 128,
-    VolumeDown = 129,
+impl KeyboardModifier {
+    /// Modifiers have their own key usages (0xE0–0xE7), but are sent as bits in reports
+    pub fn from_key(key: KeyboardKey) -> Option<Self> {
+        match key {
+            KeyboardKey::LeftCtrl => Some(KeyboardModifier::LCtrl),
+            KeyboardKey::LeftShift => Some(KeyboardModifier::LShift),
+            KeyboardKey::LeftAlt => Some(KeyboardModifier::LAlt),
+            KeyboardKey::LeftMeta => Some(KeyboardModifier::LMeta),
+            KeyboardKey::RightCtrl => Some(KeyboardModifier::RCtrl),
+            KeyboardKey::RightShift => Some(KeyboardModifier::RShift),
+            KeyboardKey::RightAlt => Some(KeyboardModifier::RAlt),
+            KeyboardKey::RightMeta => Some(KeyboardModifier::RMeta),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, Default, serde::Deserialize, serde::Serialize, ts_rs::TS)]
+#[derive(
+    Debug,
+    Eq,
+    PartialEq,
+    Clone,
+    Default,
+    serde::Deserialize,
+    serde::Serialize,
+    ts_rs::TS,
+    schemars::JsonSchema,
+)]
 #[serde(default, deny_unknown_fields)]
 #[serde(rename_all = "snake_case")]
 pub struct KeyboardReport {
@@ -184,7 +155,16 @@ impl From<&KeyboardReport> for comms::hid::KeyboardReport {
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS)]
+#[derive(
+    Debug,
+    Eq,
+    PartialEq,
+    Clone,
+    serde::Deserialize,
+    serde::Serialize,
+    ts_rs::TS,
+    schemars::JsonSchema,
+)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum HidReport {
     Mouse(MouseReport),
@@ -0,0 +1,467 @@
+use serde::de::Error as _;
+use std::str::FromStr;
+
+// Names are spelled out explicitly instead of being derived from variant names, so that
+// renaming a variant can never break existing scenario files
+macro_rules! keyboard_keys {
+    ($($variant:ident = $usage:literal => $name:literal $([$($alias:literal),+])?,)*) => {
+        /// The entire HID Keyboard/Keypad usage page (0x04–0xE7), see
+        /// https://usb.org/sites/default/files/hut1_5.pdf (section 10)
+        ///
+        /// Apart from the names, keys can be referred to by their aliases (e.g. "1", "ctrl") or
+        /// by raw usage codes in hex (e.g. "0x59").
+        #[non_exhaustive]
+        #[derive(Debug, Eq, PartialEq, Clone, Copy)]
+        #[repr(u8)]
+        pub enum KeyboardKey {
+            $($variant = $usage,)*
+        }
+
+        impl KeyboardKey {
+            pub const ALL: &'static [KeyboardKey] = &[$(KeyboardKey::$variant,)*];
+
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(KeyboardKey::$variant => $name,)*
+                }
+            }
+
+            pub fn aliases(&self) -> &'static [&'static str] {
+                match self {
+                    $(KeyboardKey::$variant => &[$($($alias),+)?],)*
+                }
+            }
+
+            pub fn from_usage(usage: u8) -> Option<Self> {
+                match usage {
+                    $($usage => Some(KeyboardKey::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+keyboard_keys! {
+    A = 0x04 => "a",
+    B = 0x05 => "b",
+    C = 0x06 => "c",
+    D = 0x07 => "d",
+    E = 0x08 => "e",
+    F = 0x09 => "f",
+    G = 0x0A => "g",
+    H = 0x0B => "h",
+    I = 0x0C => "i",
+    J = 0x0D => "j",
+    K = 0x0E => "k",
+    L = 0x0F => "l",
+    M = 0x10 => "m",
+    N = 0x11 => "n",
+    O = 0x12 => "o",
+    P = 0x13 => "p",
+    Q = 0x14 => "q",
+    R = 0x15 => "r",
+    S = 0x16 => "s",
+    T = 0x17 => "t",
+    U = 0x18 => "u",
+    V = 0x19 => "v",
+    W = 0x1A => "w",
+    X = 0x1B => "x",
+    Y = 0x1C => "y",
+    Z = 0x1D => "z",
+    OneExclamation = 0x1E => "one_exclamation" ["1"],
+    TwoAt = 0x1F => "two_at" ["2"],
+    ThreeHash = 0x20 => "three_hash" ["3"],
+    FourDollar = 0x21 => "four_dollar" ["4"],
+    FivePercent = 0x22 => "five_percent" ["5"],
+    SixCaret = 0x23 => "six_caret" ["6"],
+    SevenAmpersand = 0x24 => "seven_ampersand" ["7"],
+    EightAsterisk = 0x25 => "eight_asterisk" ["8"],
+    NineOpenParens = 0x26 => "nine_open_parens" ["9"],
+    ZeroCloseParens = 0x27 => "zero_close_parens" ["0"],
+    Enter = 0x28 => "enter",
+    Escape = 0x29 => "escape" ["esc"],
+    Backspace = 0x2A => "backspace",
+    Tab = 0x2B => "tab",
+    Spacebar = 0x2C => "spacebar" ["space"],
+    DashUnderscore = 0x2D => "dash_underscore" ["-"],
+    EqualPlus = 0x2E => "equal_plus" ["="],
+    OpenBracketBrace = 0x2F => "open_bracket_brace" ["["],
+    CloseBracketBrace = 0x30 => "close_bracket_brace" ["]"],
+    BackslashBar = 0x31 => "backslash_bar" ["\\"],
+    // the name is how it has always been derived from the variant name
+    NonUSHash = 0x32 => "non_u_s_hash" ["non_us_hash"],
+    SemiColon = 0x33 => "semi_colon" [";"],
+    SingleDoubleQuote = 0x34 => "single_double_quote" ["'"],
+    BacktickTilde = 0x35 => "backtick_tilde" ["`"],
+    CommaLess = 0x36 => "comma_less" [","],
+    PeriodGreater = 0x37 => "period_greater" ["."],
+    SlashQuestion = 0x38 => "slash_question" ["/"],
+    CapsLock = 0x39 => "caps_lock",
+    F1 = 0x3A => "f1",
+    F2 = 0x3B => "f2",
+    F3 = 0x3C => "f3",
+    F4 = 0x3D => "f4",
+    F5 = 0x3E => "f5",
+    F6 = 0x3F => "f6",
+    F7 = 0x40 => "f7",
+    F8 = 0x41 => "f8",
+    F9 = 0x42 => "f9",
+    F10 = 0x43 => "f10",
+    F11 = 0x44 => "f11",
+    F12 = 0x45 => "f12",
+    PrintScreen = 0x46 => "print_screen",
+    ScrollLock = 0x47 => "scroll_lock",
+    Pause = 0x48 => "pause",
+    Insert = 0x49 => "insert",
+    Home = 0x4A => "home",
+    PageUp = 0x4B => "page_up" ["pgup"],
+    Delete = 0x4C => "delete",
+    End = 0x4D => "end",
+    PageDown = 0x4E => "page_down" ["pgdn"],
+    Right = 0x4F => "right" ["arrow_right"],
+    Left = 0x50 => "left" ["arrow_left"],
+    Down = 0x51 => "down" ["arrow_down"],
+    Up = 0x52 => "up" ["arrow_up"],
+    KeypadNumLock = 0x53 => "keypad_num_lock" ["num_lock"],
+    KeypadDivide = 0x54 => "keypad_divide",
+    KeypadMultiply = 0x55 => "keypad_multiply",
+    KeypadMinus = 0x56 => "keypad_minus",
+    KeypadPlus = 0x57 => "keypad_plus",
+    KeypadEnter = 0x58 => "keypad_enter",
+    Keypad1 = 0x59 => "keypad_1",
+    Keypad2 = 0x5A => "keypad_2",
+    Keypad3 = 0x5B => "keypad_3",
+    Keypad4 = 0x5C => "keypad_4",
+    Keypad5 = 0x5D => "keypad_5",
+    Keypad6 = 0x5E => "keypad_6",
+    Keypad7 = 0x5F => "keypad_7",
+    Keypad8 = 0x60 => "keypad_8",
+    Keypad9 = 0x61 => "keypad_9",
+    Keypad0 = 0x62 => "keypad_0",
+    KeypadPeriod = 0x63 => "keypad_period",
+    NonUSBackslash = 0x64 => "non_us_backslash",
+    Application = 0x65 => "application" ["context_menu"],
+    Power = 0x66 => "power",
+    KeypadEqual = 0x67 => "keypad_equal",
+    F13 = 0x68 => "f13",
+    F14 = 0x69 => "f14",
+    F15 = 0x6A => "f15",
+    F16 = 0x6B => "f16",
+    F17 = 0x6C => "f17",
+    F18 = 0x6D => "f18",
+    F19 = 0x6E => "f19",
+    F20 = 0x6F => "f20",
+    F21 = 0x70 => "f21",
+    F22 = 0x71 => "f22",
+    F23 = 0x72 => "f23",
+    F24 = 0x73 => "f24",
+    Execute = 0x74 => "execute",
+    Help = 0x75 => "help",
+    Menu = 0x76 => "menu",
+    Select = 0x77 => "select",
+    Stop = 0x78 => "stop",
+    Again = 0x79 => "again",
+    Undo = 0x7A => "undo",
+    Cut = 0x7B => "cut",
+    Copy = 0x7C => "copy",
+    Paste = 0x7D => "paste",
+    Find = 0x7E => "find",
+    Mute = 0x7F => "mute",
+    VolumeUp = 0x80 => "volume_up",
+    VolumeDown = 0x81 => "volume_down",
+    LockingCapsLock = 0x82 => "locking_caps_lock",
+    LockingNumLock = 0x83 => "locking_num_lock",
+    LockingScrollLock = 0x84 => "locking_scroll_lock",
+    KeypadComma = 0x85 => "keypad_comma",
+    KeypadEqualAs400 = 0x86 => "keypad_equal_as400",
+    International1 = 0x87 => "international_1" ["ro"],
+    International2 = 0x88 => "international_2" ["katakana_hiragana"],
+    International3 = 0x89 => "international_3" ["yen"],
+    International4 = 0x8A => "international_4" ["henkan"],
+    International5 = 0x8B => "international_5" ["muhenkan"],
+    International6 = 0x8C => "international_6",
+    International7 = 0x8D => "international_7",
+    International8 = 0x8E => "international_8",
+    International9 = 0x8F => "international_9",
+    Lang1 = 0x90 => "lang_1" ["hangul_english"],
+    Lang2 = 0x91 => "lang_2" ["hanja"],
+    Lang3 = 0x92 => "lang_3" ["katakana"],
+    Lang4 = 0x93 => "lang_4" ["hiragana"],
+    Lang5 = 0x94 => "lang_5" ["zenkaku_hankaku"],
+    Lang6 = 0x95 => "lang_6",
+    Lang7 = 0x96 => "lang_7",
+    Lang8 = 0x97 => "lang_8",
+    Lang9 = 0x98 => "lang_9",
+    AlternateErase = 0x99 => "alternate_erase",
+    SysReq = 0x9A => "sys_req",
+    Cancel = 0x9B => "cancel",
+    Clear = 0x9C => "clear",
+    Prior = 0x9D => "prior",
+    Return = 0x9E => "return",
+    Separator = 0x9F => "separator",
+    Out = 0xA0 => "out",
+    Oper = 0xA1 => "oper",
+    ClearAgain = 0xA2 => "clear_again",
+    CrSel = 0xA3 => "cr_sel",
+    ExSel = 0xA4 => "ex_sel",
+    // 0xA5–0xAF are reserved
+    Keypad00 = 0xB0 => "keypad_00",
+    Keypad000 = 0xB1 => "keypad_000",
+    ThousandsSeparator = 0xB2 => "thousands_separator",
+    DecimalSeparator = 0xB3 => "decimal_separator",
+    CurrencyUnit = 0xB4 => "currency_unit",
+    CurrencySubunit = 0xB5 => "currency_subunit",
+    KeypadOpenParens = 0xB6 => "keypad_open_parens",
+    KeypadCloseParens = 0xB7 => "keypad_close_parens",
+    KeypadOpenBrace = 0xB8 => "keypad_open_brace",
+    KeypadCloseBrace = 0xB9 => "keypad_close_brace",
+    KeypadTab = 0xBA => "keypad_tab",
+    KeypadBackspace = 0xBB => "keypad_backspace",
+    KeypadA = 0xBC => "keypad_a",
+    KeypadB = 0xBD => "keypad_b",
+    KeypadC = 0xBE => "keypad_c",
+    KeypadD = 0xBF => "keypad_d",
+    KeypadE = 0xC0 => "keypad_e",
+    KeypadF = 0xC1 => "keypad_f",
+    KeypadXor = 0xC2 => "keypad_xor",
+    KeypadCaret = 0xC3 => "keypad_caret",
+    KeypadPercent = 0xC4 => "keypad_percent",
+    KeypadLess = 0xC5 => "keypad_less",
+    KeypadGreater = 0xC6 => "keypad_greater",
+    KeypadAmpersand = 0xC7 => "keypad_ampersand",
+    KeypadDoubleAmpersand = 0xC8 => "keypad_double_ampersand",
+    KeypadBar = 0xC9 => "keypad_bar",
+    KeypadDoubleBar = 0xCA => "keypad_double_bar",
+    KeypadColon = 0xCB => "keypad_colon",
+    KeypadHash = 0xCC => "keypad_hash",
+    KeypadSpace = 0xCD => "keypad_space",
+    KeypadAt = 0xCE => "keypad_at",
+    KeypadExclamation = 0xCF => "keypad_exclamation",
+    KeypadMemoryStore = 0xD0 => "keypad_memory_store",
+    KeypadMemoryRecall = 0xD1 => "keypad_memory_recall",
+    KeypadMemoryClear = 0xD2 => "keypad_memory_clear",
+    KeypadMemoryAdd = 0xD3 => "keypad_memory_add",
+    KeypadMemorySubtract = 0xD4 => "keypad_memory_subtract",
+    KeypadMemoryMultiply = 0xD5 => "keypad_memory_multiply",
+    KeypadMemoryDivide = 0xD6 => "keypad_memory_divide",
+    KeypadPlusMinus = 0xD7 => "keypad_plus_minus",
+    KeypadClear = 0xD8 => "keypad_clear",
+    KeypadClearEntry = 0xD9 => "keypad_clear_entry",
+    KeypadBinary = 0xDA => "keypad_binary",
+    KeypadOctal = 0xDB => "keypad_octal",
+    KeypadDecimal = 0xDC => "keypad_decimal",
+    KeypadHexadecimal = 0xDD => "keypad_hexadecimal",
+    // 0xDE–0xDF are reserved
+    // Modifiers are normally sent as KeyboardModifier bits, but they are valid key usages too
+    LeftCtrl = 0xE0 => "left_ctrl" ["ctrl", "l_ctrl"],
+    LeftShift = 0xE1 => "left_shift" ["shift", "l_shift"],
+    LeftAlt = 0xE2 => "left_alt" ["alt", "l_alt", "option"],
+    LeftMeta = 0xE3 => "left_meta" ["meta", "l_meta", "cmd", "win", "super"],
+    RightCtrl = 0xE4 => "right_ctrl" ["r_ctrl"],
+    RightShift = 0xE5 => "right_shift" ["r_shift"],
+    RightAlt = 0xE6 => "right_alt" ["r_alt", "alt_gr"],
+    RightMeta = 0xE7 => "right_meta" ["r_meta"],
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum KeyParseError {
+    #[error("Unknown key \"{0}\", see `late-mate hid show-type` for the list of keys")]
+    UnknownKey(String),
+    #[error("Invalid raw key usage \"{0}\", must be a hex number from 0x04 to 0xE7")]
+    InvalidUsage(String),
+    #[error("Raw key usage \"{0}\" is reserved or unassigned")]
+    ReservedUsage(String),
+}
+
+impl FromStr for KeyboardKey {
+    type Err = KeyParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            // from_str_radix alone would accept a sign
+            let is_hex = (1..=2).contains(&hex.len()) && hex.bytes().all(|b| b.is_ascii_hexdigit());
+            let usage = u8::from_str_radix(hex, 16)
+                .ok()
+                .filter(|usage| is_hex && (0x04..=0xE7).contains(usage))
+                .ok_or_else(|| KeyParseError::InvalidUsage(s.to_owned()))?;
+            return KeyboardKey::from_usage(usage)
+                .ok_or_else(|| KeyParseError::ReservedUsage(s.to_owned()));
+        }
+
+        KeyboardKey::ALL
+            .iter()
+            .find(|key| key.name() == s || key.aliases().contains(&s))
+            .copied()
+            .ok_or_else(|| KeyParseError::UnknownKey(s.to_owned()))
+    }
+}
+
+impl serde::Serialize for KeyboardKey {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for KeyboardKey {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+// Deriving TS for an enum this large overflows the trait solver, and it's just a union of
+// string literals anyway
+impl ts_rs::TS for KeyboardKey {
+    type WithoutGenerics = Self;
+
+    fn decl() -> String {
+        format!("type KeyboardKey = {};", Self::inline())
+    }
+
+    fn decl_concrete() -> String {
+        Self::decl()
+    }
+
+    fn name() -> String {
+        "KeyboardKey".to_owned()
+    }
+
+    fn inline() -> String {
+        KeyboardKey::ALL
+            .iter()
+            .flat_map(|key| match key {
+                // the derived TS type used to name it differently from serde, keep both
+                KeyboardKey::NonUSHash => vec![key.name(), "non_us_hash"],
+                _ => vec![key.name()],
+            })
+            .map(|name| format!("\"{name}\""))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    fn inline_flattened() -> String {
+        panic!("KeyboardKey cannot be flattened")
+    }
+
+    fn output_path() -> Option<&'static std::path::Path> {
+        Some(std::path::Path::new("KeyboardKey.ts"))
+    }
+}
+
+impl schemars::JsonSchema for KeyboardKey {
+    fn schema_name() -> String {
+        "KeyboardKey".to_owned()
+    }
+
+    fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        use schemars::schema::{InstanceType, Metadata, SchemaObject, StringValidation};
+
+        let names = KeyboardKey::ALL
+            .iter()
+            .flat_map(|key| std::iter::once(key.name()).chain(key.aliases().iter().copied()))
+            .map(serde_json::Value::from)
+            .collect();
+        let named = SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            enum_values: Some(names),
+            ..Default::default()
+        };
+        let raw_usage = SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some("^0[xX][0-9a-fA-F]{1,2}$".to_owned()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some(
+                    "Key name, alias (e.g. \"1\", \"ctrl\", \"esc\") or raw HID usage code (e.g. \"0x59\")"
+                        .to_owned(),
+                ),
+                ..Default::default()
+            })),
+            subschemas: Some(Box::new(schemars::schema::SubschemaValidation {
+                any_of: Some(vec![named.into(), raw_usage.into()]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usages_roundtrip() {
+        for key in KeyboardKey::ALL {
+            assert_eq!(KeyboardKey::from_usage(*key as u8), Some(*key));
+            assert_eq!(key.name().parse::<KeyboardKey>().unwrap(), *key);
+            for alias in key.aliases() {
+                assert_eq!(alias.parse::<KeyboardKey>().unwrap(), *key);
+            }
+        }
+    }
+
+    #[test]
+    fn test_names_are_unique() {
+        let mut names = KeyboardKey::ALL
+            .iter()
+            .flat_map(|k| std::iter::once(k.name()).chain(k.aliases().iter().copied()))
+            .collect::<Vec<_>>();
+        let total = names.len();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), total);
+    }
+
+    #[test]
+    fn test_parsing() {
+        assert_eq!("0x59".parse::<KeyboardKey>().unwrap(), KeyboardKey::Keypad1);
+        assert_eq!("enter".parse::<KeyboardKey>().unwrap(), KeyboardKey::Enter);
+        assert_eq!(
+            "ctrl".parse::<KeyboardKey>().unwrap(),
+            KeyboardKey::LeftCtrl
+        );
+        assert_eq!(
+            "1".parse::<KeyboardKey>().unwrap(),
+            KeyboardKey::OneExclamation
+        );
+        assert!(matches!(
+            "0xA5".parse::<KeyboardKey>(),
+            Err(KeyParseError::ReservedUsage(_))
+        ));
+        assert!(matches!(
+            "0xDE".parse::<KeyboardKey>(),
+            Err(KeyParseError::ReservedUsage(_))
+        ));
+        for invalid in ["0x1FF", "0x+4", "0x-4", "0x", "0x004", "0x03", "0xE8"] {
+            assert!(matches!(
+                invalid.parse::<KeyboardKey>(),
+                Err(KeyParseError::InvalidUsage(_))
+            ));
+        }
+        assert_eq!(
+            "non_us_hash".parse::<KeyboardKey>().unwrap(),
+            KeyboardKey::NonUSHash
+        );
+        assert_eq!(KeyboardKey::NonUSHash.name(), "non_u_s_hash");
+        assert!("nope".parse::<KeyboardKey>().is_err());
+
+        let keys: Vec<KeyboardKey> = serde_json::from_str(r#"["a", "0xE0", "f24"]"#).unwrap();
+        assert_eq!(
+            keys,
+            vec![KeyboardKey::A, KeyboardKey::LeftCtrl, KeyboardKey::F24]
+        );
+        assert_eq!(
+            serde_json::to_string(&keys).unwrap(),
+            r#"["a","left_ctrl","f24"]"#
+        );
+    }
+}
@@ -0,0 +1,571 @@
+use crate::hid::{KeyboardKey, KeyboardModifier, KeyboardReport};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use KeyboardKey as K;
+
+/// Keyboard layouts that `type_text` knows how to produce text for. Late Mate only sends
+/// key positions (HID usages), so the layout must match the one selected in the OS of
+/// the machine under test.
+#[derive(
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Clone,
+    Copy,
+    serde::Deserialize,
+    serde::Serialize,
+    ts_rs::TS,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyboardLayout {
+    #[default]
+    UsQwerty,
+    Uk,
+    GermanQwertz,
+    FrenchAzerty,
+    Dvorak,
+}
+
+impl KeyboardLayout {
+    pub const ALL: [KeyboardLayout; 5] = [
+        KeyboardLayout::UsQwerty,
+        KeyboardLayout::Uk,
+        KeyboardLayout::GermanQwertz,
+        KeyboardLayout::FrenchAzerty,
+        KeyboardLayout::Dvorak,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyboardLayout::UsQwerty => "us_qwerty",
+            KeyboardLayout::Uk => "uk",
+            KeyboardLayout::GermanQwertz => "german_qwertz",
+            KeyboardLayout::FrenchAzerty => "french_azerty",
+            KeyboardLayout::Dvorak => "dvorak",
+        }
+    }
+
+    fn table(&self) -> &'static LayoutTable {
+        match self {
+            KeyboardLayout::UsQwerty => &US_QWERTY,
+            KeyboardLayout::Uk => &UK,
+            KeyboardLayout::GermanQwertz => &GERMAN_QWERTZ,
+            KeyboardLayout::FrenchAzerty => &FRENCH_AZERTY,
+            KeyboardLayout::Dvorak => &DVORAK,
+        }
+    }
+}
+
+impl Display for KeyboardLayout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Unknown keyboard layout \"{0}\"")]
+pub struct UnknownLayout(String);
+
+impl FromStr for KeyboardLayout {
+    type Err = UnknownLayout;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        KeyboardLayout::ALL
+            .into_iter()
+            .find(|l| l.name() == s)
+            .ok_or_else(|| UnknownLayout(s.to_owned()))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Character {character:?} can't be typed using the {layout} keyboard layout")]
+pub struct UnsupportedCharacter {
+    pub character: char,
+    pub layout: KeyboardLayout,
+}
+
+// Used in tables below for key positions that don't produce a character
+const NONE: char = '\0';
+
+struct LayoutTable {
+    /// Keys with the characters they produce without and with Shift
+    keys: &'static [&'static [(KeyboardKey, char, char)]],
+    /// Characters that need AltGr (right Alt)
+    alt_gr: &'static [(KeyboardKey, char)],
+    /// Dead keys only produce their character when followed by a space
+    dead: &'static [char],
+}
+
+const WHITESPACE: &[(KeyboardKey, char, char)] = &[
+    (K::Spacebar, ' ', NONE),
+    (K::Enter, '\n', NONE),
+    (K::Tab, '\t', NONE),
+];
+
+const QWERTY_LETTERS: &[(KeyboardKey, char, char)] = &[
+    (K::A, 'a', 'A'),
+    (K::B, 'b', 'B'),
+    (K::C, 'c', 'C'),
+    (K::D, 'd', 'D'),
+    (K::E, 'e', 'E'),
+    (K::F, 'f', 'F'),
+    (K::G, 'g', 'G'),
+    (K::H, 'h', 'H'),
+    (K::I, 'i', 'I'),
+    (K::J, 'j', 'J'),
+    (K::K, 'k', 'K'),
+    (K::L, 'l', 'L'),
+    (K::M, 'm', 'M'),
+    (K::N, 'n', 'N'),
+    (K::O, 'o', 'O'),
+    (K::P, 'p', 'P'),
+    (K::Q, 'q', 'Q'),
+    (K::R, 'r', 'R'),
+    (K::S, 's', 'S'),
+    (K::T, 't', 'T'),
+    (K::U, 'u', 'U'),
+    (K::V, 'v', 'V'),
+    (K::W, 'w', 'W'),
+    (K::X, 'x', 'X'),
+    (K::Y, 'y', 'Y'),
+    (K::Z, 'z', 'Z'),
+];
+
+const US_QWERTY: LayoutTable = LayoutTable {
+    keys: &[
+        WHITESPACE,
+        QWERTY_LETTERS,
+        &[
+            (K::OneExclamation, '1', '!'),
+            (K::TwoAt, '2', '@'),
+            (K::ThreeHash, '3', '#'),
+            (K::FourDollar, '4', '$'),
+            (K::FivePercent, '5', '%'),
+            (K::SixCaret, '6', '^'),
+            (K::SevenAmpersand, '7', '&'),
+            (K::EightAsterisk, '8', '*'),
+            (K::NineOpenParens, '9', '('),
+            (K::ZeroCloseParens, '0', ')'),
+            (K::DashUnderscore, '-', '_'),
+            (K::EqualPlus, '=', '+'),
+            (K::OpenBracketBrace, '[', '{'),
+            (K::CloseBracketBrace, ']', '}'),
+            (K::BackslashBar, '\\', '|'),
+            (K::SemiColon, ';', ':'),
+            (K::SingleDoubleQuote, '\'', '"'),
+            (K::BacktickTilde, '`', '~'),
+            (K::CommaLess, ',', '<'),
+            (K::PeriodGreater, '.', '>'),
+            (K::SlashQuestion, '/', '?'),
+        ],
+    ],
+    alt_gr: &[],
+    dead: &[],
+};
+
+const UK: LayoutTable = LayoutTable {
+    keys: &[
+        WHITESPACE,
+        QWERTY_LETTERS,
+        &[
+            (K::OneExclamation, '1', '!'),
+            (K::TwoAt, '2', '"'),
+            (K::ThreeHash, '3', '£'),
+            (K::FourDollar, '4', '$'),
+            (K::FivePercent, '5', '%'),
+            (K::SixCaret, '6', '^'),
+            (K::SevenAmpersand, '7', '&'),
+            (K::EightAsterisk, '8', '*'),
+            (K::NineOpenParens, '9', '('),
+            (K::ZeroCloseParens, '0', ')'),
+            (K::DashUnderscore, '-', '_'),
+            (K::EqualPlus, '=', '+'),
+            (K::OpenBracketBrace, '[', '{'),
+            (K::CloseBracketBrace, ']', '}'),
+            (K::NonUSHash, '#', '~'),
+            (K::SemiColon, ';', ':'),
+            (K::SingleDoubleQuote, '\'', '@'),
+            (K::BacktickTilde, '`', '¬'),
+            (K::CommaLess, ',', '<'),
+            (K::PeriodGreater, '.', '>'),
+            (K::SlashQuestion, '/', '?'),
+            (K::NonUSBackslash, '\\', '|'),
+        ],
+    ],
+    alt_gr: &[(K::FourDollar, '€')],
+    dead: &[],
+};
+
+const GERMAN_QWERTZ: LayoutTable = LayoutTable {
+    keys: &[
+        WHITESPACE,
+        &[
+            (K::A, 'a', 'A'),
+            (K::B, 'b', 'B'),
+            (K::C, 'c', 'C'),
+            (K::D, 'd', 'D'),
+            (K::E, 'e', 'E'),
+            (K::F, 'f', 'F'),
+            (K::G, 'g', 'G'),
+            (K::H, 'h', 'H'),
+            (K::I, 'i', 'I'),
+            (K::J, 'j', 'J'),
+            (K::K, 'k', 'K'),
+            (K::L, 'l', 'L'),
+            (K::M, 'm', 'M'),
+            (K::N, 'n', 'N'),
+            (K::O, 'o', 'O'),
+            (K::P, 'p', 'P'),
+            (K::Q, 'q', 'Q'),
+            (K::R, 'r', 'R'),
+            (K::S, 's', 'S'),
+            (K::T, 't', 'T'),
+            (K::U, 'u', 'U'),
+            (K::V, 'v', 'V'),
+            (K::W, 'w', 'W'),
+            (K::X, 'x', 'X'),
+            (K::Z, 'y', 'Y'),
+            (K::Y, 'z', 'Z'),
+            (K::OneExclamation, '1', '!'),
+            (K::TwoAt, '2', '"'),
+            (K::ThreeHash, '3', '§'),
+            (K::FourDollar, '4', '$'),
+            (K::FivePercent, '5', '%'),
+            (K::SixCaret, '6', '&'),
+            (K::SevenAmpersand, '7', '/'),
+            (K::EightAsterisk, '8', '('),
+            (K::NineOpenParens, '9', ')'),
+            (K::ZeroCloseParens, '0', '='),
+            (K::DashUnderscore, 'ß', '?'),
+            (K::EqualPlus, '´', '`'),
+            (K::OpenBracketBrace, 'ü', 'Ü'),
+            (K::CloseBracketBrace, '+', '*'),
+            (K::NonUSHash, '#', '\''),
+            (K::SemiColon, 'ö', 'Ö'),
+            (K::SingleDoubleQuote, 'ä', 'Ä'),
+            (K::BacktickTilde, '^', '°'),
+            (K::CommaLess, ',', ';'),
+            (K::PeriodGreater, '.', ':'),
+            (K::SlashQuestion, '-', '_'),
+            (K::NonUSBackslash, '<', '>'),
+        ],
+    ],
+    alt_gr: &[
+        (K::TwoAt, '²'),
+        (K::ThreeHash, '³'),
+        (K::SevenAmpersand, '{'),
+        (K::EightAsterisk, '['),
+        (K::NineOpenParens, ']'),
+        (K::ZeroCloseParens, '}'),
+        (K::DashUnderscore, '\\'),
+        (K::CloseBracketBrace, '~'),
+        (K::NonUSBackslash, '|'),
+        (K::Q, '@'),
+        (K::E, '€'),
+        (K::M, 'µ'),
+    ],
+    dead: &['^', '´', '`'],
+};
+
+const FRENCH_AZERTY: LayoutTable = LayoutTable {
+    keys: &[
+        WHITESPACE,
+        &[
+            (K::Q, 'a', 'A'),
+            (K::B, 'b', 'B'),
+            (K::C, 'c', 'C'),
+            (K::D, 'd', 'D'),
+            (K::E, 'e', 'E'),
+            (K::F, 'f', 'F'),
+            (K::G, 'g', 'G'),
+            (K::H, 'h', 'H'),
+            (K::I, 'i', 'I'),
+            (K::J, 'j', 'J'),
+            (K::K, 'k', 'K'),
+            (K::L, 'l', 'L'),
+            (K::SemiColon, 'm', 'M'),
+            (K::N, 'n', 'N'),
+            (K::O, 'o', 'O'),
+            (K::P, 'p', 'P'),
+            (K::A, 'q', 'Q'),
+            (K::R, 'r', 'R'),
+            (K::S, 's', 'S'),
+            (K::T, 't', 'T'),
+            (K::U, 'u', 'U'),
+            (K::V, 'v', 'V'),
+            (K::Z, 'w', 'W'),
+            (K::X, 'x', 'X'),
+            (K::Y, 'y', 'Y'),
+            (K::W, 'z', 'Z'),
+            (K::OneExclamation, '&', '1'),
+            (K::TwoAt, 'é', '2'),
+            (K::ThreeHash, '"', '3'),
+            (K::FourDollar, '\'', '4'),
+            (K::FivePercent, '(', '5'),
+            (K::SixCaret, '-', '6'),
+            (K::SevenAmpersand, 'è', '7'),
+            (K::EightAsterisk, '_', '8'),
+            (K::NineOpenParens, 'ç', '9'),
+            (K::ZeroCloseParens, 'à', '0'),
+            (K::DashUnderscore, ')', '°'),
+            (K::EqualPlus, '=', '+'),
+            (K::CloseBracketBrace, '$', '£'),
+            (K::NonUSHash, '*', 'µ'),
+            (K::SingleDoubleQuote, 'ù', '%'),
+            (K::BacktickTilde, '²', NONE),
+            (K::M, ',', '?'),
+            (K::CommaLess, ';', '.'),
+            (K::PeriodGreater, ':', '/'),
+            (K::SlashQuestion, '!', '§'),
+            (K::NonUSBackslash, '<', '>'),
+        ],
+    ],
+    alt_gr: &[
+        (K::TwoAt, '~'),
+        (K::ThreeHash, '#'),
+        (K::FourDollar, '{'),
+        (K::FivePercent, '['),
+        (K::SixCaret, '|'),
+        (K::SevenAmpersand, '`'),
+        (K::EightAsterisk, '\\'),
+        (K::NineOpenParens, '^'),
+        (K::ZeroCloseParens, '@'),
+        (K::DashUnderscore, ']'),
+        (K::EqualPlus, '}'),
+        (K::CloseBracketBrace, '¤'),
+        (K::E, '€'),
+    ],
+    dead: &['~', '`'],
+};
+
+const DVORAK: LayoutTable = LayoutTable {
+    keys: &[
+        WHITESPACE,
+        &[
+            (K::A, 'a', 'A'),
+            (K::N, 'b', 'B'),
+            (K::I, 'c', 'C'),
+            (K::H, 'd', 'D'),
+            (K::D, 'e', 'E'),
+            (K::Y, 'f', 'F'),
+            (K::U, 'g', 'G'),
+            (K::J, 'h', 'H'),
+            (K::G, 'i', 'I'),
+            (K::C, 'j', 'J'),
+            (K::V, 'k', 'K'),
+            (K::P, 'l', 'L'),
+            (K::M, 'm', 'M'),
+            (K::L, 'n', 'N'),
+            (K::S, 'o', 'O'),
+            (K::R, 'p', 'P'),
+            (K::X, 'q', 'Q'),
+            (K::O, 'r', 'R'),
+            (K::SemiColon, 's', 'S'),
+            (K::K, 't', 'T'),
+            (K::F, 'u', 'U'),
+            (K::PeriodGreater, 'v', 'V'),
+            (K::CommaLess, 'w', 'W'),
+            (K::B, 'x', 'X'),
+            (K::T, 'y', 'Y'),
+            (K::SlashQuestion, 'z', 'Z'),
+            (K::OneExclamation, '1', '!'),
+            (K::TwoAt, '2', '@'),
+            (K::ThreeHash, '3', '#'),
+            (K::FourDollar, '4', '$'),
+            (K::FivePercent, '5', '%'),
+            (K::SixCaret, '6', '^'),
+            (K::SevenAmpersand, '7', '&'),
+            (K::EightAsterisk, '8', '*'),
+            (K::NineOpenParens, '9', '('),
+            (K::ZeroCloseParens, '0', ')'),
+            (K::DashUnderscore, '[', '{'),
+            (K::EqualPlus, ']', '}'),
+            (K::Q, '\'', '"'),
+            (K::W, ',', '<'),
+            (K::E, '.', '>'),
+            (K::OpenBracketBrace, '/', '?'),
+            (K::CloseBracketBrace, '=', '+'),
+            (K::BackslashBar, '\\', '|'),
+            (K::SingleDoubleQuote, '-', '_'),
+            (K::Z, ';', ':'),
+            (K::BacktickTilde, '`', '~'),
+        ],
+    ],
+    alt_gr: &[],
+    dead: &[],
+};
+
+/// A single key press with the modifiers needed to produce a character
+#[derive(Debug, Eq, PartialEq, Clone)]
+struct Keystroke {
+    modifiers: Vec<KeyboardModifier>,
+    key: KeyboardKey,
+}
+
+impl LayoutTable {
+    fn find(&self, c: char) -> Option<Keystroke> {
+        let keys = self.keys.iter().flat_map(|k| k.iter());
+        for &(key, plain, shifted) in keys {
+            if c == plain {
+                return Some(Keystroke {
+                    modifiers: vec![],
+                    key,
+                });
+            } else if c == shifted {
+                return Some(Keystroke {
+                    modifiers: vec![KeyboardModifier::LShift],
+                    key,
+                });
+            }
+        }
+
+        self.alt_gr
+            .iter()
+            .find(|(_, alt_gr)| *alt_gr == c)
+            .map(|&(key, _)| Keystroke {
+                modifiers: vec![KeyboardModifier::RAlt],
+                key,
+            })
+    }
+
+    fn keystrokes(&self, c: char) -> Option<Vec<Keystroke>> {
+        if c == NONE {
+            return None;
+        }
+
+        let keystroke = self.find(c)?;
+        if self.dead.contains(&c) {
+            let space = Keystroke {
+                modifiers: vec![],
+                key: KeyboardKey::Spacebar,
+            };
+            Some(vec![keystroke, space])
+        } else {
+            Some(vec![keystroke])
+        }
+    }
+}
+
+/// Expands text into pairs of keyboard reports that press and then release every key
+/// needed to type it, with Shift/AltGr modifiers as the layout requires.
+pub fn type_text(
+    text: &str,
+    layout: KeyboardLayout,
+) -> Result<Vec<KeyboardReport>, UnsupportedCharacter> {
+    let table = layout.table();
+    let mut reports = Vec::new();
+
+    for character in text.chars() {
+        let keystrokes = table
+            .keystrokes(character)
+            .ok_or(UnsupportedCharacter { character, layout })?;
+
+        for Keystroke { modifiers, key } in keystrokes {
+            reports.push(KeyboardReport {
+                modifiers,
+                pressed_keys: vec![key],
+            });
+            reports.push(KeyboardReport::default());
+        }
+    }
+
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(modifiers: &[KeyboardModifier], key: KeyboardKey) -> KeyboardReport {
+        KeyboardReport {
+            modifiers: modifiers.to_vec(),
+            pressed_keys: vec![key],
+        }
+    }
+
+    fn presses(text: &str, layout: KeyboardLayout) -> Vec<KeyboardReport> {
+        let reports = type_text(text, layout).unwrap();
+        for release in reports.iter().skip(1).step_by(2) {
+            assert_eq!(release, &KeyboardReport::default());
+        }
+        reports.into_iter().step_by(2).collect()
+    }
+
+    #[test]
+    fn test_us_qwerty() {
+        use KeyboardModifier::LShift;
+        assert_eq!(
+            presses("hI, !", KeyboardLayout::UsQwerty),
+            vec![
+                press(&[], K::H),
+                press(&[LShift], K::I),
+                press(&[], K::CommaLess),
+                press(&[], K::Spacebar),
+                press(&[LShift], K::OneExclamation),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layout_differences() {
+        use KeyboardModifier::{LShift, RAlt};
+        assert_eq!(
+            presses("@", KeyboardLayout::Uk),
+            vec![press(&[LShift], K::SingleDoubleQuote)]
+        );
+        assert_eq!(
+            presses("zä@", KeyboardLayout::GermanQwertz),
+            vec![
+                press(&[], K::Y),
+                press(&[], K::SingleDoubleQuote),
+                press(&[RAlt], K::Q),
+            ]
+        );
+        assert_eq!(
+            presses("am1", KeyboardLayout::FrenchAzerty),
+            vec![
+                press(&[], K::Q),
+                press(&[], K::SemiColon),
+                press(&[LShift], K::OneExclamation),
+            ]
+        );
+        assert_eq!(
+            presses("hello", KeyboardLayout::Dvorak),
+            vec![
+                press(&[], K::J),
+                press(&[], K::D),
+                press(&[], K::P),
+                press(&[], K::P),
+                press(&[], K::S),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dead_keys() {
+        assert_eq!(
+            presses("^", KeyboardLayout::GermanQwertz),
+            vec![press(&[], K::BacktickTilde), press(&[], K::Spacebar)]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_character() {
+        let error = type_text("a€", KeyboardLayout::UsQwerty).unwrap_err();
+        assert_eq!(error.character, '€');
+        assert!(type_text("\0", KeyboardLayout::UsQwerty).is_err());
+    }
+
+    #[test]
+    fn test_layout_names() {
+        for layout in KeyboardLayout::ALL {
+            assert_eq!(layout.name().parse::<KeyboardLayout>().unwrap(), layout);
+            assert_eq!(
+                serde_json::to_value(layout).unwrap(),
+                serde_json::Value::String(layout.name().to_owned())
+            );
+        }
+    }
+}
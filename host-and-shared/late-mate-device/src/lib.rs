@@ -1,7 +1,8 @@
 use crate::agents::dispatcher::DispatcherHandle;
+use crate::agents::usb_rx::UsbRxHandle;
 use crate::agents::usb_tx::UsbTxHandle;
-use crate::agents::{agent_watcher, dispatcher, usb_rx, usb_tx};
-use crate::scenario::{to_device_scenario, Moment, Recording, Scenario};
+use crate::agents::{agent_watcher, dispatcher, tcp, usb_rx, usb_tx};
+use crate::scenario::{to_device_scenario, HidState, Moment, Recording, Scenario};
 use crate::usb::UsbDevice;
 use futures::TryStream;
 use late_mate_shared::comms;
@@ -10,13 +11,15 @@ use late_mate_shared::comms::host_to_device;
 use rand::rngs::SmallRng;
 use rand::{Rng, SeedableRng};
 use std::time::Duration;
+use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use tokio::task::JoinSet;
-use tokio::time::{sleep, timeout};
+use tokio::time::{interval, sleep, timeout, MissedTickBehavior};
 use tokio_stream::wrappers::ReceiverStream;
 
 mod agents;
 pub mod hid;
+pub mod proxy;
 pub mod scenario;
 mod usb;
 
@@ -30,6 +33,8 @@ pub enum Error {
     UsbError(&'static str, #[source] nusb::Error),
     #[error("USB transfer error while {0}")]
     UsbTransferError(&'static str, #[source] nusb::transfer::TransferError),
+    #[error("Network error while {0}")]
+    NetworkError(&'static str, #[source] std::io::Error),
     #[error("Timeout while sending the request")]
     RequestTimeout,
     #[error("Timeout while waiting for the response")]
@@ -78,59 +83,10 @@ pub struct Device {
     pub last_panic_message: Option<String>,
 }
 
-// struct BackgroundLevelMonitor {
-//     is_active_sender: watch::Sender<bool>,
-//     sender: broadcast::Sender<u32>,
-// }
-
-// pub async fn bg_request_loop(
-//     tx_sender: mpsc::Sender<HostToDevice>,
-//     mut is_active_receiver: watch::Receiver<bool>,
-// ) -> anyhow::Result<()> {
-//     loop {
-//         is_active_receiver
-//             .wait_for(|x| *x)
-//             .await
-//             .context("is_active_sender is dropped => device is dropped => exit")?;
-//         tx_sender
-//             .send(HostToDevice::StreamLightLevel { duration_ms: 1300 })
-//             .await
-//             .context("device tx channel is closed => device is disconnected => exit")?;
-//         sleep(Duration::from_millis(1000)).await;
-//     }
-// }
-//
-// pub async fn bg_channel_loop(
-//     mut rx_reciever: broadcast::Receiver<DeviceToHost>,
-//     mut is_active_receiver: watch::Receiver<bool>,
-//     bg_sender: broadcast::Sender<u32>,
-// ) -> anyhow::Result<()> {
-//     loop {
-//         is_active_receiver
-//             .wait_for(|x| *x)
-//             .await
-//             .context("is_active_sender is dropped => device is dropped => exit")?;
-//
-//         match rx_reciever.recv().await {
-//             Ok(DeviceToHost::CurrentLightLevel(level)) => match bg_sender.send(level) {
-//                 Ok(_) => (),
-//                 Err(_) => {
-//                     // all receivers were dropped, but they might rejoin later
-//                     // todo: maybe I should stop here?
-//                     continue;
-//                 }
-//             },
-//             Ok(_) => (),
-//             // todo: lagged
-//             Err(RecvError::Lagged(_)) => (),
-//             Err(RecvError::Closed) => {
-//                 return Err(anyhow!(
-//                     "device rx channel is closed => device is disconnected => exit"
-//                 ))
-//             }
-//         }
-//     }
-// }
+/// The firmware streams the light level for this long after every StreamLightLevel request
+const LIGHT_STREAM_DURATION: Duration = Duration::from_millis(1300);
+/// StreamLightLevel is a keepalive: it's re-sent before the previous one runs out
+const LIGHT_STREAM_KEEPALIVE: Duration = Duration::from_millis(1000);
 
 impl Device {
     pub async fn init() -> Result<Self, Error> {
@@ -143,7 +99,33 @@ impl Device {
         let mut agent_set: JoinSet<()> = JoinSet::new();
         let usb_rx = usb_rx::start(&mut agent_set, in_queue);
         let usb_tx = usb_tx::start(&mut agent_set, out_queue);
-        let dispatcher = dispatcher::start(&mut agent_set, usb_rx);
+
+        Self::start(agent_set, usb_rx, usb_tx).await
+    }
+
+    /// Uses a device shared by `late-mate proxy` on another machine
+    pub async fn connect(address: &str) -> Result<Self, Error> {
+        tracing::debug!("Connecting to the proxy at {address}");
+        let stream = TcpStream::connect(address)
+            .await
+            .map_err(|e| Error::NetworkError("connecting to the proxy", e))?;
+        stream
+            .set_nodelay(true)
+            .map_err(|e| Error::NetworkError("configuring the connection", e))?;
+
+        tracing::debug!("Starting the agents");
+        let mut agent_set: JoinSet<()> = JoinSet::new();
+        let (rx, tx) = tcp::start(&mut agent_set, stream);
+
+        Self::start(agent_set, rx, tx).await
+    }
+
+    async fn start(
+        mut agent_set: JoinSet<()>,
+        rx: UsbRxHandle,
+        usb_tx: UsbTxHandle,
+    ) -> Result<Self, Error> {
+        let dispatcher = dispatcher::start(&mut agent_set, rx);
         agent_watcher::start(agent_set);
 
         let mut self_ = Self {
@@ -284,14 +266,19 @@ impl Device {
         Ok(timeline)
     }
 
+    /// Streams recordings of all repeats, the first `scenario.warmup` of them are warmups
     pub async fn run_scenario(
         &self,
         scenario: Scenario,
     ) -> Result<impl TryStream<Ok = Recording, Error = Error>, scenario::ValidationError> {
         scenario.validate()?;
 
-        let (test_device_scenario, test_hid_index) = to_device_scenario(scenario.test.as_slice());
-        let revert = scenario.revert.as_deref().map(to_device_scenario);
+        let mut hid_state = HidState::default();
+        let (test_device_scenario, test_hid_index) =
+            to_device_scenario(scenario.test.as_slice(), &mut hid_state);
+        let revert = scenario
+            .revert_steps()?
+            .map(|revert| to_device_scenario(&revert, &mut hid_state));
         let delay_range = scenario.delay_between_ms.0..=scenario.delay_between_ms.1;
 
         let (sender, receiver) = mpsc::channel::<Result<Recording, Error>>(1);
@@ -301,17 +288,8 @@ impl Device {
         tokio::spawn(async move {
             let mut unsafe_rng = SmallRng::from_entropy();
 
-            for _repeat in 0..scenario.repeats {
-                let request = host_to_device::Message::RunScenario(test_device_scenario.clone());
-                let recording_result = match device.make_request(request).await {
-                    Ok(resp_receiver) => Device::assemble_timeline(resp_receiver, &test_hid_index)
-                        .await
-                        .map(|timeline| Recording {
-                            max_light_level: device.max_light_level,
-                            timeline,
-                        }),
-                    Err(e) => Err(e),
-                };
+            for _repeat in 0..u32::from(scenario.warmup) + u32::from(scenario.repeats) {
+                let recording_result = device.record(&test_device_scenario, &test_hid_index).await;
 
                 // this is needed to only break AFTER sending the error
                 let recording_err = recording_result.is_err();
@@ -322,15 +300,10 @@ impl Device {
                 }
 
                 if let Some((ref device_scenario, _)) = revert {
-                    let request = host_to_device::Message::RunScenario(device_scenario.clone());
-                    match device.one_off(request).await {
-                        Ok(None) => (),
-                        Ok(Some(_)) => unreachable!("Revert scenario should not return anything"),
-                        Err(e) => {
-                            // I break anyway, doesn't matter if the receiver went away
-                            _ = sender.send(Err(e)).await;
-                            break;
-                        }
+                    if let Err(e) = device.revert(device_scenario).await {
+                        // I break anyway, doesn't matter if the receiver went away
+                        _ = sender.send(Err(e)).await;
+                        break;
                     }
                 }
 
@@ -342,25 +315,127 @@ impl Device {
         Ok(ReceiverStream::new(receiver))
     }
 
-    //
-    // // todo: detect drop or stop subscribing in some way?
-    // pub fn subscribe_to_background(&mut self) -> broadcast::Receiver<u32> {
-    //     self.bg_light.sender.subscribe()
-    // }
-    //
-    // // todo: this doesn't actually help to handle multiple pages open at once,
-    // //       rework this completely
-    // pub fn background_enable(&mut self) {
-    //     self.bg_light
-    //         .is_active_sender
-    //         .send(true)
-    //         .expect("is_active channel must be opened here");
-    // }
-    //
-    // pub fn background_disable(&mut self) {
-    //     self.bg_light
-    //         .is_active_sender
-    //         .send(false)
-    //         .expect("is_active channel must be opened here");
-    // }
+    /// Runs the scenario until the stream is dropped, ignoring its "repeats", the first
+    /// `scenario.warmup` recordings are still warmups. Unlike `run_scenario`, a repeat
+    /// (including its revert) only starts when the next recording is polled for, so the caller
+    /// can stop after any repeat without the device running another one in the background
+    pub async fn run_scenario_open_ended(
+        &self,
+        scenario: Scenario,
+    ) -> Result<impl TryStream<Ok = Recording, Error = Error>, scenario::ValidationError> {
+        scenario.validate()?;
+
+        let mut hid_state = HidState::default();
+        let (test_device_scenario, test_hid_index) =
+            to_device_scenario(scenario.test.as_slice(), &mut hid_state);
+        let revert = scenario
+            .revert_steps()?
+            .map(|revert| to_device_scenario(&revert, &mut hid_state).0);
+        let delay_range = scenario.delay_between_ms.0..=scenario.delay_between_ms.1;
+
+        let device = self.clone();
+        let unsafe_rng = SmallRng::from_entropy();
+        let stream =
+            futures::stream::try_unfold((unsafe_rng, true), move |(mut unsafe_rng, first)| {
+                let device = device.clone();
+                let test_device_scenario = test_device_scenario.clone();
+                let test_hid_index = test_hid_index.clone();
+                let revert = revert.clone();
+                let delay_range = delay_range.clone();
+                async move {
+                    if !first {
+                        let sleep_ms = unsafe_rng.gen_range(delay_range);
+                        sleep(Duration::from_millis(sleep_ms as u64)).await;
+                    }
+                    let recording = device
+                        .record(&test_device_scenario, &test_hid_index)
+                        .await?;
+                    if let Some(ref device_scenario) = revert {
+                        device.revert(device_scenario).await?;
+                    }
+                    Ok(Some((recording, (unsafe_rng, false))))
+                }
+            });
+
+        // boxed to be Unpin like the stream of run_scenario
+        Ok(Box::pin(stream))
+    }
+
+    /// Runs the test part of a scenario once
+    async fn record(
+        &self,
+        device_scenario: &host_to_device::Scenario,
+        hid_index: &[hid::HidReport],
+    ) -> Result<Recording, Error> {
+        let request = host_to_device::Message::RunScenario(device_scenario.clone());
+        let response_receiver = self.make_request(request).await?;
+        let timeline = Device::assemble_timeline(response_receiver, hid_index).await?;
+        Ok(Recording {
+            max_light_level: self.max_light_level,
+            timeline,
+        })
+    }
+
+    async fn revert(&self, device_scenario: &host_to_device::Scenario) -> Result<(), Error> {
+        let request = host_to_device::Message::RunScenario(device_scenario.clone());
+        match self.one_off(request).await? {
+            None => Ok(()),
+            Some(_) => unreachable!("Revert scenario should not return anything"),
+        }
+    }
+
+    /// Streams the current light level until the stream is dropped. The firmware pauses
+    /// streaming while scenarios are running
+    pub async fn stream_light_level(
+        &self,
+    ) -> Result<impl TryStream<Ok = u32, Error = Error>, Error> {
+        let request = host_to_device::Message::StreamLightLevel {
+            duration_ms: LIGHT_STREAM_DURATION.as_millis() as u16,
+        };
+        let mut response_receiver = self.make_request(request.clone()).await?;
+
+        let (sender, receiver) = mpsc::channel::<Result<u32, Error>>(64);
+        let device = self.clone();
+
+        tokio::spawn(async move {
+            let mut keepalive = interval(LIGHT_STREAM_KEEPALIVE);
+            keepalive.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // the first tick completes immediately, the first request is already sent
+            keepalive.tick().await;
+
+            loop {
+                let result = tokio::select! {
+                    _ = keepalive.tick() => {
+                        // the firmware switches to the new request ID right away, so
+                        // the previous receiver won't get anything else
+                        match device.make_request(request.clone()).await {
+                            Ok(receiver) => {
+                                response_receiver = receiver;
+                                continue;
+                            }
+                            Err(e) => Err(e),
+                        }
+                    }
+                    response = response_receiver.recv() => {
+                        match response.expect("Pending response channel should not be dropped") {
+                            Ok(Some(device_to_host::Message::CurrentLightLevel(level))) => Ok(level),
+                            // the acknowledgement of the request itself
+                            Ok(None) => continue,
+                            Ok(Some(_)) => unreachable!("Light level stream must only contain light levels"),
+                            Err(e) => Err(e),
+                        }
+                    }
+                    // the receiver went away
+                    _ = sender.closed() => break,
+                };
+
+                let is_err = result.is_err();
+                if sender.send(result).await.is_err() || is_err {
+                    break;
+                }
+            }
+        });
+
+        Ok(ReceiverStream::new(receiver))
+    }
 }
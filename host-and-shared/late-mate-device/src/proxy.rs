@@ -0,0 +1,214 @@
+//! Sharing a local device with remote clients (see `Device::connect`) over TCP.
+//!
+//! Every client numbers its requests independently, so requests are registered with the local
+//! dispatcher under new IDs, and responses are sent back under the client's original ones.
+//! The firmware only streams light levels for the latest StreamLightLevel request, so
+//! clients monitoring the light level at the same time take turns
+
+use crate::agents::usb_rx::{self, ProcessingError};
+use crate::usb::{ALIGNED_BUFFER_SIZE, OPERATION_TIMEOUT};
+use crate::{Device, Error, ResponseResult};
+use late_mate_shared::comms;
+use late_mate_shared::comms::{device_to_host, host_to_device, CrcCobsAccumulator};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+/// Serves the device until the listener fails
+pub async fn serve(device: Device, listener: TcpListener) -> Result<(), Error> {
+    loop {
+        let (stream, address) = listener
+            .accept()
+            .await
+            .map_err(|e| Error::NetworkError("accepting a connection", e))?;
+
+        tracing::info!("{address} connected");
+        tokio::spawn(handle_client(device.clone(), stream, address));
+    }
+}
+
+async fn handle_client(device: Device, stream: TcpStream, address: SocketAddr) {
+    if let Err(e) = stream.set_nodelay(true) {
+        tracing::warn!("Couldn't disable Nagle's algorithm for {address}: {e}");
+    }
+    let (read_half, write_half) = stream.into_split();
+
+    let (request_sender, mut request_receiver) = mpsc::channel(4);
+    let (response_sender, response_receiver) = mpsc::channel(16);
+    let reader = tokio::spawn(client_rx_loop(read_half, request_sender));
+    let writer = tokio::spawn(client_tx_loop(write_half, response_receiver));
+
+    while let Some(host_to_device::Envelope {
+        request_id,
+        request,
+    }) = request_receiver.recv().await
+    {
+        let responses = match device.make_request(request.clone()).await {
+            Ok(responses) => responses,
+            Err(e) => {
+                tracing::error!("Local device error while serving {address}: {e}");
+                break;
+            }
+        };
+        tokio::spawn(forward_responses(
+            request,
+            request_id,
+            responses,
+            response_sender.clone(),
+        ));
+    }
+
+    reader.abort();
+    writer.abort();
+    tracing::info!("{address} disconnected");
+}
+
+async fn client_rx_loop(
+    mut read_half: OwnedReadHalf,
+    sender: mpsc::Sender<host_to_device::Envelope>,
+) {
+    let mut buf = vec![0; ALIGNED_BUFFER_SIZE];
+    let mut cobs_acc = CrcCobsAccumulator::new();
+
+    loop {
+        let len = match read_half.read(&mut buf).await {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) => {
+                tracing::error!("Proxy RX error: {e}");
+                break;
+            }
+        };
+
+        match usb_rx::process_packet(&sender, &mut cobs_acc, &buf[..len]).await {
+            Ok(_) => (),
+            Err(ProcessingError::ChannelClosed) => break,
+            Err(e) => tracing::error!("Proxy packet deserialisation error: {e}"),
+        }
+    }
+}
+
+async fn client_tx_loop(
+    mut write_half: OwnedWriteHalf,
+    mut receiver: mpsc::Receiver<device_to_host::Envelope>,
+) {
+    let mut buf = vec![0; ALIGNED_BUFFER_SIZE];
+
+    while let Some(envelope) = receiver.recv().await {
+        let used_len = comms::encode(&envelope, buf.as_mut_slice());
+        if let Err(e) = write_half.write_all(&buf[..used_len]).await {
+            tracing::error!("Proxy TX error: {e}");
+            break;
+        }
+    }
+}
+
+/// Whether more responses can follow this one
+fn is_final(request: &host_to_device::Message, response: &ResponseResult) -> bool {
+    use device_to_host::Message as DTH;
+    use host_to_device::Message as HTD;
+
+    match (request, response) {
+        (_, Err(_)) => true,
+        // light levels keep coming after the acknowledgement
+        (HTD::StreamLightLevel { .. }, _) => false,
+        // panic chunks come before the status
+        (HTD::GetStatus, Ok(response)) => matches!(response, Some(DTH::Status(_))),
+        // scenario results come before the acknowledgement
+        (_, Ok(response)) => response.is_none(),
+    }
+}
+
+async fn forward_responses(
+    request: host_to_device::Message,
+    client_request_id: host_to_device::RequestId,
+    mut responses: mpsc::Receiver<ResponseResult>,
+    sender: mpsc::Sender<device_to_host::Envelope>,
+) {
+    loop {
+        // light level streams don't have an end, they go quiet once replaced or expired
+        let Ok(Some(response)) = timeout(OPERATION_TIMEOUT, responses.recv()).await else {
+            break;
+        };
+
+        let is_final = is_final(&request, &response);
+        let envelope = device_to_host::Envelope {
+            request_id: client_request_id,
+            // the protocol can't transmit errors, the client sees this as OnDeviceError
+            response: response.map_err(|_| ()),
+        };
+        if sender.send(envelope).await.is_err() || is_final {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::usb_rx::UsbRxHandle;
+    use crate::agents::usb_tx::{TxRequest, UsbTxHandle};
+    use late_mate_shared::comms::device_to_host::{FirmwareVersion, Status, Version};
+    use tokio::task::JoinSet;
+
+    /// Answers GetStatus requests with the request ID as max_light_level
+    async fn fake_firmware(
+        mut requests: mpsc::Receiver<TxRequest>,
+        responses: mpsc::Sender<device_to_host::Envelope>,
+    ) {
+        while let Some((envelope, _)) = requests.recv().await {
+            assert_eq!(envelope.request, host_to_device::Message::GetStatus);
+            let status = Status {
+                version: Version {
+                    hardware: 1,
+                    firmware: FirmwareVersion {
+                        git_commit: [0; 4],
+                        is_dirty: false,
+                    },
+                },
+                max_light_level: envelope.request_id,
+                serial_number: [0; 8],
+            };
+            let response = device_to_host::Envelope {
+                request_id: envelope.request_id,
+                response: Ok(Some(device_to_host::Message::Status(status))),
+            };
+            responses.send(response).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_ids_across_clients() {
+        let (tx_sender, tx_receiver) = mpsc::channel(4);
+        let (rx_sender, rx_receiver) = mpsc::channel(16);
+        tokio::spawn(fake_firmware(tx_receiver, rx_sender));
+        // the agent watcher expects the USB agents to be there
+        let mut agent_set = JoinSet::new();
+        agent_set.spawn(std::future::pending());
+        let local = Device::start(
+            agent_set,
+            UsbRxHandle::new(rx_receiver),
+            UsbTxHandle::new(tx_sender),
+        )
+        .await
+        .unwrap();
+        // the initial status request
+        assert_eq!(local.max_light_level, 0);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(serve(local, listener));
+
+        // both clients start their request IDs from 0, the local dispatcher
+        // has to keep them apart
+        let mut first = Device::connect(&address).await.unwrap();
+        let mut second = Device::connect(&address).await.unwrap();
+        assert_eq!(first.max_light_level, 1);
+        assert_eq!(second.max_light_level, 2);
+        assert_eq!(first.get_status().await.unwrap().max_light_level, 3);
+        assert_eq!(second.get_status().await.unwrap().max_light_level, 4);
+    }
+}
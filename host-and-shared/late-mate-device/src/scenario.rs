@@ -1,8 +1,10 @@
 use crate::hid;
+use crate::hid::layout::{self, KeyboardLayout};
 use late_mate_shared::comms;
 use late_mate_shared::comms::device_to_host;
 use late_mate_shared::comms::host_to_device;
 use late_mate_shared::{heapless, MAX_SCENARIO_DURATION_MS, MAX_SCENARIO_LENGTH};
+use std::borrow::Cow;
 use std::time::Duration;
 
 #[derive(Debug, thiserror::Error)]
@@ -23,52 +25,637 @@ pub enum ValidationError {
     StartTimingInRevert,
     #[error("Random delay range start must be less than or equal than its end")]
     InvalidDelayRange,
+    #[error(transparent)]
+    UnsupportedCharacter(#[from] layout::UnsupportedCharacter),
+    #[error("Key \"{}\" is pressed while it's already held", .0.name())]
+    KeyAlreadyHeld(hid::KeyboardKey),
+    #[error("Key \"{}\" is released while it isn't held", .0.name())]
+    KeyNotHeld(hid::KeyboardKey),
+    #[error("Key \"{}\" is typed by type_text while it's held, typing would release it", .0.name())]
+    TypedKeyHeld(hid::KeyboardKey),
+    #[error("At most {MAX_HELD_KEYS} keys (excluding modifiers) can be held at the same time")]
+    TooManyKeysHeld,
+    #[error("Mouse button {0:?} is pressed while it's already held")]
+    ButtonAlreadyHeld(hid::MouseButton),
+    #[error("Mouse button {0:?} is released while it isn't held")]
+    ButtonNotHeld(hid::MouseButton),
+    #[error("Keys or mouse buttons are still held at the end of the test section, and there is no revert section to release them")]
+    HeldAfterTest,
+    #[error("Keys or mouse buttons are still held at the end of the revert section")]
+    HeldAfterRevert,
+    #[error("undo_chord can only be used with revert = \"auto\"")]
+    UndoChordWithoutAutoRevert,
+    #[error("Invalid detector parameter: {0}")]
+    InvalidDetector(&'static str),
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS)]
+/// Things that make a scenario valid but likely to produce misleading measurements
+#[derive(Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Lint {
+    #[error("There is no wait after the last HID report in the test section, recording stops when the test section ends")]
+    NoTrailingWait,
+    #[error("Keys or mouse buttons are still held at the end of the test section and are only released by the revert section")]
+    HeldAtEndOfTest,
+    #[error("Random delay range is only {width_ms}ms wide, less than {MIN_DELAY_RANGE_MS}ms makes aliasing with frames and timers more likely")]
+    NarrowDelayRange { width_ms: u32 },
+    #[error("Nothing moves the mouse back to where it was before the test section (x {x:+}, y {y:+}, wheel {wheel:+}, pan {pan:+}), revert it or use revert = \"auto\"")]
+    MouseNotReverted {
+        x: i32,
+        y: i32,
+        wheel: i32,
+        pan: i32,
+    },
+    #[error("Only {ms}ms are recorded after start_timing, changepoint detection needs more than {noise_window_ms}ms to estimate noise")]
+    ShortRecording { ms: u64, noise_window_ms: u32 },
+}
+
+/// Random delays narrower than this don't do much to avoid aliasing
+/// (it's about three frames at 60Hz)
+const MIN_DELAY_RANGE_MS: u32 = 50;
+
+/// Changepoint detection estimates noise from the first milliseconds of a recording,
+/// it's unlikely there's any meaningful change that early
+const NOISE_WINDOW_MS: u32 = 7;
+
+/// HID keyboard reports have room for this many simultaneously pressed keys
+const MAX_HELD_KEYS: usize = 6;
+
+#[derive(
+    Debug,
+    Eq,
+    PartialEq,
+    Clone,
+    serde::Deserialize,
+    serde::Serialize,
+    ts_rs::TS,
+    schemars::JsonSchema,
+)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ScenarioStep {
     Wait {
         ms: u16,
     },
     StartTiming,
+    /// Sends a keyboard report with the key added to the currently held ones
+    KeyDown {
+        key: hid::KeyboardKey,
+    },
+    /// Sends a keyboard report with the key removed from the currently held ones
+    KeyUp {
+        key: hid::KeyboardKey,
+    },
+    /// Sends a mouse report with the button added to the currently held ones
+    ButtonDown {
+        button: hid::MouseButton,
+    },
+    /// Sends a mouse report with the button removed from the currently held ones
+    ButtonUp {
+        button: hid::MouseButton,
+    },
+    /// Expands into a press/release pair of keyboard reports for every character, dead keys
+    /// take two pairs. Every report is a device step, so with the test section limited to
+    /// MAX_SCENARIO_LENGTH steps only a few characters fit
+    TypeText {
+        text: String,
+        #[serde(default)]
+        #[ts(optional, as = "Option<KeyboardLayout>")]
+        layout: KeyboardLayout,
+        /// Wait between the press and the release, some targets ignore zero-length presses.
+        /// It's another device step for every press
+        #[serde(default)]
+        #[ts(optional)]
+        hold_ms: Option<u16>,
+    },
+    // schemars doesn't support untagged variants, it's added in Scenario::json_schema()
     #[serde(untagged)]
+    #[schemars(skip)]
     HidReport(hid::HidReport),
 }
 
+impl ScenarioStep {
+    /// Number of steps this step takes on the device
+    pub fn device_len(&self) -> Result<usize, layout::UnsupportedCharacter> {
+        match self {
+            ScenarioStep::StartTiming => Ok(0),
+            ScenarioStep::TypeText {
+                text,
+                layout,
+                hold_ms,
+            } => {
+                let reports = layout::type_text(text, *layout)?.len();
+                // a wait after every press
+                Ok(reports + hold_ms.map_or(0, |_| reports / 2))
+            }
+            ScenarioStep::Wait { .. }
+            | ScenarioStep::KeyDown { .. }
+            | ScenarioStep::KeyUp { .. }
+            | ScenarioStep::ButtonDown { .. }
+            | ScenarioStep::ButtonUp { .. }
+            | ScenarioStep::HidReport(_) => Ok(1),
+        }
+    }
+}
+
+/// HID reports are full snapshots of everything that's pressed, so press/release steps need
+/// to know what is currently held to produce them
+#[derive(Debug, Default, Eq, PartialEq, Clone)]
+pub struct HidState {
+    keyboard: hid::KeyboardReport,
+    mouse_buttons: Vec<hid::MouseButton>,
+    // accumulated relative mouse movement, it's only tracked to be undone by automatic reverts
+    mouse_offset: MouseOffset,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Clone)]
+struct MouseOffset {
+    x: i32,
+    y: i32,
+    wheel: i32,
+    pan: i32,
+    // every movement as it was sent, pointer acceleration only cancels out if the same
+    // movements are undone one by one
+    moves: Vec<hid::MouseReport>,
+}
+
+impl MouseOffset {
+    fn add(&mut self, report: &hid::MouseReport) {
+        self.x += i32::from(report.x);
+        self.y += i32::from(report.y);
+        self.wheel += i32::from(report.wheel);
+        self.pan += i32::from(report.pan);
+        if [report.x, report.y, report.wheel, report.pan] != [0; 4] {
+            self.moves.push(hid::MouseReport {
+                buttons: vec![],
+                ..report.clone()
+            });
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        [self.x, self.y, self.wheel, self.pan] == [0; 4]
+    }
+
+    /// Mouse reports that move the mouse back to where it was before the offset accumulated,
+    /// every movement is negated in reverse order
+    fn undo_reports(&self) -> Vec<hid::MouseReport> {
+        let negate = |v: i8| v.checked_neg().unwrap_or(i8::MAX);
+        // -128 can't be negated in a single report, the remaining unit is sent separately
+        let remainder = |v: i8| i8::from(v == i8::MIN);
+
+        let mut reports = Vec::new();
+        for report in self.moves.iter().rev() {
+            reports.push(hid::MouseReport {
+                buttons: vec![],
+                x: negate(report.x),
+                y: negate(report.y),
+                wheel: negate(report.wheel),
+                pan: negate(report.pan),
+            });
+            if [report.x, report.y, report.wheel, report.pan].contains(&i8::MIN) {
+                reports.push(hid::MouseReport {
+                    buttons: vec![],
+                    x: remainder(report.x),
+                    y: remainder(report.y),
+                    wheel: remainder(report.wheel),
+                    pan: remainder(report.pan),
+                });
+            }
+        }
+        reports
+    }
+}
+
+impl HidState {
+    pub fn is_released(&self) -> bool {
+        self.keyboard.modifiers.is_empty()
+            && self.keyboard.pressed_keys.is_empty()
+            && self.mouse_buttons.is_empty()
+    }
+
+    fn keyboard_report(&self) -> hid::HidReport {
+        hid::HidReport::Keyboard(self.keyboard.clone())
+    }
+
+    fn mouse_report(&self) -> hid::HidReport {
+        hid::HidReport::Mouse(hid::MouseReport {
+            buttons: self.mouse_buttons.clone(),
+            ..Default::default()
+        })
+    }
+
+    fn key_down(&mut self, key: hid::KeyboardKey) -> Result<(), ValidationError> {
+        if let Some(modifier) = hid::KeyboardModifier::from_key(key) {
+            if self.keyboard.modifiers.contains(&modifier) {
+                return Err(ValidationError::KeyAlreadyHeld(key));
+            }
+            self.keyboard.modifiers.push(modifier);
+        } else {
+            if self.keyboard.pressed_keys.contains(&key) {
+                return Err(ValidationError::KeyAlreadyHeld(key));
+            }
+            if self.keyboard.pressed_keys.len() == MAX_HELD_KEYS {
+                return Err(ValidationError::TooManyKeysHeld);
+            }
+            self.keyboard.pressed_keys.push(key);
+        }
+        Ok(())
+    }
+
+    fn key_up(&mut self, key: hid::KeyboardKey) -> Result<(), ValidationError> {
+        let held = if let Some(modifier) = hid::KeyboardModifier::from_key(key) {
+            remove_item(&mut self.keyboard.modifiers, &modifier)
+        } else {
+            remove_item(&mut self.keyboard.pressed_keys, &key)
+        };
+        if held {
+            Ok(())
+        } else {
+            Err(ValidationError::KeyNotHeld(key))
+        }
+    }
+
+    /// Turns a step into full-state HID reports, updating the state along the way
+    pub fn resolve(&mut self, step: &ScenarioStep) -> Result<Vec<hid::HidReport>, ValidationError> {
+        let reports = match step {
+            ScenarioStep::Wait { .. } | ScenarioStep::StartTiming => vec![],
+            ScenarioStep::KeyDown { key } => {
+                self.key_down(*key)?;
+                vec![self.keyboard_report()]
+            }
+            ScenarioStep::KeyUp { key } => {
+                self.key_up(*key)?;
+                vec![self.keyboard_report()]
+            }
+            ScenarioStep::ButtonDown { button } => {
+                if self.mouse_buttons.contains(button) {
+                    return Err(ValidationError::ButtonAlreadyHeld(*button));
+                }
+                self.mouse_buttons.push(*button);
+                vec![self.mouse_report()]
+            }
+            ScenarioStep::ButtonUp { button } => {
+                if !remove_item(&mut self.mouse_buttons, button) {
+                    return Err(ValidationError::ButtonNotHeld(*button));
+                }
+                vec![self.mouse_report()]
+            }
+            // typed characters are pressed on top of whatever is already held
+            ScenarioStep::TypeText { text, layout, .. } => layout::type_text(text, *layout)?
+                .into_iter()
+                .map(|typed| {
+                    if let Some(key) = typed
+                        .pressed_keys
+                        .iter()
+                        .find(|key| self.keyboard.pressed_keys.contains(key))
+                    {
+                        return Err(ValidationError::TypedKeyHeld(*key));
+                    }
+                    let mut report = self.keyboard.clone();
+                    for modifier in typed.modifiers {
+                        if !report.modifiers.contains(&modifier) {
+                            report.modifiers.push(modifier);
+                        }
+                    }
+                    report.pressed_keys.extend(typed.pressed_keys);
+                    if report.pressed_keys.len() > MAX_HELD_KEYS {
+                        return Err(ValidationError::TooManyKeysHeld);
+                    }
+                    Ok(hid::HidReport::Keyboard(report))
+                })
+                .collect::<Result<_, _>>()?,
+            // raw reports are sent as is and replace the state
+            ScenarioStep::HidReport(report) => {
+                match report {
+                    hid::HidReport::Keyboard(keyboard) => self.keyboard.clone_from(keyboard),
+                    hid::HidReport::Mouse(mouse) => {
+                        self.mouse_buttons.clone_from(&mouse.buttons);
+                        self.mouse_offset.add(mouse);
+                    }
+                }
+                vec![report.to_owned()]
+            }
+        };
+
+        Ok(reports)
+    }
+}
+
+fn remove_item<T: PartialEq>(items: &mut Vec<T>, item: &T) -> bool {
+    let len_before = items.len();
+    items.retain(|x| x != item);
+    items.len() != len_before
+}
+
+/// Number of steps a list of scenario steps takes on the device
+pub fn device_len(steps: &[ScenarioStep]) -> Result<usize, layout::UnsupportedCharacter> {
+    steps.iter().map(ScenarioStep::device_len).sum()
+}
+
 impl From<&ScenarioStep> for Duration {
     fn from(value: &ScenarioStep) -> Self {
         let total_ms = match value {
-            ScenarioStep::Wait { ms } => *ms,
-            // it takes 1ms to send a HID report + margin of error
-            ScenarioStep::HidReport(_) => 2,
+            ScenarioStep::Wait { ms } => u64::from(*ms),
             ScenarioStep::StartTiming => 0,
+            ScenarioStep::TypeText {
+                text,
+                layout,
+                hold_ms,
+            } => {
+                let reports = layout::type_text(text, *layout).map_or(0, |r| r.len() as u64);
+                2 * reports + u64::from(hold_ms.unwrap_or(0)) * reports / 2
+            }
+            // it takes 1ms to send a HID report + margin of error
+            ScenarioStep::KeyDown { .. }
+            | ScenarioStep::KeyUp { .. }
+            | ScenarioStep::ButtonDown { .. }
+            | ScenarioStep::ButtonUp { .. }
+            | ScenarioStep::HidReport(_) => 2 * value.device_len().unwrap_or(0) as u64,
         };
-        Duration::from_millis(total_ms as u64)
+        Duration::from_millis(total_ms)
+    }
+}
+
+#[derive(
+    Debug,
+    Eq,
+    PartialEq,
+    Clone,
+    serde::Deserialize,
+    serde::Serialize,
+    ts_rs::TS,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum AutoRevert {
+    Auto,
+}
+
+#[derive(
+    Debug,
+    Eq,
+    PartialEq,
+    Clone,
+    serde::Deserialize,
+    serde::Serialize,
+    ts_rs::TS,
+    schemars::JsonSchema,
+)]
+#[serde(untagged)]
+pub enum Revert {
+    /// Derived from the test section: releases everything that's held, presses the undo chord
+    /// (if any) and moves the mouse back by undoing every movement in reverse order
+    Auto(AutoRevert),
+    Steps(Vec<ScenarioStep>),
+}
+
+/// How the moment the screen reacted is found in recordings. Noise is measured in the first
+/// and the last `noise_window_ms` of the recording, where the light level is expected to be
+/// stable
+#[derive(
+    Debug, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS, schemars::JsonSchema,
+)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Detector {
+    /// The change is detected if the ends differ by more than `detect_multiplier` noise
+    /// ranges, and happens where the signal gets more than `change_multiplier` noise ranges
+    /// away from the start
+    Threshold(ThresholdParams),
+    /// Two-sided CUSUM of deviations from the starting level (in standard deviations).
+    /// Deviations smaller than `drift` are ignored, the change is detected once the sum
+    /// exceeds `threshold` and happens where the sum started growing
+    Cusum(CusumParams),
+    /// A single step fitted with least squares. It finds the middle of slow transitions
+    /// rather than their beginning. The step must be larger than `min_step_sigmas`
+    /// standard deviations of the residuals
+    StepFit(StepFitParams),
+    /// The change happens where the signal covers `percent` of the way from the starting level
+    /// to the final one, which must differ by more than `detect_multiplier` noise ranges
+    Swing(SwingParams),
+}
+
+impl Default for Detector {
+    fn default() -> Self {
+        Self::Threshold(ThresholdParams::default())
+    }
+}
+
+#[derive(
+    Debug, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS, schemars::JsonSchema,
+)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThresholdParams {
+    pub noise_window_ms: u32,
+    pub detect_multiplier: f64,
+    pub change_multiplier: f64,
+}
+
+impl Default for ThresholdParams {
+    fn default() -> Self {
+        Self {
+            noise_window_ms: NOISE_WINDOW_MS,
+            detect_multiplier: 2.0,
+            change_multiplier: 1.0,
+        }
+    }
+}
+
+#[derive(
+    Debug, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS, schemars::JsonSchema,
+)]
+#[serde(default, deny_unknown_fields)]
+pub struct CusumParams {
+    pub noise_window_ms: u32,
+    pub drift: f64,
+    pub threshold: f64,
+}
+
+impl Default for CusumParams {
+    fn default() -> Self {
+        Self {
+            noise_window_ms: NOISE_WINDOW_MS,
+            drift: 0.5,
+            threshold: 8.0,
+        }
+    }
+}
+
+#[derive(
+    Debug, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS, schemars::JsonSchema,
+)]
+#[serde(default, deny_unknown_fields)]
+pub struct StepFitParams {
+    pub min_step_sigmas: f64,
+}
+
+impl Default for StepFitParams {
+    fn default() -> Self {
+        Self {
+            min_step_sigmas: 5.0,
+        }
+    }
+}
+
+#[derive(
+    Debug, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS, schemars::JsonSchema,
+)]
+#[serde(default, deny_unknown_fields)]
+pub struct SwingParams {
+    pub noise_window_ms: u32,
+    pub percent: f64,
+    pub detect_multiplier: f64,
+}
+
+impl Default for SwingParams {
+    fn default() -> Self {
+        Self {
+            noise_window_ms: NOISE_WINDOW_MS,
+            percent: 50.0,
+            detect_multiplier: 2.0,
+        }
+    }
+}
+
+impl Detector {
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        fn non_negative(value: f64, name: &'static str) -> Result<(), ValidationError> {
+            if value.is_finite() && value >= 0.0 {
+                Ok(())
+            } else {
+                Err(ValidationError::InvalidDetector(name))
+            }
+        }
+
+        if self.noise_window_ms() == Some(0) {
+            return Err(ValidationError::InvalidDetector(
+                "noise_window_ms must be larger than 0",
+            ));
+        }
+
+        match self {
+            Detector::Threshold(params) => {
+                non_negative(
+                    params.detect_multiplier,
+                    "detect_multiplier must be 0 or more",
+                )?;
+                non_negative(
+                    params.change_multiplier,
+                    "change_multiplier must be 0 or more",
+                )
+            }
+            Detector::Cusum(params) => {
+                non_negative(params.drift, "drift must be 0 or more")?;
+                non_negative(params.threshold, "threshold must be 0 or more")
+            }
+            Detector::StepFit(params) => {
+                non_negative(params.min_step_sigmas, "min_step_sigmas must be 0 or more")
+            }
+            Detector::Swing(params) => {
+                if !(params.percent > 0.0 && params.percent < 100.0) {
+                    return Err(ValidationError::InvalidDetector(
+                        "percent must be between 0 and 100",
+                    ));
+                }
+                non_negative(
+                    params.detect_multiplier,
+                    "detect_multiplier must be 0 or more",
+                )
+            }
+        }
     }
+
+    /// How much of the recording is used to estimate noise, if the method does that
+    pub fn noise_window_ms(&self) -> Option<u32> {
+        match self {
+            Detector::Threshold(params) => Some(params.noise_window_ms),
+            Detector::Cusum(params) => Some(params.noise_window_ms),
+            Detector::Swing(params) => Some(params.noise_window_ms),
+            Detector::StepFit(_) => None,
+        }
+    }
+}
+
+/// How the changepoint is estimated between the light level samples around it
+#[derive(
+    Debug,
+    Default,
+    Eq,
+    PartialEq,
+    Clone,
+    Copy,
+    serde::Deserialize,
+    serde::Serialize,
+    ts_rs::TS,
+    schemars::JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    /// The first sample after the change
+    None,
+    /// A straight line between the samples around the change
+    #[default]
+    Linear,
+    /// A sigmoid fitted to the transition, falls back to linear for transitions that are too
+    /// fast to fit
+    Sigmoid,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS)]
+#[derive(
+    Debug, PartialEq, Clone, serde::Deserialize, serde::Serialize, ts_rs::TS, schemars::JsonSchema,
+)]
 #[serde(default, deny_unknown_fields)]
 pub struct Scenario {
     pub test: Vec<ScenarioStep>,
-    pub revert: Option<Vec<ScenarioStep>>,
+    pub revert: Option<Revert>,
+    /// Keys that are pressed together (and then released) by the automatic revert,
+    /// e.g. ["left_meta", "z"]
+    #[ts(optional, as = "Option<Vec<hid::KeyboardKey>>")]
+    pub undo_chord: Vec<hid::KeyboardKey>,
     pub repeats: u16,
+    /// Repeats that run before the measured ones, e.g. while the app warms up its caches.
+    /// They are recorded but excluded from statistics
+    #[ts(optional, as = "Option<u16>")]
+    pub warmup: u16,
     pub delay_between_ms: (u32, u32),
+    /// Changepoint detection method and its parameters
+    #[ts(optional, as = "Option<Detector>")]
+    pub detector: Detector,
+    /// Sub-sample estimation of the changepoint
+    #[ts(optional, as = "Option<Interpolation>")]
+    pub interpolation: Interpolation,
 }
 
 impl Scenario {
+    /// JSON Schema of scenarios, it can be used by editors for validation and completion.
+    /// Templating (vars, includes, blocks) is handled by the CLI and isn't part of it
+    pub fn json_schema() -> schemars::schema::RootSchema {
+        let mut gen = schemars::gen::SchemaSettings::draft07().into_generator();
+        let hid_report = gen.subschema_for::<hid::HidReport>();
+        let mut root = gen.into_root_schema_for::<Scenario>();
+
+        if let Some(schemars::schema::Schema::Object(step)) =
+            root.definitions.get_mut("ScenarioStep")
+        {
+            step.subschemas()
+                .one_of
+                .get_or_insert_with(Vec::new)
+                .push(hid_report);
+        }
+
+        root
+    }
+
     pub fn test_duration(&self) -> Duration {
         self.test.iter().map(Duration::from).sum()
     }
 
     pub fn total_duration(&self) -> (Duration, Duration) {
         let revert_duration = self
-            .revert
-            .as_ref()
+            .revert_steps()
+            .ok()
+            .flatten()
             .map_or(Duration::default(), |r| r.iter().map(Duration::from).sum());
 
-        let repeats = u32::from(self.repeats);
+        let repeats = u32::from(self.repeats) + u32::from(self.warmup);
         let base = (revert_duration + self.test_duration()) * repeats;
 
         (
@@ -77,18 +664,64 @@ impl Scenario {
         )
     }
 
+    fn auto_revert(&self) -> Result<Vec<ScenarioStep>, ValidationError> {
+        let mut hid_state = HidState::default();
+        for step in &self.test {
+            hid_state.resolve(step)?;
+        }
+
+        let mut steps = Vec::new();
+        let release_keyboard = hid::HidReport::Keyboard(hid::KeyboardReport::default());
+        if hid_state.keyboard != hid::KeyboardReport::default() {
+            steps.push(ScenarioStep::HidReport(release_keyboard.clone()));
+        }
+        if !hid_state.mouse_buttons.is_empty() {
+            steps.push(ScenarioStep::HidReport(hid::HidReport::Mouse(
+                hid::MouseReport::default(),
+            )));
+        }
+        if !self.undo_chord.is_empty() {
+            for key in &self.undo_chord {
+                steps.push(ScenarioStep::KeyDown { key: *key });
+            }
+            steps.push(ScenarioStep::HidReport(release_keyboard));
+        }
+        for report in hid_state.mouse_offset.undo_reports() {
+            steps.push(ScenarioStep::HidReport(hid::HidReport::Mouse(report)));
+        }
+
+        Ok(steps)
+    }
+
+    /// The revert section as it's going to be run, with automatic reverts resolved into steps
+    pub fn revert_steps(&self) -> Result<Option<Cow<'_, [ScenarioStep]>>, ValidationError> {
+        match &self.revert {
+            None => Ok(None),
+            Some(Revert::Steps(steps)) => Ok(Some(Cow::Borrowed(steps))),
+            Some(Revert::Auto(AutoRevert::Auto)) => Ok(Some(Cow::Owned(self.auto_revert()?))),
+        }
+    }
+
     pub fn validate(&self) -> Result<(), ValidationError> {
         if self.repeats == 0 {
             return Err(ValidationError::ZeroRepeats);
         }
+        self.detector.validate()?;
 
-        if self.test.len() > MAX_SCENARIO_LENGTH {
-            return Err(ValidationError::TestTooLarge(self.test.len()));
+        if !self.undo_chord.is_empty() && !matches!(self.revert, Some(Revert::Auto(_))) {
+            return Err(ValidationError::UndoChordWithoutAutoRevert);
         }
+        let revert = self.revert_steps()?;
 
-        if let Some(revert) = &self.revert {
-            if revert.len() > MAX_SCENARIO_LENGTH {
-                return Err(ValidationError::ReverseTooLarge(revert.len()));
+        let test_len = device_len(&self.test)?;
+        if test_len > MAX_SCENARIO_LENGTH {
+            return Err(ValidationError::TestTooLarge(test_len));
+        }
+
+        if let Some(revert) = &revert {
+            let revert_len = device_len(revert)?;
+            if revert_len > MAX_SCENARIO_LENGTH {
+                return Err(ValidationError::ReverseTooLarge(revert_len));
             }
         }
 
@@ -111,7 +744,7 @@ impl Scenario {
             return Err(ValidationError::MultipleStartTiming);
         }
 
-        if let Some(revert) = &self.revert {
+        if let Some(revert) = &revert {
             if revert
                 .iter()
                 .any(|s| matches!(s, ScenarioStep::StartTiming))
@@ -120,12 +753,96 @@ impl Scenario {
             }
         }
 
+        // every repeat must start with nothing held, and the revert section continues
+        // from whatever the test section left held
+        let mut hid_state = HidState::default();
+        for step in &self.test {
+            hid_state.resolve(step)?;
+        }
+        match &revert {
+            None if !hid_state.is_released() => return Err(ValidationError::HeldAfterTest),
+            None => {}
+            Some(revert) => {
+                for step in revert.iter() {
+                    hid_state.resolve(step)?;
+                }
+                if !hid_state.is_released() {
+                    return Err(ValidationError::HeldAfterRevert);
+                }
+            }
+        }
+
         if self.delay_between_ms.0 > self.delay_between_ms.1 {
             return Err(ValidationError::InvalidDelayRange);
         }
 
         Ok(())
     }
+
+    /// Warnings for a valid scenario, see Lint
+    pub fn lints(&self) -> Vec<Lint> {
+        let mut lints = Vec::new();
+
+        let last_report = self
+            .test
+            .iter()
+            .rposition(|s| !matches!(s, ScenarioStep::Wait { .. } | ScenarioStep::StartTiming));
+        if let Some(last_report) = last_report {
+            let waits_after = self.test[last_report..]
+                .iter()
+                .any(|s| matches!(s, ScenarioStep::Wait { ms } if *ms > 0));
+            if !waits_after {
+                lints.push(Lint::NoTrailingWait);
+            }
+        }
+
+        let mut hid_state = HidState::default();
+        let test_ok = self.test.iter().all(|s| hid_state.resolve(s).is_ok());
+        if test_ok && self.revert.is_some() && !hid_state.is_released() {
+            lints.push(Lint::HeldAtEndOfTest);
+        }
+
+        // automatic reverts always move the mouse back
+        let revert = match &self.revert {
+            None => Some(&[][..]),
+            Some(Revert::Steps(steps)) => Some(&steps[..]),
+            Some(Revert::Auto(_)) => None,
+        };
+        if let (true, Some(revert)) = (test_ok, revert) {
+            let revert_ok = revert.iter().all(|s| hid_state.resolve(s).is_ok());
+            let MouseOffset {
+                x, y, wheel, pan, ..
+            } = hid_state.mouse_offset;
+            if revert_ok && !hid_state.mouse_offset.is_zero() {
+                lints.push(Lint::MouseNotReverted { x, y, wheel, pan });
+            }
+        }
+
+        let (delay_min, delay_max) = self.delay_between_ms;
+        let width_ms = delay_max.saturating_sub(delay_min);
+        if width_ms < MIN_DELAY_RANGE_MS {
+            lints.push(Lint::NarrowDelayRange { width_ms });
+        }
+
+        if let Some(start) = self
+            .test
+            .iter()
+            .position(|s| matches!(s, ScenarioStep::StartTiming))
+        {
+            let recorded: Duration = self.test[start..].iter().map(Duration::from).sum();
+            let ms = u64::try_from(recorded.as_millis()).expect("Test duration must fit into u64");
+            if let Some(noise_window_ms) = self.detector.noise_window_ms() {
+                if ms <= u64::from(noise_window_ms) {
+                    lints.push(Lint::ShortRecording {
+                        ms,
+                        noise_window_ms,
+                    });
+                }
+            }
+        }
+
+        lints
+    }
 }
 
 impl Default for Scenario {
@@ -133,14 +850,20 @@ impl Default for Scenario {
         Self {
             test: vec![],
             revert: None,
+            undo_chord: vec![],
             repeats: 50,
+            warmup: 0,
             delay_between_ms: (300, 500),
+            detector: Detector::default(),
+            interpolation: Interpolation::default(),
         }
     }
 }
 
+/// `hid_state` is what's held before the steps run, it's updated to what's held after them
 pub fn to_device_scenario(
     steps: &[ScenarioStep],
+    hid_state: &mut HidState,
 ) -> (host_to_device::Scenario, Vec<hid::HidReport>) {
     let mut start_recording_at_idx = None;
     let mut device_steps =
@@ -149,31 +872,51 @@ pub fn to_device_scenario(
 
     // this justifies .unwrap()s below
     assert!(
-        steps.len() <= MAX_SCENARIO_LENGTH,
+        device_len(steps).is_ok_and(|len| len <= MAX_SCENARIO_LENGTH),
         "Maximum scenario length exceeded (non-validated scenario?)"
     );
 
-    for (idx, s) in steps.iter().enumerate() {
+    for s in steps {
         match s {
             ScenarioStep::Wait { ms } => {
                 device_steps
                     .push(host_to_device::ScenarioStep::Wait { ms: *ms })
                     .unwrap();
             }
-            ScenarioStep::HidReport(report) => {
-                let id = u8::try_from(hid_report_index.len()).unwrap();
-                hid_report_index.push(report.to_owned());
-
-                let hid_request = comms::hid::HidRequest {
-                    id,
-                    report: report.into(),
+            ScenarioStep::KeyDown { .. }
+            | ScenarioStep::KeyUp { .. }
+            | ScenarioStep::ButtonDown { .. }
+            | ScenarioStep::ButtonUp { .. }
+            | ScenarioStep::TypeText { .. }
+            | ScenarioStep::HidReport(_) => {
+                let reports = hid_state
+                    .resolve(s)
+                    .expect("HID state must be consistent (non-validated scenario?)");
+                let hold_ms = match s {
+                    ScenarioStep::TypeText { hold_ms, .. } => *hold_ms,
+                    _ => None,
                 };
-                device_steps
-                    .push(host_to_device::ScenarioStep::HidRequest(hid_request))
-                    .unwrap();
+                for (idx, report) in reports.into_iter().enumerate() {
+                    let id = u8::try_from(hid_report_index.len()).unwrap();
+                    let hid_request = comms::hid::HidRequest {
+                        id,
+                        report: (&report).into(),
+                    };
+                    hid_report_index.push(report);
+
+                    device_steps
+                        .push(host_to_device::ScenarioStep::HidRequest(hid_request))
+                        .unwrap();
+                    // typed reports are press/release pairs
+                    if let Some(ms) = hold_ms.filter(|_| idx % 2 == 0) {
+                        device_steps
+                            .push(host_to_device::ScenarioStep::Wait { ms })
+                            .unwrap();
+                    }
+                }
             }
             ScenarioStep::StartTiming => {
-                start_recording_at_idx = Some(u8::try_from(idx).unwrap());
+                start_recording_at_idx = Some(u8::try_from(device_steps.len()).unwrap());
             }
         }
     }
@@ -188,8 +931,8 @@ pub fn to_device_scenario(
 }
 
 // note that it's different from shared comms stuff becauase it has the actual report,
-// not just the ID
-#[derive(Debug, serde::Serialize)]
+// not just the ID. Deserialize is for reading saved recordings back
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Event {
     LightLevel(u32),
@@ -207,7 +950,7 @@ impl Event {
     }
 }
 
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 pub struct Moment {
     pub microsecond: u32,
     pub event: Event,
@@ -232,8 +975,321 @@ impl Moment {
     }
 }
 
-#[derive(Debug, serde::Serialize)]
+#[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
 pub struct Recording {
     pub max_light_level: u32,
     pub timeline: Vec<Moment>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hid::{KeyboardKey, KeyboardModifier, KeyboardReport, MouseButton};
+
+    fn scenario(test: Vec<ScenarioStep>, revert: Option<Vec<ScenarioStep>>) -> Scenario {
+        Scenario {
+            test,
+            revert: revert.map(Revert::Steps),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_lints() {
+        let wait = ScenarioStep::Wait { ms: 200 };
+        let press = ScenarioStep::KeyDown {
+            key: KeyboardKey::A,
+        };
+        let release = ScenarioStep::KeyUp {
+            key: KeyboardKey::A,
+        };
+        let move_right = ScenarioStep::HidReport(hid::HidReport::Mouse(hid::MouseReport {
+            x: 10,
+            ..Default::default()
+        }));
+
+        let clean = scenario(
+            vec![
+                ScenarioStep::StartTiming,
+                press.clone(),
+                release.clone(),
+                wait.clone(),
+            ],
+            None,
+        );
+        assert_eq!(clean.lints(), []);
+
+        let mut noisy = scenario(
+            vec![ScenarioStep::StartTiming, move_right.clone(), press],
+            Some(vec![release]),
+        );
+        noisy.delay_between_ms = (300, 310);
+        assert_eq!(
+            noisy.lints(),
+            [
+                Lint::NoTrailingWait,
+                Lint::HeldAtEndOfTest,
+                Lint::MouseNotReverted {
+                    x: 10,
+                    y: 0,
+                    wheel: 0,
+                    pan: 0
+                },
+                Lint::NarrowDelayRange { width_ms: 10 },
+                Lint::ShortRecording {
+                    ms: 4,
+                    noise_window_ms: NOISE_WINDOW_MS
+                },
+            ]
+        );
+
+        // the mouse isn't moved back without a revert section either
+        let no_revert = scenario(
+            vec![ScenarioStep::StartTiming, move_right.clone(), wait.clone()],
+            None,
+        );
+        assert_eq!(
+            no_revert.lints(),
+            [Lint::MouseNotReverted {
+                x: 10,
+                y: 0,
+                wheel: 0,
+                pan: 0
+            }]
+        );
+
+        noisy.revert = Some(Revert::Auto(AutoRevert::Auto));
+        assert!(!noisy
+            .lints()
+            .iter()
+            .any(|l| matches!(l, Lint::MouseNotReverted { .. })));
+    }
+
+    #[test]
+    fn test_press_release_tracking() {
+        let steps = [
+            ScenarioStep::KeyDown {
+                key: KeyboardKey::LeftShift,
+            },
+            ScenarioStep::KeyDown {
+                key: KeyboardKey::A,
+            },
+            ScenarioStep::KeyDown {
+                key: KeyboardKey::B,
+            },
+            ScenarioStep::KeyUp {
+                key: KeyboardKey::A,
+            },
+            ScenarioStep::KeyUp {
+                key: KeyboardKey::LeftShift,
+            },
+        ];
+        let mut hid_state = HidState::default();
+        let (_, reports) = to_device_scenario(&steps, &mut hid_state);
+
+        let keyboard = |modifiers: &[KeyboardModifier], keys: &[KeyboardKey]| {
+            hid::HidReport::Keyboard(KeyboardReport {
+                modifiers: modifiers.to_vec(),
+                pressed_keys: keys.to_vec(),
+            })
+        };
+        let shift = KeyboardModifier::LShift;
+        assert_eq!(
+            reports,
+            vec![
+                keyboard(&[shift], &[]),
+                keyboard(&[shift], &[KeyboardKey::A]),
+                keyboard(&[shift], &[KeyboardKey::A, KeyboardKey::B]),
+                keyboard(&[shift], &[KeyboardKey::B]),
+                keyboard(&[], &[KeyboardKey::B]),
+            ]
+        );
+        assert!(!hid_state.is_released());
+    }
+
+    #[test]
+    fn test_held_at_the_end() {
+        let test = vec![
+            ScenarioStep::StartTiming,
+            ScenarioStep::ButtonDown {
+                button: MouseButton::Left,
+            },
+        ];
+        assert!(matches!(
+            scenario(test.clone(), None).validate(),
+            Err(ValidationError::HeldAfterTest)
+        ));
+        assert!(matches!(
+            scenario(test.clone(), Some(vec![])).validate(),
+            Err(ValidationError::HeldAfterRevert)
+        ));
+
+        let revert = vec![ScenarioStep::ButtonUp {
+            button: MouseButton::Left,
+        }];
+        assert!(scenario(test, Some(revert)).validate().is_ok());
+    }
+
+    #[test]
+    fn test_auto_revert() {
+        let mouse = |buttons: Vec<MouseButton>, x: i8| {
+            ScenarioStep::HidReport(hid::HidReport::Mouse(hid::MouseReport {
+                buttons,
+                x,
+                ..Default::default()
+            }))
+        };
+        let scenario = Scenario {
+            test: vec![
+                ScenarioStep::StartTiming,
+                mouse(vec![MouseButton::Left], 100),
+                mouse(vec![MouseButton::Left], 100),
+            ],
+            revert: Some(Revert::Auto(AutoRevert::Auto)),
+            undo_chord: vec![KeyboardKey::LeftMeta, KeyboardKey::Z],
+            ..Default::default()
+        };
+        assert!(scenario.validate().is_ok());
+
+        let release = ScenarioStep::HidReport(hid::HidReport::Keyboard(KeyboardReport::default()));
+        assert_eq!(
+            scenario.revert_steps().unwrap().unwrap().into_owned(),
+            vec![
+                mouse(vec![], 0),
+                ScenarioStep::KeyDown {
+                    key: KeyboardKey::LeftMeta
+                },
+                ScenarioStep::KeyDown {
+                    key: KeyboardKey::Z
+                },
+                release,
+                mouse(vec![], -100),
+                mouse(vec![], -100),
+            ]
+        );
+
+        let mut offset = MouseOffset::default();
+        for x in [60, -128] {
+            offset.add(&hid::MouseReport {
+                x,
+                ..Default::default()
+            });
+        }
+        let undo_x = offset
+            .undo_reports()
+            .iter()
+            .map(|r| r.x)
+            .collect::<Vec<_>>();
+        assert_eq!(undo_x, [127, 1, -60]);
+    }
+
+    #[test]
+    fn test_inconsistent_steps() {
+        let test = vec![
+            ScenarioStep::StartTiming,
+            ScenarioStep::KeyUp {
+                key: KeyboardKey::A,
+            },
+        ];
+        assert!(matches!(
+            scenario(test, None).validate(),
+            Err(ValidationError::KeyNotHeld(KeyboardKey::A))
+        ));
+    }
+
+    #[test]
+    fn test_type_text_with_held_keys() {
+        let type_text = |text: &str| ScenarioStep::TypeText {
+            text: text.to_owned(),
+            layout: KeyboardLayout::default(),
+            hold_ms: None,
+        };
+        let press = |key| ScenarioStep::KeyDown { key };
+
+        let mut hid_state = HidState::default();
+        hid_state.resolve(&press(KeyboardKey::A)).unwrap();
+        assert!(matches!(
+            hid_state.resolve(&type_text("bad")),
+            Err(ValidationError::TypedKeyHeld(KeyboardKey::A))
+        ));
+
+        // held modifiers stay held, typed keys are released on top of them
+        let mut hid_state = HidState::default();
+        hid_state.resolve(&press(KeyboardKey::LeftShift)).unwrap();
+        let reports = hid_state.resolve(&type_text("B")).unwrap();
+        assert_eq!(
+            reports.last(),
+            Some(&hid::HidReport::Keyboard(KeyboardReport {
+                modifiers: vec![KeyboardModifier::LShift],
+                pressed_keys: vec![],
+            }))
+        );
+    }
+
+    #[test]
+    fn test_type_text_length() {
+        let type_text = |text: &str, hold_ms| {
+            scenario(
+                vec![
+                    ScenarioStep::StartTiming,
+                    ScenarioStep::TypeText {
+                        text: text.to_owned(),
+                        layout: KeyboardLayout::default(),
+                        hold_ms,
+                    },
+                    ScenarioStep::Wait { ms: 200 },
+                ],
+                None,
+            )
+        };
+
+        assert!(type_text("hello", None).validate().is_ok());
+        assert!(matches!(
+            type_text("hello, World!", None).validate(),
+            Err(ValidationError::TestTooLarge(27))
+        ));
+        // a wait after every press
+        assert!(type_text("hello", Some(10)).validate().is_ok());
+        assert!(matches!(
+            type_text("hello!", Some(10)).validate(),
+            Err(ValidationError::TestTooLarge(19))
+        ));
+
+        let (device_scenario, _) =
+            to_device_scenario(&type_text("hi", Some(10)).test, &mut HidState::default());
+        let waits = device_scenario
+            .steps
+            .iter()
+            .map(|s| match s {
+                host_to_device::ScenarioStep::Wait { ms } => Some(*ms),
+                host_to_device::ScenarioStep::HidRequest(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            waits,
+            [None, Some(10), None, None, Some(10), None, Some(200)]
+        );
+    }
+
+    #[test]
+    fn test_recording_roundtrip() {
+        let recording = Recording {
+            max_light_level: 1000,
+            timeline: vec![
+                Moment {
+                    microsecond: 0,
+                    event: Event::HidReport(hid::HidReport::Keyboard(KeyboardReport::default())),
+                },
+                Moment {
+                    microsecond: 500,
+                    event: Event::LightLevel(42),
+                },
+            ],
+        };
+        let serialised = serde_json::to_string(&recording).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Recording>(&serialised).unwrap(),
+            recording
+        );
+    }
+}
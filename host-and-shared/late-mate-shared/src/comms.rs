@@ -185,14 +185,12 @@ mod tests {
 
     #[test]
     fn test_basic_roundtrip() {
-        let packet = Message::BufferedMeasurement {
-            measurement: BufferedMoment {
-                microsecond: 17,
-                event: Event::LightLevel(42),
-            },
+        let packet = Message::BufferedMoment(BufferedMoment {
+            microsecond: 17,
+            event: Event::LightLevel(42),
             idx: 5,
             total: 10,
-        };
+        });
         let buffer = &mut [0u8; MAX_BUFFER_SIZE];
         let cobs_len = encode(&packet, buffer);
 
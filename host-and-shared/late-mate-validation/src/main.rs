@@ -1,4 +1,4 @@
-use evdev::{EventType, InputEventKind, Key};
+use evdev::{InputEventKind, Key};
 use std::error::Error;
 
 use rppal::gpio::Gpio;